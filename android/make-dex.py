@@ -0,0 +1,206 @@
+#!/usr/bin/env python3
+"""Writes `status-receiver.dex`, run it from here with `python3 make-dex.py`.
+
+A native activity has no Java code of its own, but the status of a PackageInstaller session only
+ever reaches a BroadcastReceiver. The dex holds the smallest one there is, whose `onReceive` is
+implemented in Rust, see `src/receiver.rs`:
+
+    package me.avidor.edroid;
+
+    public final class StatusReceiver extends android.content.BroadcastReceiver {
+        public StatusReceiver() {
+            super();
+        }
+
+        @Override
+        public native void onReceive(android.content.Context context, android.content.Intent intent);
+    }
+
+It's written by hand rather than compiled to keep the build free of the Android SDK, see
+https://source.android.com/docs/core/runtime/dex-format for the layout.
+"""
+
+import hashlib
+import struct
+import zlib
+
+HEADER_SIZE = 0x70
+NO_INDEX = 0xFFFFFFFF
+ACC_PUBLIC = 0x1
+ACC_FINAL = 0x10
+ACC_NATIVE = 0x100
+ACC_CONSTRUCTOR = 0x10000
+
+# Sorted by their UTF-16 code units, which is byte order for ASCII
+STRINGS = [
+    "<init>",
+    "Landroid/content/BroadcastReceiver;",
+    "Landroid/content/Context;",
+    "Landroid/content/Intent;",
+    "Lme/avidor/edroid/StatusReceiver;",
+    "V",
+    "VLL",
+    "onReceive",
+]
+# Sorted by string index
+TYPES = [
+    "Landroid/content/BroadcastReceiver;",
+    "Landroid/content/Context;",
+    "Landroid/content/Intent;",
+    "Lme/avidor/edroid/StatusReceiver;",
+    "V",
+]
+# (shorty, return type, parameters), sorted by return type and then parameters
+PROTOS = [
+    ("V", "V", []),
+    ("VLL", "V", ["Landroid/content/Context;", "Landroid/content/Intent;"]),
+]
+# (class, name, proto index), sorted by class, name and proto
+METHODS = [
+    ("Landroid/content/BroadcastReceiver;", "<init>", 0),
+    ("Lme/avidor/edroid/StatusReceiver;", "<init>", 0),
+    ("Lme/avidor/edroid/StatusReceiver;", "onReceive", 1),
+]
+
+SUPER_INIT = METHODS.index(("Landroid/content/BroadcastReceiver;", "<init>", 0))
+# The constructor: invoke-direct {v0}, BroadcastReceiver.<init>; return-void
+INIT_INSNS = [0x1070, SUPER_INIT, 0x0000, 0x000E]
+
+
+def uleb128(value):
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def align(data, alignment=4):
+    return data + b"\0" * (-len(data) % alignment)
+
+
+def type_index(descriptor):
+    return TYPES.index(descriptor)
+
+
+def main():
+    string_ids_off = HEADER_SIZE
+    type_ids_off = string_ids_off + 4 * len(STRINGS)
+    proto_ids_off = type_ids_off + 4 * len(TYPES)
+    method_ids_off = proto_ids_off + 12 * len(PROTOS)
+    class_defs_off = method_ids_off + 8 * len(METHODS)
+    data_off = class_defs_off + 32
+
+    # The data section: the code of the constructor, the parameters of onReceive, the strings,
+    # the class data and the map, in the order the map lists them
+    data = bytearray()
+    code_off = data_off + len(data)
+    data += struct.pack("<HHHHII", 1, 1, 1, 0, 0, len(INIT_INSNS))
+    data += struct.pack(f"<{len(INIT_INSNS)}H", *INIT_INSNS)
+    data = bytearray(align(bytes(data)))
+
+    type_list_off = data_off + len(data)
+    parameters = PROTOS[1][2]
+    data += struct.pack("<I", len(parameters))
+    data += struct.pack(f"<{len(parameters)}H", *map(type_index, parameters))
+    data = bytearray(align(bytes(data)))
+
+    string_data_off = data_off + len(data)
+    string_offs = []
+    for string in STRINGS:
+        string_offs.append(data_off + len(data))
+        data += uleb128(len(string)) + string.encode() + b"\0"
+
+    class_data_off = data_off + len(data)
+    init_index = METHODS.index(("Lme/avidor/edroid/StatusReceiver;", "<init>", 0))
+    on_receive_index = METHODS.index(("Lme/avidor/edroid/StatusReceiver;", "onReceive", 1))
+    data += uleb128(0) + uleb128(0) + uleb128(1) + uleb128(1)
+    data += uleb128(init_index) + uleb128(ACC_PUBLIC | ACC_CONSTRUCTOR) + uleb128(code_off)
+    data += uleb128(on_receive_index) + uleb128(ACC_PUBLIC | ACC_NATIVE) + uleb128(0)
+    data = bytearray(align(bytes(data)))
+
+    map_off = data_off + len(data)
+    sections = [
+        (0x0000, 1, 0),
+        (0x0001, len(STRINGS), string_ids_off),
+        (0x0002, len(TYPES), type_ids_off),
+        (0x0003, len(PROTOS), proto_ids_off),
+        (0x0005, len(METHODS), method_ids_off),
+        (0x0006, 1, class_defs_off),
+        (0x2001, 1, code_off),
+        (0x1001, 1, type_list_off),
+        (0x2002, len(STRINGS), string_data_off),
+        (0x2000, 1, class_data_off),
+        (0x1000, 1, map_off),
+    ]
+    data += struct.pack("<I", len(sections))
+    for kind, size, offset in sections:
+        data += struct.pack("<HHII", kind, 0, size, offset)
+
+    ids = bytearray()
+    ids += b"".join(struct.pack("<I", off) for off in string_offs)
+    ids += b"".join(struct.pack("<I", STRINGS.index(t)) for t in TYPES)
+    for shorty, return_type, params in PROTOS:
+        ids += struct.pack(
+            "<III",
+            STRINGS.index(shorty),
+            type_index(return_type),
+            type_list_off if params else 0,
+        )
+    for class_name, name, proto in METHODS:
+        ids += struct.pack("<HHI", type_index(class_name), proto, STRINGS.index(name))
+    ids += struct.pack(
+        "<IIIIIIII",
+        type_index("Lme/avidor/edroid/StatusReceiver;"),
+        ACC_PUBLIC | ACC_FINAL,
+        type_index("Landroid/content/BroadcastReceiver;"),
+        0,
+        NO_INDEX,
+        0,
+        class_data_off,
+        0,
+    )
+    assert HEADER_SIZE + len(ids) == data_off
+
+    file_size = data_off + len(data)
+    header = struct.pack(
+        "<8sI20sIIIIIIIIIIIIIIIIIIII",
+        b"dex\n035\0",
+        0,
+        b"\0" * 20,
+        file_size,
+        HEADER_SIZE,
+        0x12345678,
+        0,
+        0,
+        map_off,
+        len(STRINGS),
+        string_ids_off,
+        len(TYPES),
+        type_ids_off,
+        len(PROTOS),
+        proto_ids_off,
+        0,
+        0,
+        len(METHODS),
+        method_ids_off,
+        1,
+        class_defs_off,
+        len(data),
+        data_off,
+    )
+    dex = bytearray(header + ids + data)
+    dex[12:32] = hashlib.sha1(dex[32:]).digest()
+    dex[8:12] = struct.pack("<I", zlib.adler32(dex[12:]))
+
+    with open("status-receiver.dex", "wb") as f:
+        f.write(dex)
+    print("status-receiver.dex", hashlib.sha256(dex).hexdigest())
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,426 @@
+//! Who signed an apk.
+//!
+//! The signer comes from the APK Signature Scheme v2 or v3 block that sits right in front of the
+//! central directory, or from the v1 (JAR) signature for apks without one. Either way the
+//! signature is checked against the contents of the apk before the certificate is trusted, and
+//! like for indexes only RSA signatures are supported.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use cms::cert::x509::Certificate;
+use der::{Decode, Encode};
+use md5::Md5;
+use rsa::{pkcs8::DecodePublicKey, Pkcs1v15Sign, Pss, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha512};
+use zip::ZipArchive;
+
+use crate::jar;
+
+const EOCD_MAGIC: [u8; 4] = [0x50, 0x4b, 0x05, 0x06];
+const EOCD_SIZE: usize = 22;
+const SIGNING_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+const V2_BLOCK_ID: u32 = 0x7109871a;
+const V3_BLOCK_ID: u32 = 0xf05368c0;
+/// Contents are digested in chunks of this size, and the chunk digests digested again.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Checks the signature of the apk at `path` and returns the DER encoded certificate it's signed with.
+///
+/// The v2 signer is preferred over the v3 one, with key rotation it's still the original
+/// certificate, which is what F-Droid lists.
+pub fn signer(path: &Path) -> Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let layout = Layout::read(&mut file)?;
+
+    if let Some(block) = SigningBlock::read(&mut file, &layout)? {
+        for (id, v3) in [(V2_BLOCK_ID, false), (V3_BLOCK_ID, true)] {
+            if let Some(value) = block.get(id)? {
+                return verify_scheme(&mut file, &layout, block.start, value, v3);
+            }
+        }
+    }
+
+    file.rewind()?;
+    jar::verify_all(&mut ZipArchive::new(file)?)
+}
+
+/// What F-Droid puts in `sig` for apks signed with `cert`, the MD5 of the hex encoded certificate.
+pub fn fdroid_sig(cert: &[u8]) -> String {
+    hex::encode(Md5::digest(hex::encode(cert)))
+}
+
+/// Where the parts of the zip covered by a v2/v3 signature are.
+struct Layout {
+    cd_offset: u64,
+    cd_size: u64,
+    eocd: Vec<u8>,
+}
+
+impl Layout {
+    fn read(file: &mut File) -> Result<Self> {
+        let len = file.seek(SeekFrom::End(0))?;
+        // The end of central directory record is followed by a comment of up to 64 KiB
+        let tail_len = len.min((EOCD_SIZE + u16::MAX as usize) as u64);
+        file.seek(SeekFrom::Start(len - tail_len))?;
+        let mut tail = vec![0; tail_len as usize];
+        file.read_exact(&mut tail)?;
+
+        let last = tail
+            .len()
+            .checked_sub(EOCD_SIZE)
+            .context("not a zip file")?;
+        let start = (0..=last)
+            .rev()
+            .find(|&i| {
+                tail[i..].starts_with(&EOCD_MAGIC)
+                    && u16::from_le_bytes([tail[i + 20], tail[i + 21]]) as usize
+                        == tail.len() - i - EOCD_SIZE
+            })
+            .context("not a zip file")?;
+        let eocd = tail[start..].to_vec();
+
+        Ok(Self {
+            cd_size: u32::from_le_bytes(eocd[12..16].try_into()?).into(),
+            cd_offset: u32::from_le_bytes(eocd[16..20].try_into()?).into(),
+            eocd,
+        })
+    }
+}
+
+/// The APK Signing Block, which holds the v2 and v3 signatures among other things.
+struct SigningBlock {
+    start: u64,
+    /// ID-value pairs, each prefixed with its length.
+    pairs: Vec<u8>,
+}
+
+impl SigningBlock {
+    fn read(file: &mut File, layout: &Layout) -> Result<Option<Self>> {
+        let Some(footer_start) = layout.cd_offset.checked_sub(24) else {
+            return Ok(None);
+        };
+        let mut footer = [0; 24];
+        file.seek(SeekFrom::Start(footer_start))?;
+        file.read_exact(&mut footer)?;
+        if &footer[8..] != SIGNING_BLOCK_MAGIC {
+            return Ok(None);
+        }
+
+        // The size is repeated at the start of the block and doesn't count itself
+        let size = u64::from_le_bytes(footer[..8].try_into()?);
+        let start = layout
+            .cd_offset
+            .checked_sub(size)
+            .and_then(|s| s.checked_sub(8))
+            .filter(|_| size >= 24)
+            .context("apk signing block is out of bounds")?;
+        let mut block = vec![0; size as usize + 8];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut block)?;
+        ensure!(
+            block[..8] == footer[..8],
+            "apk signing block sizes don't match"
+        );
+
+        block.truncate(block.len() - 24);
+        block.drain(..8);
+        Ok(Some(Self {
+            start,
+            pairs: block,
+        }))
+    }
+
+    fn get(&self, id: u32) -> Result<Option<&[u8]>> {
+        let mut pairs = self.pairs.as_slice();
+        while !pairs.is_empty() {
+            let len = take_u64(&mut pairs)? as usize;
+            ensure!(
+                len >= 4 && len <= pairs.len(),
+                "apk signing block is malformed"
+            );
+            let (pair, rest) = pairs.split_at(len);
+            pairs = rest;
+            if u32::from_le_bytes(pair[..4].try_into()?) == id {
+                return Ok(Some(&pair[4..]));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Checks a v2 or v3 signature and returns its certificate.
+fn verify_scheme(
+    file: &mut File,
+    layout: &Layout,
+    block_start: u64,
+    mut value: &[u8],
+    v3: bool,
+) -> Result<Vec<u8>> {
+    let mut signers = take_prefixed(&mut value)?;
+    let mut signer = take_prefixed(&mut signers)?;
+    ensure!(signers.is_empty(), "apk must have exactly one signer");
+
+    let signed_data = take_prefixed(&mut signer)?;
+    if v3 {
+        let _min_sdk = take_u32(&mut signer)?;
+        let _max_sdk = take_u32(&mut signer)?;
+    }
+    let mut signatures = take_prefixed(&mut signer)?;
+    let public_key = take_prefixed(&mut signer)?;
+
+    let mut supported = Vec::new();
+    while !signatures.is_empty() {
+        let mut signature = take_prefixed(&mut signatures)?;
+        let id = take_u32(&mut signature)?;
+        if let Some(algorithm) = Algorithm::from_id(id) {
+            supported.push((algorithm, take_prefixed(&mut signature)?));
+        }
+    }
+    let (algorithm, signature) = supported
+        .into_iter()
+        .max_by_key(|(a, _)| a.is_sha512())
+        .context("only RSA signed apks are supported")?;
+    let key = RsaPublicKey::from_public_key_der(public_key)
+        .context("only RSA signed apks are supported")?;
+    algorithm.verify(&key, signed_data, signature)?;
+
+    let mut signed_data = signed_data;
+    let mut digests = take_prefixed(&mut signed_data)?;
+    let mut certificates = take_prefixed(&mut signed_data)?;
+    let cert = take_prefixed(&mut certificates)?.to_vec();
+    ensure!(
+        Certificate::from_der(&cert)?
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()?
+            == public_key,
+        "apk is signed with a different key than the one of its certificate"
+    );
+
+    let mut expected = None;
+    while !digests.is_empty() {
+        let mut digest = take_prefixed(&mut digests)?;
+        if take_u32(&mut digest)? == algorithm as u32 {
+            expected = Some(take_prefixed(&mut digest)?);
+        }
+    }
+    let expected = expected.context("signed data lacks the digest of the apk")?;
+    let actual = if algorithm.is_sha512() {
+        content_digest::<Sha512>(file, layout, block_start)?
+    } else {
+        content_digest::<Sha256>(file, layout, block_start)?
+    };
+    ensure!(actual == expected, "apk doesn't match its signature");
+
+    Ok(cert)
+}
+
+#[derive(Clone, Copy)]
+enum Algorithm {
+    RsaPssSha256 = 0x0101,
+    RsaPssSha512 = 0x0102,
+    RsaPkcs1Sha256 = 0x0103,
+    RsaPkcs1Sha512 = 0x0104,
+}
+
+impl Algorithm {
+    fn from_id(id: u32) -> Option<Self> {
+        Some(match id {
+            0x0101 => Self::RsaPssSha256,
+            0x0102 => Self::RsaPssSha512,
+            0x0103 => Self::RsaPkcs1Sha256,
+            0x0104 => Self::RsaPkcs1Sha512,
+            _ => return None,
+        })
+    }
+
+    fn is_sha512(self) -> bool {
+        matches!(self, Self::RsaPssSha512 | Self::RsaPkcs1Sha512)
+    }
+
+    fn verify(self, key: &RsaPublicKey, data: &[u8], signature: &[u8]) -> Result<()> {
+        match self {
+            Self::RsaPssSha256 => {
+                key.verify(Pss::new::<Sha256>(), &Sha256::digest(data), signature)
+            }
+            Self::RsaPssSha512 => {
+                key.verify(Pss::new::<Sha512>(), &Sha512::digest(data), signature)
+            }
+            Self::RsaPkcs1Sha256 => key.verify(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(data),
+                signature,
+            ),
+            Self::RsaPkcs1Sha512 => key.verify(
+                Pkcs1v15Sign::new::<Sha512>(),
+                &Sha512::digest(data),
+                signature,
+            ),
+        }
+        .map_err(|e| anyhow!("bad apk signature: {e}"))
+    }
+}
+
+/// Digest of everything but the signing block, with the central directory offset in the end of
+/// central directory record pointing at the signing block as if it wasn't there.
+fn content_digest<D: Digest>(
+    file: &mut File,
+    layout: &Layout,
+    block_start: u64,
+) -> Result<Vec<u8>> {
+    let mut eocd = layout.eocd.clone();
+    let offset = u32::try_from(block_start)?;
+    eocd[16..20].copy_from_slice(&offset.to_le_bytes());
+
+    let mut chunk_digests = Vec::new();
+    let mut chunks = 0u32;
+    let mut digest_chunk = |chunk: &[u8]| {
+        chunk_digests.extend(
+            D::new()
+                .chain_update([0xa5])
+                .chain_update((chunk.len() as u32).to_le_bytes())
+                .chain_update(chunk)
+                .finalize(),
+        );
+        chunks += 1;
+    };
+
+    let mut buf = vec![0; CHUNK_SIZE];
+    for (start, len) in [(0, block_start), (layout.cd_offset, layout.cd_size)] {
+        file.seek(SeekFrom::Start(start))?;
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(CHUNK_SIZE as u64) as usize;
+            file.read_exact(&mut buf[..n])?;
+            digest_chunk(&buf[..n]);
+            left -= n as u64;
+        }
+    }
+    eocd.chunks(CHUNK_SIZE).for_each(&mut digest_chunk);
+
+    Ok(D::new()
+        .chain_update([0x5a])
+        .chain_update(chunks.to_le_bytes())
+        .chain_update(&chunk_digests)
+        .finalize()
+        .to_vec())
+}
+
+fn take_prefixed<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = take_u32(data)? as usize;
+    if len > data.len() {
+        bail!("apk signature is truncated");
+    }
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
+
+fn take_u32(data: &mut &[u8]) -> Result<u32> {
+    let (value, rest) = data
+        .split_first_chunk::<4>()
+        .context("apk signature is truncated")?;
+    *data = rest;
+    Ok(u32::from_le_bytes(*value))
+}
+
+fn take_u64(data: &mut &[u8]) -> Result<u64> {
+    let (value, rest) = data
+        .split_first_chunk::<8>()
+        .context("apk signature is truncated")?;
+    *data = rest;
+    Ok(u64::from_le_bytes(*value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// SHA-256 of the certificates the fixtures are signed with, see `make-apks.py`.
+    const SIGNER: &str = "8f59aaa0c814d58c32496e513861afb1f22add89a403e1fb4caf7d9991001c2c";
+    const OTHER_SIGNER: &str = "c87105c20e29913ab9ef4b3b24a756b87d61d00a4af51fd9ce200ab2da7e0fb0";
+    /// The certificate of `app-v1.apk`, see `make-jars.py`.
+    const V1_SIGNER: &str = "eeba212c729c2e69a8b3bf740a3b3df5262f5b14ef156d139ac33bd5413c35ad";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn signer_of(name: &str) -> Result<String> {
+        signer(&fixture(name)).map(|cert| hex::encode(Sha256::digest(cert)))
+    }
+
+    fn block_ids(name: &str) -> (bool, bool) {
+        let mut file = File::open(fixture(name)).unwrap();
+        let layout = Layout::read(&mut file).unwrap();
+        let block = SigningBlock::read(&mut file, &layout).unwrap().unwrap();
+        (
+            block.get(V2_BLOCK_ID).unwrap().is_some(),
+            block.get(V3_BLOCK_ID).unwrap().is_some(),
+        )
+    }
+
+    #[test]
+    fn v2_signatures_are_verified() {
+        assert_eq!(block_ids("app-v2.apk"), (true, false));
+        assert_eq!(signer_of("app-v2.apk").unwrap(), SIGNER);
+        assert_eq!(signer_of("app-other-signer.apk").unwrap(), OTHER_SIGNER);
+    }
+
+    #[test]
+    fn v3_signatures_are_verified() {
+        assert_eq!(block_ids("app-v3.apk"), (false, true));
+        assert_eq!(signer_of("app-v3.apk").unwrap(), SIGNER);
+    }
+
+    #[test]
+    fn v1_signatures_are_verified() {
+        let mut file = File::open(fixture("app-v1.apk")).unwrap();
+        let layout = Layout::read(&mut file).unwrap();
+        assert!(SigningBlock::read(&mut file, &layout).unwrap().is_none());
+        assert_eq!(signer_of("app-v1.apk").unwrap(), V1_SIGNER);
+    }
+
+    #[test]
+    fn several_v3_signers_are_rejected() {
+        let e = signer_of("app-v3-two-signers.apk").unwrap_err();
+        assert_eq!(e.to_string(), "apk must have exactly one signer");
+    }
+
+    #[test]
+    fn changed_apks_are_rejected() {
+        let e = signer_of("app-tampered.apk").unwrap_err();
+        assert_eq!(e.to_string(), "apk doesn't match its signature");
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let e = signer_of("index-v1.json").unwrap_err();
+        assert_eq!(e.to_string(), "not a zip file");
+    }
+
+    #[test]
+    fn short_files_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("edroid-{}-short", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for len in 0..EOCD_SIZE {
+            let path = dir.join(format!("{len}.apk"));
+            let mut data = EOCD_MAGIC.to_vec();
+            data.resize(len, 0);
+            std::fs::write(&path, data).unwrap();
+
+            let e = signer(&path).unwrap_err();
+            assert_eq!(e.to_string(), "not a zip file", "{len} bytes");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
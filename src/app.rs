@@ -1,19 +1,54 @@
 use std::{
-    io::{BufReader, Cursor, Read},
-    sync::Arc,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use anyhow::Result;
-use egui::{Align, Context, Layout, RichText};
+use egui::{Align, Button, Context, Layout, TextEdit, Ui};
 use itertools::Itertools;
-use jni::objects::{JObject, JString, JValue};
+#[cfg(target_os = "android")]
+use jni::objects::{JObject, JString};
 use parking_lot::Mutex;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use tokio::runtime::Runtime;
-use zip::ZipArchive;
+use tokio::{
+    runtime::{Handle, Runtime},
+    sync::Notify,
+};
+
+use crate::{
+    cache, description,
+    device::Device,
+    download::{DownloadKey, Downloads},
+    egui_custom::{group_button, sync_status},
+    install::{self, Installs},
+    installed::Installed,
+    notify::{self, Link, Notifications},
+    repo::{AntiFeature, App, Category, Repo},
+    schedule::{self, SyncSchedule},
+    sync::{self, SyncStatus},
+};
 
-use crate::{egui_custom::group_button, repo::Repo};
+/// How long to wait for a server (or mirror) before giving up on it.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a download may stall before giving up on it.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long after startup the first scheduled sync may happen.
+const SCHEDULE_DELAY: Duration = Duration::from_secs(10);
+/// How many apks are downloaded at the same time, the others wait in line.
+const MAX_DOWNLOADS: usize = 3;
+
+mod categories;
+mod detail;
+mod downloads;
+mod notifications;
+mod repos;
+mod search;
+mod settings;
+mod updates;
 
 //const PACKAGE_PATH: &str = "/data/data/me.avidor.edroid/files/";
 
@@ -25,7 +60,80 @@ pub struct Edroid {
     #[serde(skip)]
     web_client: Client,
     repos: Arc<Mutex<Vec<Repo>>>,
+    /// Goes up whenever the apps of a repo were loaded, synced, toggled or removed, so what's
+    /// derived from them knows when to look again.
+    #[serde(skip)]
+    repos_version: Arc<AtomicU64>,
     layout: LatestAppsLayout,
+    category_sort: categories::CategorySort,
+    /// What packages are picked for, detected on Android.
+    device: Device,
+    /// When the repos are synced in the background.
+    schedule: Arc<Mutex<SyncSchedule>>,
+    /// Apps with any of these are left out of every list.
+    hidden_anti_features: BTreeSet<AntiFeature>,
+    /// Whether updates are downloaded and installed without asking.
+    auto_update: bool,
+    /// How each app is updated, keyed by app id.
+    update_settings: BTreeMap<String, updates::UpdateSettings>,
+    #[serde(skip)]
+    screen: Screen,
+    /// Screens to go back to, the last one first.
+    #[serde(skip)]
+    back: Vec<Screen>,
+    #[serde(skip)]
+    repos_form: repos::ReposForm,
+    #[serde(skip)]
+    downloads: Arc<Downloads>,
+    #[serde(skip)]
+    search: search::Search,
+    #[serde(skip)]
+    installed: Installed,
+    #[serde(skip)]
+    installs: Arc<Installs>,
+    /// Apks the auto-updater started downloading during this run.
+    #[serde(skip)]
+    auto_downloads: HashSet<DownloadKey>,
+    #[serde(skip)]
+    notifications: Arc<Mutex<Notifications>>,
+    /// Signalled whenever what the notifications show may have changed.
+    #[serde(skip)]
+    changed: Arc<Notify>,
+    /// Whether the window had the focus last frame.
+    #[serde(skip)]
+    focused: bool,
+    #[serde(skip)]
+    descriptions: description::Parsed,
+    #[serde(skip)]
+    update_check: updates::UpdateCheck,
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub enum Screen {
+    #[default]
+    Latest,
+    Repos,
+    Downloads,
+    Updates,
+    Settings,
+    Categories,
+    /// Apps of a category from all enabled repos.
+    Category(Category),
+    /// Details of the app `id` from the repo with the [Repo::cache_key] `repo`.
+    App {
+        repo: String,
+        id: String,
+    },
+}
+
+impl From<Link> for Screen {
+    fn from(link: Link) -> Self {
+        match link {
+            Link::Updates => Self::Updates,
+            Link::Downloads => Self::Downloads,
+            Link::App { repo, id } => Self::App { repo, id },
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Default)]
@@ -35,72 +143,85 @@ pub enum LatestAppsLayout {
 }
 
 impl eframe::App for Edroid {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, self);
+    }
+
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
+        self.refresh_search(ctx);
+        self.refresh_updates();
+        self.auto_update(ctx);
+        self.open_tapped(ctx);
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
+                    if !self.back.is_empty() && ui.button("⏴").clicked() {
+                        self.go_back();
+                    }
                     ui.heading("Edroid");
+                    let search = ui.add(
+                        TextEdit::singleline(&mut self.search.query)
+                            .hint_text("Search apps")
+                            .desired_width(150.0),
+                    );
+                    if search.changed() && self.screen != Screen::Latest {
+                        self.open(Screen::Latest);
+                    }
                 });
 
                 ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
-                    if ui.button("Sync").clicked() {
+                    let syncing = self
+                        .repos
+                        .lock()
+                        .iter()
+                        .any(|r| matches!(r.status, SyncStatus::Syncing));
+                    if ui.add_enabled(!syncing, Button::new("Sync")).clicked() {
                         self.sync(ctx);
                     };
+                    if ui.button("⚙").on_hover_text("Settings").clicked() {
+                        self.open(Screen::Settings);
+                    }
+                    if ui.button("Categories").clicked() {
+                        self.open(Screen::Categories);
+                    }
+                    if ui.button("Repos").clicked() {
+                        self.open(Screen::Repos);
+                    }
+                    let updates = match self.updates().len() {
+                        0 => "Updates".to_string(),
+                        count => format!("Updates ({count})"),
+                    };
+                    if ui.button(updates).clicked() {
+                        self.installed.refresh(self.rt.handle(), ctx);
+                        self.open(Screen::Updates);
+                    }
+                    let downloads = match self.downloads.active() {
+                        0 => "Downloads".to_string(),
+                        active => format!("Downloads ({active})"),
+                    };
+                    if ui.button(downloads).clicked() {
+                        self.open(Screen::Downloads);
+                    }
+
+                    for repo in self.repos.lock().iter().rev() {
+                        sync_status(ui, repo);
+                    }
                 });
             });
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                let lock = self.repos.lock();
-                let mut apps = lock
-                    .iter()
-                    .flat_map(|r| {
-                        if let Some(apps) = &r.apps {
-                            apps.iter().collect::<Vec<_>>()
-                        } else {
-                            Vec::new()
-                        }
-                    })
-                    .sorted_by_key(|a| date_to_integer(&a.last_updated).map(|i| -i))
-                    .take(50);
-
-                match self.layout {
-                    LatestAppsLayout::Fdroid => {
-                        for row_type in (0..=2u8).cycle() {
-                            match row_type {
-                                0 => {
-                                    if let Some(app) = apps.next() {
-                                        group_button(ui, app, true);
-                                    } else {
-                                        break;
-                                    }
-                                }
-                                1 => {
-                                    if let (Some(app1), Some(app2)) = (apps.next(), apps.next()) {
-                                        ui.columns(2, |ui| {
-                                            group_button(&mut ui[0], app1, false);
-                                            group_button(&mut ui[1], app2, false);
-                                        });
-                                    } else {
-                                        break;
-                                    }
-                                }
-                                2 => {
-                                    if let (Some(app1), Some(app2)) = (apps.next(), apps.next()) {
-                                        ui.columns(2, |ui| {
-                                            group_button(&mut ui[0], app1, true);
-                                            group_button(&mut ui[1], app2, true);
-                                        });
-                                    } else {
-                                        break;
-                                    }
-                                }
-                                _ => unreachable!(),
-                            }
-                        }
-                    }
-                }
+            egui::ScrollArea::vertical().show(ui, |ui| match self.screen.clone() {
+                Screen::Latest if !self.search.query.trim().is_empty() => self.search_screen(ui),
+                Screen::Latest => self.latest_screen(ui),
+                Screen::Repos => self.repos_screen(ui, ctx),
+                Screen::Downloads => self.downloads_screen(ui),
+                Screen::Updates => self.updates_screen(ui),
+                Screen::Settings => self.settings_screen(ui),
+                Screen::Categories => self.categories_screen(ui),
+                Screen::Category(category) => self.category_screen(ui, &category),
+                Screen::App { repo, id } => self.app_screen(ui, &repo, &id),
             });
         });
     }
@@ -108,11 +229,53 @@ impl eframe::App for Edroid {
 
 impl Default for Edroid {
     fn default() -> Self {
+        let rt = Runtime::new().unwrap();
+        let (package_manager, installer) = install::platform();
+        let installed = Installed::new(package_manager);
+        let update_check = updates::UpdateCheck::new(installed.clone());
+        let changed = Arc::new(Notify::new());
+        let web_client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .unwrap();
+
         Self {
-            rt: Runtime::new().unwrap(),
-            web_client: Client::new(),
+            search: Default::default(),
+            installed: installed.clone(),
+            installs: Arc::new(Installs::new(
+                installer,
+                installed,
+                rt.handle().clone(),
+                changed.clone(),
+            )),
+            downloads: Arc::new(Downloads::new(
+                web_client.clone(),
+                rt.handle().clone(),
+                cache::apk_dir(),
+                MAX_DOWNLOADS,
+                changed.clone(),
+            )),
+            rt,
+            web_client,
             repos: Arc::new(Mutex::new(vec![Repo::default()])),
+            repos_version: Default::default(),
             layout: Default::default(),
+            category_sort: Default::default(),
+            device: Default::default(),
+            schedule: Default::default(),
+            hidden_anti_features: Default::default(),
+            auto_update: false,
+            update_settings: Default::default(),
+            auto_downloads: Default::default(),
+            notifications: Arc::new(Mutex::new(Notifications::new(notify::notifier()))),
+            changed,
+            focused: false,
+            descriptions: Default::default(),
+            update_check,
+            screen: Default::default(),
+            back: Default::default(),
+            repos_form: Default::default(),
         }
     }
 }
@@ -123,47 +286,208 @@ impl Edroid {
 
         cc.egui_ctx.set_zoom_factor(1.5);
 
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        let mut edroid: Self = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
+        // Repos added before the url was kept are keyed by the address of their index
+        for repo in edroid.repos.lock().iter_mut() {
+            if repo.config.url.is_none() {
+                repo.config.url = repo.meta.url.clone();
+            }
+        }
+        edroid.load_cache(&cc.egui_ctx);
+        edroid.installed.refresh(edroid.rt.handle(), &cc.egui_ctx);
+        edroid.start_schedule(&cc.egui_ctx);
+        edroid.start_notifications();
+
+        #[cfg(target_os = "android")]
+        match Device::detect() {
+            Ok(device) => edroid.device = device,
+            Err(e) => log::error!("can't read the device profile: {e:#}"),
+        }
+
+        if let Some(link) = Self::launch_link() {
+            match Link::parse(&link) {
+                // Opened from a notification
+                Some(link) => edroid.open(link.into()),
+                None => {
+                    edroid.repos_form = repos::ReposForm::with_link(link);
+                    edroid.open(Screen::Repos);
+                }
+            }
+        }
+
+        edroid
+    }
+
+    /// Fills in the apps of every repo from [cache] without blocking startup.
+    fn load_cache(&self, ctx: &Context) {
+        let keys: Vec<String> = self.repos.lock().iter().map(Repo::cache_key).collect();
+        let repos = self.repos.clone();
+        let version = self.repos_version.clone();
+        let ctx = ctx.clone();
+
+        self.rt.spawn_blocking(move || {
+            for key in keys {
+                let cached = cache::load(&key);
+
+                let mut lock = repos.lock();
+                let Some(repo) = lock.iter_mut().find(|r| r.cache_key() == key) else {
+                    continue;
+                };
+                // A sync that finished or started first has newer apps, or will have
+                if matches!(repo.status, SyncStatus::Ok(_) | SyncStatus::Syncing) {
+                    continue;
+                }
+                match cached {
+                    Ok(cached) => {
+                        repo.apps = Some(cached.apps);
+                        repo.raw_v2 = cached.raw_v2;
+                        version.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => log::warn!("{}: no cached index ({e:#})", repo.meta.name),
+                }
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Shows `screen`, the current one is kept to go back to.
+    fn open(&mut self, screen: Screen) {
+        if screen != self.screen {
+            self.back.push(std::mem::replace(&mut self.screen, screen));
         }
+    }
 
-        Default::default()
+    fn go_back(&mut self) {
+        self.screen = self.back.pop().unwrap_or_default();
+    }
+
+    fn latest_screen(&mut self, ui: &mut Ui) {
+        let repos = self.repos.clone();
+        let lock = repos.lock();
+        let mut apps = lock
+            .iter()
+            .filter(|r| r.config.enabled)
+            .flat_map(|r| {
+                if let Some(apps) = &r.apps {
+                    apps.iter().map(|a| (r, a)).collect::<Vec<_>>()
+                } else {
+                    Vec::new()
+                }
+            })
+            .filter(|(_, a)| !a.hidden_by(&self.hidden_anti_features))
+            .sorted_by_key(|(_, a)| date_to_integer(&a.last_updated).map(|i| -i))
+            .take(50);
+        let mut clicked = None;
+        let mut button = |ui: &mut Ui, item, image_left| {
+            let (repo, app): (&Repo, &App) = item;
+            if group_button(ui, repo, app, image_left) {
+                clicked = Some(item);
+            }
+        };
+
+        match self.layout {
+            LatestAppsLayout::Fdroid => {
+                for row_type in (0..=2u8).cycle() {
+                    match row_type {
+                        0 => {
+                            if let Some(app) = apps.next() {
+                                button(ui, app, true);
+                            } else {
+                                break;
+                            }
+                        }
+                        1 => {
+                            if let (Some(app1), Some(app2)) = (apps.next(), apps.next()) {
+                                ui.columns(2, |ui| {
+                                    button(&mut ui[0], app1, false);
+                                    button(&mut ui[1], app2, false);
+                                });
+                            } else {
+                                break;
+                            }
+                        }
+                        2 => {
+                            if let (Some(app1), Some(app2)) = (apps.next(), apps.next()) {
+                                ui.columns(2, |ui| {
+                                    button(&mut ui[0], app1, true);
+                                    button(&mut ui[1], app2, true);
+                                });
+                            } else {
+                                break;
+                            }
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+
+        if let Some((repo, app)) = clicked {
+            self.open(Screen::App {
+                repo: repo.cache_key(),
+                id: app.id.clone(),
+            });
+        }
     }
 
     pub fn sync(&self, ctx: &Context) {
-        for (idx, repo) in self.repos.lock().iter().enumerate() {
-            if let Some(url) = &repo.meta.url {
-                let index = format!("{url}/index.jar");
-                let client = self.web_client.clone();
-                let repos = self.repos.clone();
-                let ctx = ctx.clone();
-
-                self.rt.spawn(async move {
-                    let bytes = client
-                        .get(&index)
-                        .send()
-                        .await
-                        .unwrap()
-                        .bytes()
-                        .await
-                        .unwrap();
-                    let cursor = Cursor::new(bytes);
-                    let new_repo: Repo = quick_xml::de::from_reader(BufReader::new(
-                        ZipArchive::new(cursor)
-                            .unwrap()
-                            .by_name("index.xml")
-                            .unwrap(),
-                    ))
-                    .unwrap();
-                    repos.lock()[idx] = new_repo;
-                    ctx.request_repaint();
-                });
-            } else if let Some(mirrors) = &repo.meta.mirrors {
-            };
+        for repo in self.repos.lock().iter_mut() {
+            if repo.config.enabled {
+                self.sync_repo(repo, ctx);
+            }
         }
     }
 
-    fn get_cache_path() -> String {
+    fn sync_repo(&self, repo: &mut Repo, ctx: &Context) {
+        spawn_sync(
+            self.rt.handle(),
+            &self.web_client,
+            &self.repos,
+            &self.repos_version,
+            &self.changed,
+            repo,
+            ctx,
+        );
+    }
+
+    /// Syncs the repos that are due on the schedule, now and then every
+    /// [schedule::CHECK_INTERVAL], unless the network or battery keep it from doing so.
+    fn start_schedule(&self, ctx: &Context) {
+        let rt = self.rt.handle().clone();
+        let client = self.web_client.clone();
+        let repos = self.repos.clone();
+        let version = self.repos_version.clone();
+        let changed = self.changed.clone();
+        let schedule = self.schedule.clone();
+        let conditions = schedule::conditions();
+        let ctx = ctx.clone();
+
+        self.rt.spawn(async move {
+            // Let the cached indexes load first
+            tokio::time::sleep(SCHEDULE_DELAY).await;
+            loop {
+                let current = schedule.lock().clone();
+                match tokio::task::block_in_place(|| current.blocked_by(conditions.as_ref())) {
+                    Ok(Some(reason)) => log::info!("not syncing, {reason}"),
+                    Ok(None) => {
+                        for repo in repos.lock().iter_mut() {
+                            if repo.config.enabled && current.is_due(repo) {
+                                spawn_sync(&rt, &client, &repos, &version, &changed, repo, &ctx);
+                            }
+                        }
+                    }
+                    Err(e) => log::error!("can't check whether to sync: {e:#}"),
+                }
+                tokio::time::sleep(schedule::CHECK_INTERVAL).await;
+            }
+        });
+    }
+
+    #[cfg(target_os = "android")]
+    pub(crate) fn get_cache_path() -> String {
         let ctx = ndk_context::android_context();
         let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
         let mut env = vm.attach_current_thread().unwrap();
@@ -183,80 +507,136 @@ impl Edroid {
         jni_str.to_string_lossy().to_string()
     }
 
-    fn install_apk(&self, path: &str) -> Result<()> {
+    /// A repo or notification link the app was opened with, the intent data on Android and the
+    /// first argument elsewhere, if it is a link.
+    ///
+    /// A native activity doesn't see the intent of a link opened while it runs, the activity is
+    /// `singleTask` so that only brings it to the front instead of starting it again.
+    fn launch_link() -> Option<String> {
+        #[cfg(target_os = "android")]
+        return Self::get_intent_data().unwrap_or_else(|e| {
+            log::error!("can't read the intent: {e:#}");
+            None
+        });
+
+        #[cfg(not(target_os = "android"))]
+        std::env::args()
+            .nth(1)
+            .filter(|arg| Link::parse(arg).is_some() || crate::repo::parse_repo_link(arg).is_ok())
+    }
+
+    /// The uri of the intent the activity was started with.
+    #[cfg(target_os = "android")]
+    fn get_intent_data() -> anyhow::Result<Option<String>> {
         let ctx = ndk_context::android_context();
         let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }?;
         let mut env = vm.attach_current_thread()?;
 
-        let file = env.new_string(path)?;
-        let uri = env
-            .call_static_method(
-                "android/net/Uri",
-                "parse",
-                "(Ljava/lang/String;)Landroid/net/Uri;",
-                &[JValue::Object(&JObject::from(file))],
-            )?
+        let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+        let intent = env
+            .call_method(activity, "getIntent", "()Landroid/content/Intent;", &[])?
             .l()?;
+        if intent.is_null() {
+            return Ok(None);
+        }
 
-        let view_action = env.new_string("android.intent.action.VIEW")?.into();
-
-        let intent = env.new_object(
-            "android/content/Intent",
-            "(Ljava/lang/String;Landroid/net/Uri;)V",
-            &[JValue::Object(&view_action), JValue::Object(&uri)],
-        )?;
-
-        env.call_method(
-            &intent,
-            "setFlags",
-            "(I)V",
-            &[JValue::Int(0x00000001 | 0x00000040)],
-        )?;
-
-        let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
-        env.call_method(
-            activity,
-            "startActivity",
-            "(Landroid/content/Intent;)V",
-            &[JValue::Object(&intent)],
-        )?;
-
-        Ok(())
+        let data = env
+            .call_method(intent, "getDataString", "()Ljava/lang/String;", &[])?
+            .l()?;
+        if data.is_null() {
+            return Ok(None);
+        }
+        let data: String = env.get_string(&JString::from(data))?.into();
+        Ok(Some(data))
     }
+}
 
-    /*async fn download_apk(
-        client: &Client,
-        url: &str,
-        progress: Arc<Mutex<HashMap<String, f32>>>,
-        package_name: String,
-    ) -> Result<()> {
-        let resp = client.get(url).send().await?;
-        let total_size = resp.content_length().unwrap_or(0);
-
-        let mut downloaded = 0u64;
-        let mut stream = resp.bytes_stream();
-        let mut bytes = Vec::new();
-
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            downloaded += chunk.len() as u64;
-            bytes.extend_from_slice(&chunk);
-
-            let progress_value = downloaded as f32 / total_size as f32;
-            let mut progress_map = progress.lock().await;
-            progress_map.insert(package_name.clone(), progress_value);
+/// Syncs `repo` in the background, the result goes into the repo of `repos` with the same
+/// [Repo::cache_key], a new index bumps `version` and `changed` is signalled once it's done.
+fn spawn_sync(
+    rt: &Handle,
+    client: &Client,
+    repos: &Arc<Mutex<Vec<Repo>>>,
+    version: &Arc<AtomicU64>,
+    changed: &Arc<Notify>,
+    repo: &mut Repo,
+    ctx: &Context,
+) {
+    if matches!(repo.status, SyncStatus::Syncing) {
+        return;
+    }
+    repo.status = SyncStatus::Syncing;
+
+    let key = repo.cache_key();
+    let mirrors = repo.mirrors();
+    let local = sync::Local {
+        meta: repo.meta.clone(),
+        has_apps: repo.apps.is_some(),
+        raw_v2: repo.raw_v2.clone(),
+        validators: repo.config.validators.clone(),
+    };
+    let client = client.clone();
+    let repos = repos.clone();
+    let version = version.clone();
+    let changed = changed.clone();
+    let ctx = ctx.clone();
+
+    rt.spawn(async move {
+        let result = sync::fetch_index(&client, &mirrors, &local).await;
+        let mut cached = true;
+        if let Ok(sync::Fetched {
+            index: Some(index), ..
+        }) = &result
+        {
+            let repo = &index.repo;
+            if let Err(e) = tokio::task::block_in_place(|| cache::save(repo)) {
+                log::error!("{}: can't cache index: {e:#}", local.meta.name);
+                cached = false;
+            }
         }
 
-        let mut state = state.lock().await;
-        let file_name = format!("{}.apk", package_name);
-        let path = std::path::Path::new(&state.cache_path).join(&file_name);
-        tokio::fs::write(&path, bytes).await?;
-        state
-            .cached_apps
-            .insert(package_name, path.to_str().unwrap().to_string());
-
-        Ok(())
-    }*/
+        // The repo may have been moved, removed or pinned to another certificate in the meantime
+        let mut lock = repos.lock();
+        let Some(repo) = lock.iter_mut().find(|r| {
+            r.cache_key() == key
+                && r.meta.pub_key == local.meta.pub_key
+                && r.meta.fingerprint == local.meta.fingerprint
+        }) else {
+            return;
+        };
+        match result {
+            Ok(fetched) => {
+                repo.config.last_mirror = Some(fetched.mirror);
+                match fetched.index {
+                    Some(mut index) => {
+                        if cached {
+                            repo.config.validators = Some(index.validators);
+                        } else {
+                            // What's on disk is still the old index, the next sync has to
+                            // download the new one in full again
+                            index.repo.meta.timestamp = repo.meta.timestamp;
+                            index.repo.raw_v2 = None;
+                        }
+                        index.repo.config = repo.config.clone();
+                        *repo = index.repo;
+                        version.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => log::info!("{}: index is up to date", local.meta.name),
+                }
+                repo.status = SyncStatus::succeeded_now();
+                if let SyncStatus::Ok(now) = repo.status {
+                    repo.config.last_synced = Some(now);
+                }
+            }
+            Err(e) => {
+                log::error!("{}: {e}", local.meta.name);
+                repo.status = SyncStatus::Failed(e);
+            }
+        }
+        drop(lock);
+        changed.notify_one();
+        ctx.request_repaint();
+    });
 }
 
 fn date_to_integer(date_str: &str) -> Option<i32> {
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use egui::{Align, Layout, RichText, Ui};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use super::{date_to_integer, Edroid, Screen};
+use crate::{egui_custom::group_button, repo::Category};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum CategorySort {
+    Name,
+    #[default]
+    LastUpdated,
+    Added,
+}
+
+impl CategorySort {
+    const ALL: [Self; 3] = [Self::Name, Self::LastUpdated, Self::Added];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Name => "Name",
+            Self::LastUpdated => "Last updated",
+            Self::Added => "Added",
+        }
+    }
+}
+
+impl Edroid {
+    pub(super) fn categories_screen(&mut self, ui: &mut Ui) {
+        ui.heading("Categories");
+
+        let mut counts: BTreeMap<Category, usize> = BTreeMap::new();
+        for repo in self.repos.lock().iter().filter(|r| r.config.enabled) {
+            for app in repo.apps.iter().flatten() {
+                if app.hidden_by(&self.hidden_anti_features) {
+                    continue;
+                }
+                for category in &app.categories {
+                    if !category.name().is_empty() {
+                        *counts.entry(category.clone()).or_default() += 1;
+                    }
+                }
+            }
+        }
+        if counts.is_empty() {
+            ui.label("No apps yet, sync the repos first");
+            return;
+        }
+
+        let mut clicked = None;
+        for (category, count) in counts {
+            let response = ui
+                .horizontal(|ui| {
+                    ui.label(RichText::new(category.name()).strong());
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        ui.label(count.to_string());
+                    });
+                })
+                .response
+                .interact(egui::Sense::click());
+            if response.clicked() {
+                clicked = Some(category);
+            }
+            ui.separator();
+        }
+
+        if let Some(category) = clicked {
+            self.open(Screen::Category(category));
+        }
+    }
+
+    pub(super) fn category_screen(&mut self, ui: &mut Ui, category: &Category) {
+        ui.horizontal(|ui| {
+            ui.heading(category.name());
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                for sort in CategorySort::ALL.into_iter().rev() {
+                    ui.selectable_value(&mut self.category_sort, sort, sort.label());
+                }
+            });
+        });
+
+        let repos = self.repos.clone();
+        let repos = repos.lock();
+        let apps = repos
+            .iter()
+            .filter(|r| r.config.enabled)
+            .flat_map(|r| r.apps.iter().flatten().map(move |a| (r, a)))
+            .filter(|(_, a)| a.categories.contains(category))
+            .filter(|(_, a)| !a.hidden_by(&self.hidden_anti_features));
+        let apps: Vec<_> = match self.category_sort {
+            CategorySort::Name => apps
+                .sorted_by_cached_key(|(_, a)| a.name.to_lowercase())
+                .collect(),
+            CategorySort::LastUpdated => apps
+                .sorted_by_key(|(_, a)| newest_first(&a.last_updated))
+                .collect(),
+            CategorySort::Added => apps
+                .sorted_by_key(|(_, a)| newest_first(&a.added))
+                .collect(),
+        };
+
+        let mut clicked = None;
+        for (repo, app) in apps {
+            if group_button(ui, repo, app, true) {
+                clicked = Some(Screen::App {
+                    repo: repo.cache_key(),
+                    id: app.id.clone(),
+                });
+            }
+        }
+        drop(repos);
+
+        if let Some(screen) = clicked {
+            self.open(screen);
+        }
+    }
+}
+
+/// Sort key putting the newest `YYYY-MM-DD` date first and apps without one last.
+fn newest_first(date: &str) -> (bool, i32) {
+    match date_to_integer(date) {
+        Some(date) => (false, -date),
+        None => (true, 0),
+    }
+}
@@ -0,0 +1,360 @@
+use std::path::PathBuf;
+
+use egui::{Align, ComboBox, Layout, ProgressBar, RichText, Ui};
+use itertools::Itertools;
+
+use super::{
+    updates::{UpdatePolicy, UpdateSettings},
+    Edroid,
+};
+use crate::{
+    description,
+    download::{DownloadKey, DownloadState},
+    egui_custom::mib,
+    install::{InstallState, UninstallState},
+    repo::{App, Package, Repo},
+};
+
+impl Edroid {
+    pub(super) fn app_screen(&mut self, ui: &mut Ui, repo_key: &str, id: &str) {
+        let repos = self.repos.clone();
+        let repos = repos.lock();
+        let Some((repo, app)) = repos
+            .iter()
+            .filter(|r| r.cache_key() == repo_key)
+            .find_map(|r| Some((r, r.apps.as_ref()?.iter().find(|a| a.id == id)?)))
+        else {
+            ui.label("This app isn't in the repo anymore");
+            return;
+        };
+
+        header(ui, repo, app);
+        self.installed_row(ui, id);
+        anti_features(ui, app);
+        ui.separator();
+
+        let description = self.descriptions.get(repo_key, app);
+        description::show(ui, &description.desc);
+        if let Some(whats_new) = &description.whats_new {
+            ui.label(RichText::new("What's new").strong());
+            description::show(ui, whats_new);
+        }
+        screenshots(ui, repo, app);
+        ui.separator();
+
+        about(ui, app);
+        links(ui, app);
+        donations(ui, app);
+        ui.separator();
+
+        ui.heading("Versions");
+        let suggested = self.device.suggested(app);
+        if suggested.is_none() && !app.packages.is_empty() {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                "No version of this app can be installed on this device",
+            );
+        }
+        let mirrors = repo.mirrors();
+        for package in &app.packages {
+            let problems = self.device.check(package);
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.vertical(|ui| {
+                        let mut title = RichText::new(format!(
+                            "{} ({})",
+                            package.version, package.version_code
+                        ))
+                        .strong();
+                        if !problems.is_empty() {
+                            title = title.weak();
+                        }
+                        ui.horizontal(|ui| {
+                            ui.label(title);
+                            if suggested == Some(package) {
+                                ui.label(RichText::new("Suggested").small());
+                            }
+                        });
+                        let mut details = vec![mib(package.size.into())];
+                        if let Some(sdk) = package.sdk_version {
+                            details.push(format!("Android SDK {sdk}+"));
+                        }
+                        if !package.added.is_empty() {
+                            details.push(format!("added {}", package.added));
+                        }
+                        ui.label(details.join(" · "));
+                        for problem in &problems {
+                            ui.colored_label(
+                                ui.visuals().warn_fg_color,
+                                format!("Incompatible: {problem}"),
+                            );
+                        }
+                    });
+                    if problems.is_empty() {
+                        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                            self.download_button(ui, repo_key, &mirrors, id, package);
+                        });
+                    }
+                });
+            });
+        }
+    }
+
+    /// Downloads `package` of the app `id` from the repo `repo_key`, and installs it once it's
+    /// downloaded.
+    pub(super) fn download_button(
+        &self,
+        ui: &mut Ui,
+        repo_key: &str,
+        mirrors: &[String],
+        id: &str,
+        package: &Package,
+    ) {
+        let key = DownloadKey::new(repo_key, package);
+        match self.downloads.state(&key) {
+            None => {
+                if ui.button("Download").clicked() {
+                    self.downloads
+                        .start(repo_key, mirrors.to_vec(), package, ui.ctx());
+                }
+            }
+            Some(state @ (DownloadState::Queued | DownloadState::Running { .. })) => {
+                if ui.button("✖").on_hover_text("Cancel").clicked() {
+                    self.downloads.cancel(&key);
+                }
+                ui.add(
+                    ProgressBar::new(state.progress().unwrap_or_default())
+                        .desired_width(100.0)
+                        .show_percentage(),
+                );
+            }
+            Some(DownloadState::Done(path)) => self.install_button(ui, key, id, package, path),
+            Some(DownloadState::Failed(e)) => {
+                if ui.button("Retry").clicked() {
+                    self.downloads
+                        .start(repo_key, mirrors.to_vec(), package, ui.ctx());
+                }
+                ui.colored_label(ui.visuals().error_fg_color, "✖")
+                    .on_hover_text(e.to_string());
+            }
+        }
+    }
+
+    /// The installed version with a button to uninstall it, and how it's updated.
+    fn installed_row(&mut self, ui: &mut Ui, id: &str) {
+        let state = self.installs.uninstall_state(id);
+        ui.horizontal(|ui| {
+            let Some(installed) = self.installed.get(id) else {
+                if let Some(UninstallState::Uninstalled) = state {
+                    ui.label("✔ Uninstalled");
+                }
+                return;
+            };
+
+            ui.label(format!("Installed: {}", installed.version_name));
+            match state {
+                Some(UninstallState::Waiting) => {
+                    ui.spinner();
+                    ui.label("Waiting for confirmation");
+                }
+                state => {
+                    if ui.button("Uninstall").clicked() {
+                        self.installs.uninstall(id, ui.ctx());
+                    }
+                    if let Some(UninstallState::Failed(e)) = state {
+                        ui.colored_label(ui.visuals().error_fg_color, "✖")
+                            .on_hover_text(e.to_string());
+                    }
+                }
+            }
+
+            let mut settings = self.update_settings.get(id).cloned().unwrap_or_default();
+            ComboBox::from_id_salt("update_policy")
+                .selected_text(settings.policy.label())
+                .show_ui(ui, |ui| {
+                    for policy in UpdatePolicy::ALL {
+                        ui.selectable_value(&mut settings.policy, policy, policy.label());
+                    }
+                });
+            if let Some(version_code) = settings.ignored_version {
+                if ui
+                    .button(format!("Ignoring up to {version_code}"))
+                    .on_hover_text("Offer these versions again")
+                    .clicked()
+                {
+                    settings.ignored_version = None;
+                }
+            }
+            // Apps left at the defaults aren't stored
+            if settings == UpdateSettings::default() {
+                self.update_settings.remove(id);
+            } else {
+                self.update_settings.insert(id.to_string(), settings);
+            }
+        });
+    }
+
+    fn install_button(
+        &self,
+        ui: &mut Ui,
+        key: DownloadKey,
+        id: &str,
+        package: &Package,
+        apk: PathBuf,
+    ) {
+        let installed = self
+            .installed
+            .get(id)
+            .is_some_and(|a| a.version_code == package.version_code);
+        let state = self
+            .installs
+            .state(&key)
+            // Uninstalled since
+            .filter(|s| installed || !matches!(s, InstallState::Installed));
+        match state {
+            None => {
+                if ui.button("Install").clicked() {
+                    self.installs
+                        .start(key, id, package, apk, &self.device, false, ui.ctx());
+                }
+            }
+            Some(InstallState::Verifying) => {
+                ui.spinner();
+                ui.label("Checking");
+            }
+            Some(InstallState::Writing { done, total }) => {
+                ui.add(
+                    ProgressBar::new(done as f32 / total.max(1) as f32)
+                        .desired_width(100.0)
+                        .text("Installing"),
+                );
+            }
+            Some(InstallState::Committed) => {
+                ui.spinner();
+                ui.label("Waiting for confirmation");
+            }
+            Some(InstallState::Installed) => {
+                ui.label("✔ Installed");
+            }
+            Some(InstallState::Failed(e)) => {
+                if ui.button("Install").clicked() {
+                    self.installs
+                        .start(key, id, package, apk, &self.device, false, ui.ctx());
+                }
+                ui.colored_label(ui.visuals().error_fg_color, "✖")
+                    .on_hover_text(e.to_string());
+            }
+        }
+    }
+}
+
+fn header(ui: &mut Ui, repo: &Repo, app: &App) {
+    ui.horizontal(|ui| {
+        if let Some(icon) = app.icon_path().and_then(|i| repo.file_url(&i)) {
+            ui.add(egui::Image::new(icon).max_height(64.0));
+        }
+        ui.vertical(|ui| {
+            ui.heading(&app.name);
+            if !app.summary.is_empty() {
+                ui.label(&app.summary);
+            }
+            ui.label(RichText::new(format!("{} · {}", app.market_version, repo.meta.name)).weak());
+        });
+    });
+}
+
+fn anti_features(ui: &mut Ui, app: &App) {
+    if app.anti_features.is_empty() {
+        return;
+    }
+    ui.horizontal_wrapped(|ui| {
+        for anti_feature in &app.anti_features {
+            let badge = RichText::new(format!("⚠ {}", anti_feature.name()))
+                .color(ui.visuals().warn_fg_color)
+                .background_color(ui.visuals().faint_bg_color);
+            ui.label(badge).on_hover_text(anti_feature.description());
+        }
+    });
+}
+
+fn screenshots(ui: &mut Ui, repo: &Repo, app: &App) {
+    if app.screenshots.is_empty() {
+        return;
+    }
+    egui::ScrollArea::horizontal()
+        .id_salt("screenshots")
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                for url in app.screenshots.iter().filter_map(|s| repo.file_url(s)) {
+                    ui.add(egui::Image::new(url).max_height(300.0));
+                }
+            });
+        });
+}
+
+fn about(ui: &mut Ui, app: &App) {
+    egui::Grid::new("about").num_columns(2).show(ui, |ui| {
+        if let Some(author) = &app.author {
+            ui.label("Author");
+            match &app.email {
+                Some(email) => ui.hyperlink_to(author, format!("mailto:{email}")),
+                None => ui.label(author),
+            };
+            ui.end_row();
+        }
+        if !app.license.is_empty() {
+            ui.label("License");
+            ui.label(&app.license);
+            ui.end_row();
+        }
+        if !app.categories.is_empty() {
+            ui.label("Categories");
+            ui.label(app.categories.iter().map(|c| c.name()).join(", "));
+            ui.end_row();
+        }
+        if !app.last_updated.is_empty() {
+            ui.label("Updated");
+            ui.label(&app.last_updated);
+            ui.end_row();
+        }
+    });
+}
+
+fn links(ui: &mut Ui, app: &App) {
+    let links = [
+        ("Website", &app.web),
+        ("Source code", &app.source),
+        ("Issue tracker", &app.tracker),
+        ("Changelog", &app.changelog),
+    ];
+    ui.horizontal_wrapped(|ui| {
+        for (text, url) in links {
+            if let Some(url) = url.as_ref().filter(|u| !u.is_empty()) {
+                ui.hyperlink_to(text, url);
+            }
+        }
+    });
+}
+
+fn donations(ui: &mut Ui, app: &App) {
+    if app.donate.is_none() && app.bitcoin.is_none() && app.open_collective.is_none() {
+        return;
+    }
+
+    ui.label(RichText::new("Donate").strong());
+    ui.horizontal_wrapped(|ui| {
+        if let Some(url) = &app.donate {
+            ui.hyperlink_to("Donate", url);
+        }
+        if let Some(address) = &app.bitcoin {
+            ui.hyperlink_to("Bitcoin", format!("bitcoin:{address}"))
+                .on_hover_text(address);
+        }
+        if let Some(name) = &app.open_collective {
+            ui.hyperlink_to(
+                "Open Collective",
+                format!("https://opencollective.com/{name}"),
+            );
+        }
+    });
+}
@@ -0,0 +1,56 @@
+use egui::{Align, Layout, ProgressBar, Ui};
+
+use super::Edroid;
+use crate::{download::DownloadState, egui_custom::mib};
+
+impl Edroid {
+    pub(super) fn downloads_screen(&self, ui: &mut Ui) {
+        ui.heading("Downloads");
+
+        let downloads = self.downloads.all();
+        if downloads.is_empty() {
+            ui.label("Nothing downloaded yet");
+        }
+
+        for (key, state) in downloads {
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label(&key.apk_name);
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        let close = if state.is_active() {
+                            "Cancel"
+                        } else {
+                            "Dismiss"
+                        };
+                        if ui.button(close).clicked() {
+                            self.downloads.cancel(&key);
+                        }
+                    });
+                });
+
+                match &state {
+                    DownloadState::Queued => {
+                        ui.label("Waiting for other downloads");
+                    }
+                    DownloadState::Running { done, total } => {
+                        let text = match total {
+                            Some(total) => format!("{} / {}", mib(*done), mib(*total)),
+                            None => mib(*done),
+                        };
+                        ui.add(
+                            ProgressBar::new(state.progress().unwrap_or_default())
+                                .text(text)
+                                .animate(true),
+                        );
+                    }
+                    DownloadState::Done(path) => {
+                        ui.label(format!("Saved to {}", path.display()));
+                    }
+                    DownloadState::Failed(e) => {
+                        ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+                    }
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use egui::Context;
+use itertools::Itertools;
+use parking_lot::Mutex;
+
+use super::{updates::UpdateCheck, Edroid};
+use crate::{
+    download::{DownloadState, Downloads},
+    install::{InstallState, Installs},
+    installed::Installed,
+    notify::{Channel, Link, Notification},
+    repo::Repo,
+};
+
+/// What the notifications are worked out from, shared with the task posting them.
+struct Sources {
+    repos: Arc<Mutex<Vec<Repo>>>,
+    repos_version: Arc<AtomicU64>,
+    update_check: UpdateCheck,
+    downloads: Arc<Downloads>,
+    installs: Arc<Installs>,
+    installed: Installed,
+}
+
+impl Sources {
+    /// Notifications about the available updates, the running downloads and the apks ready to
+    /// be installed.
+    fn wanted(&self) -> Vec<Notification> {
+        let mut wanted = Vec::new();
+
+        self.update_check
+            .recheck(&self.repos, self.repos_version.load(Ordering::Relaxed));
+        let updates = self.update_check.found();
+        if !updates.is_empty() {
+            let title = match updates.len() {
+                1 => "1 update available".to_string(),
+                count => format!("{count} updates available"),
+            };
+            wanted.push(Notification {
+                tag: "updates".to_string(),
+                channel: Channel::Updates,
+                title,
+                text: updates.iter().map(|u| &u.name).join(", "),
+                ongoing: false,
+                percent: None,
+                link: Link::Updates,
+            });
+        }
+
+        let repos = self.repos.lock();
+        for (key, state) in self.downloads.all() {
+            let found = repos
+                .iter()
+                .filter(|r| r.cache_key() == key.repo)
+                .find_map(|r| {
+                    r.apps.as_ref()?.iter().find_map(|a| {
+                        let package = a.packages.iter().find(|p| p.apk_name == key.apk_name)?;
+                        Some((r.cache_key(), a, package.version_code))
+                    })
+                });
+            let title = found
+                .as_ref()
+                .map_or(&key.apk_name, |(_, app, _)| &app.name)
+                .clone();
+            let link = match &found {
+                Some((repo, app, _)) => Link::App {
+                    repo: repo.clone(),
+                    id: app.id.clone(),
+                },
+                None => Link::Downloads,
+            };
+
+            let (text, ongoing, percent) = match &state {
+                DownloadState::Queued => ("Waiting for other downloads", true, None),
+                DownloadState::Running { .. } => (
+                    "Downloading",
+                    true,
+                    state.progress().map(|p| (p * 100.0) as u8),
+                ),
+                DownloadState::Done(_) => {
+                    let installed = found.as_ref().is_some_and(|(_, app, version_code)| {
+                        self.installed
+                            .get(&app.id)
+                            .is_some_and(|a| a.version_code == *version_code)
+                    });
+                    let installing = self
+                        .installs
+                        .state(&key)
+                        .is_some_and(|s| s.is_active() || matches!(s, InstallState::Installed));
+                    if installed || installing {
+                        continue;
+                    }
+                    ("Ready to install", false, None)
+                }
+                DownloadState::Failed(_) => ("Download failed", false, None),
+            };
+            wanted.push(Notification {
+                tag: format!("download:{}/{}", key.repo, key.apk_name),
+                channel: Channel::Downloads,
+                title,
+                text: text.to_string(),
+                ongoing,
+                percent,
+                link,
+            });
+        }
+
+        wanted
+    }
+}
+
+impl Edroid {
+    /// Posts the notifications again every time a sync, a download, an install or the updates
+    /// signal [Edroid::changed], also while the app is in the background.
+    pub(super) fn start_notifications(&self) {
+        let sources = Sources {
+            repos: self.repos.clone(),
+            repos_version: self.repos_version.clone(),
+            update_check: self.update_check.clone(),
+            downloads: self.downloads.clone(),
+            installs: self.installs.clone(),
+            installed: self.installed.clone(),
+        };
+        let notifications = self.notifications.clone();
+        let changed = self.changed.clone();
+
+        self.rt.spawn(async move {
+            loop {
+                tokio::task::block_in_place(|| notifications.lock().show_only(sources.wanted()));
+                changed.notified().await;
+            }
+        });
+    }
+
+    /// Opens the link of a notification the user tapped, when the app gets the focus back from
+    /// the notification shade.
+    pub(super) fn open_tapped(&mut self, ctx: &Context) {
+        let focused = ctx.input(|i| i.focused);
+        if focused && !self.focused {
+            let tapped = self.notifications.lock().tapped();
+            if let Some(link) = tapped {
+                self.open(link.into());
+            }
+        }
+        self.focused = focused;
+    }
+}
@@ -0,0 +1,333 @@
+use std::sync::atomic::Ordering;
+
+use egui::{Align, Button, Context, Layout, RichText, TextEdit, Ui};
+
+use super::Edroid;
+use crate::{
+    cache,
+    repo::{normalize_fingerprint, parse_repo_link, Repo},
+};
+
+/// State of the repos screen that isn't worth persisting.
+#[derive(Default)]
+pub struct ReposForm {
+    /// A url or a shared repo link, see [parse_repo_link].
+    url: String,
+    fingerprint: String,
+    error: Option<String>,
+    /// Key of the repo the user asked to remove, waiting for confirmation.
+    confirm_remove: Option<String>,
+    editing: Option<RepoEdit>,
+}
+
+/// The url and fingerprint of a repo being edited.
+struct RepoEdit {
+    key: String,
+    url: String,
+    fingerprint: String,
+    error: Option<String>,
+}
+
+impl ReposForm {
+    /// The form filled in with a link the app was opened with, for the user to confirm.
+    pub fn with_link(link: String) -> Self {
+        Self {
+            url: link,
+            ..Default::default()
+        }
+    }
+}
+
+enum Action {
+    MoveUp(usize),
+    MoveDown(usize),
+    Remove(usize),
+    /// Replace the repo with one at another url or pinned to another fingerprint.
+    Edit(usize),
+}
+
+impl Edroid {
+    pub(super) fn repos_screen(&mut self, ui: &mut Ui, ctx: &Context) {
+        ui.heading("Repositories");
+
+        let mut action = None;
+        {
+            let mut repos = self.repos.lock();
+            let count = repos.len();
+            for (idx, repo) in repos.iter_mut().enumerate() {
+                let key = repo.cache_key();
+                ui.group(|ui| {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .checkbox(&mut repo.config.enabled, "")
+                            .on_hover_text("Sync this repo and show its apps")
+                            .changed()
+                        {
+                            self.repos_version.fetch_add(1, Ordering::Relaxed);
+                        }
+                        ui.vertical(|ui| {
+                            ui.label(RichText::new(&repo.meta.name).strong());
+                            if let Some(url) = &repo.meta.url {
+                                ui.label(url);
+                            }
+                        });
+
+                        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                            if self.repos_form.confirm_remove.as_ref() == Some(&key) {
+                                if ui.button("Cancel").clicked() {
+                                    self.repos_form.confirm_remove = None;
+                                }
+                                if ui.button("Remove").clicked() {
+                                    action = Some(Action::Remove(idx));
+                                }
+                            } else if ui.button("🗑").on_hover_text("Remove").clicked() {
+                                self.repos_form.confirm_remove = Some(key.clone());
+                            }
+                            if ui.button("✏").on_hover_text("Edit").clicked() {
+                                self.repos_form.editing = Some(RepoEdit {
+                                    key: key.clone(),
+                                    url: repo.config.url.clone().unwrap_or_default(),
+                                    fingerprint: repo.meta.signer_fingerprint().unwrap_or_default(),
+                                    error: None,
+                                });
+                            }
+                            if ui.add_enabled(idx + 1 < count, Button::new("⏷")).clicked() {
+                                action = Some(Action::MoveDown(idx));
+                            }
+                            if ui.add_enabled(idx > 0, Button::new("⏶")).clicked() {
+                                action = Some(Action::MoveUp(idx));
+                            }
+                        });
+                    });
+
+                    if let Some(edit) = self
+                        .repos_form
+                        .editing
+                        .as_mut()
+                        .filter(|edit| edit.key == key)
+                    {
+                        ui.add(TextEdit::singleline(&mut edit.url).hint_text("Url"));
+                        ui.add(
+                            TextEdit::singleline(&mut edit.fingerprint)
+                                .hint_text("Fingerprint (optional)"),
+                        );
+                        if let Some(error) = &edit.error {
+                            ui.colored_label(ui.visuals().error_fg_color, error);
+                        }
+                        ui.horizontal(|ui| {
+                            if ui.button("Save").clicked() {
+                                action = Some(Action::Edit(idx));
+                            }
+                            if ui.button("Cancel").clicked() {
+                                self.repos_form.editing = None;
+                            }
+                        });
+                    }
+
+                    egui::CollapsingHeader::new("Details")
+                        .id_salt(&key)
+                        .show(ui, |ui| repo_details(ui, repo));
+                });
+            }
+
+            match action {
+                Some(Action::MoveUp(idx)) => repos.swap(idx, idx - 1),
+                Some(Action::MoveDown(idx)) => repos.swap(idx, idx + 1),
+                Some(Action::Remove(idx)) => {
+                    let repo = repos.remove(idx);
+                    cache::remove(&repo.cache_key());
+                    self.repos_form.confirm_remove = None;
+                    self.repos_version.fetch_add(1, Ordering::Relaxed);
+                }
+                Some(Action::Edit(idx)) => {
+                    if let Some(edit) = &mut self.repos_form.editing {
+                        match edited_repo(&repos, idx, edit) {
+                            Ok(mut repo) => {
+                                cache::remove(&repos[idx].cache_key());
+                                if repo.config.enabled {
+                                    self.sync_repo(&mut repo, ctx);
+                                }
+                                repos[idx] = repo;
+                                self.repos_form.editing = None;
+                                self.repos_version.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(e) => edit.error = Some(e),
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+
+        ui.separator();
+        ui.heading("Add repository");
+        ui.add(
+            TextEdit::singleline(&mut self.repos_form.url)
+                .hint_text("https://example.org/fdroid/repo?fingerprint=…"),
+        );
+        ui.add(
+            TextEdit::singleline(&mut self.repos_form.fingerprint)
+                .hint_text("Fingerprint (optional)"),
+        );
+        if let Some(error) = &self.repos_form.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        if ui.button("Add").clicked() {
+            match self.add_repo(ctx) {
+                Ok(()) => self.repos_form = Default::default(),
+                Err(e) => self.repos_form.error = Some(e),
+            }
+        }
+    }
+
+    /// Adds the repo entered in the form and syncs it right away.
+    fn add_repo(&self, ctx: &Context) -> Result<(), String> {
+        let (url, fingerprint) = parse_form(&self.repos_form.url, &self.repos_form.fingerprint)?;
+
+        let mut repo = Repo::new(&url, fingerprint);
+        let mut repos = self.repos.lock();
+        if repos.iter().any(|r| r.cache_key() == repo.cache_key()) {
+            return Err("This repo is already added".into());
+        }
+        self.sync_repo(&mut repo, ctx);
+        repos.push(repo);
+
+        Ok(())
+    }
+}
+
+/// The url and fingerprint entered in a form, from a repo link or a url and a separate
+/// fingerprint.
+fn parse_form(link: &str, fingerprint: &str) -> Result<(String, Option<String>), String> {
+    let (url, link_fingerprint) = parse_repo_link(link).map_err(|e| e.to_string())?;
+
+    if fingerprint.trim().is_empty() {
+        return Ok((url, link_fingerprint));
+    }
+    let fingerprint = normalize_fingerprint(fingerprint).map_err(|e| e.to_string())?;
+    if link_fingerprint.is_some_and(|f| f != fingerprint) {
+        return Err("The fingerprint doesn't match the one in the link".into());
+    }
+    Ok((url, Some(fingerprint)))
+}
+
+/// A new repo replacing the one at `idx` of `repos` with what's in `edit`, it keeps the settings
+/// but not the index, which may not be signed by the new fingerprint.
+///
+/// A pinned repo stays pinned: without a fingerprint in the form it keeps the one it had, and
+/// with the same url and fingerprint it keeps the certificate too.
+fn edited_repo(repos: &[Repo], idx: usize, edit: &RepoEdit) -> Result<Repo, String> {
+    let (url, fingerprint) = parse_form(&edit.url, &edit.fingerprint)?;
+
+    let old = &repos[idx];
+    let pinned = old.meta.signer_fingerprint();
+    let mut repo = Repo::new(&url, fingerprint.or_else(|| pinned.clone()));
+    let key = repo.cache_key();
+    if repos
+        .iter()
+        .enumerate()
+        .any(|(i, r)| i != idx && r.cache_key() == key)
+    {
+        return Err("This repo is already added".into());
+    }
+    repo.config.enabled = old.config.enabled;
+    if key == old.cache_key() {
+        repo.config.preferred_mirrors = old.config.preferred_mirrors.clone();
+        if repo.meta.fingerprint.is_some() && repo.meta.fingerprint == pinned {
+            repo.meta.pub_key = old.meta.pub_key.clone();
+        }
+    }
+    Ok(repo)
+}
+
+fn repo_details(ui: &mut Ui, repo: &mut Repo) {
+    if let Some(desc) = &repo.meta.desc {
+        ui.label(desc.trim());
+    }
+
+    ui.label(RichText::new("Fingerprint").strong());
+    match repo.meta.signer_fingerprint() {
+        Some(fingerprint) => ui.monospace(fingerprint),
+        None => ui.label("Not pinned yet, the first synced index decides"),
+    };
+
+    let mirrors: Vec<String> = repo
+        .meta
+        .url
+        .iter()
+        .chain(repo.meta.mirrors.iter().flatten())
+        .map(|m| m.trim_end_matches('/').to_string())
+        .collect();
+    if mirrors.len() > 1 {
+        ui.label(RichText::new("Mirrors").strong())
+            .on_hover_text("Preferred mirrors are tried first, in the order they were picked");
+        let preferred = &mut repo.config.preferred_mirrors;
+        for mirror in mirrors {
+            let mut is_preferred = preferred.contains(&mirror);
+            if ui.checkbox(&mut is_preferred, &mirror).changed() {
+                if is_preferred {
+                    preferred.push(mirror);
+                } else {
+                    preferred.retain(|m| *m != mirror);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://example.org/fdroid/repo";
+    /// A certificate as it's pinned after a sync, and its fingerprint.
+    const PUB_KEY: &str = "3082035e30820246";
+    const FINGERPRINT: &str = "1da9aa3bdbd8a87d3f4dcb2a9c3d91e0e4db6d7e9ef5f1a4f24e5d1b8ec9e0a5";
+
+    fn synced() -> Repo {
+        let mut repo = Repo::new(URL, None);
+        repo.meta.pub_key = PUB_KEY.to_string();
+        repo
+    }
+
+    fn edit(repos: &[Repo], url: &str, fingerprint: &str) -> Result<Repo, String> {
+        let edit = RepoEdit {
+            key: repos[0].cache_key(),
+            url: url.to_string(),
+            fingerprint: fingerprint.to_string(),
+            error: None,
+        };
+        edited_repo(repos, 0, &edit)
+    }
+
+    #[test]
+    fn unchanged_edits_keep_the_certificate() {
+        let repos = [synced()];
+        let pinned = repos[0].meta.signer_fingerprint().unwrap();
+        assert_ne!(pinned, FINGERPRINT);
+
+        let repo = edit(&repos, URL, &pinned).unwrap();
+        assert_eq!(repo.meta.pub_key, PUB_KEY);
+        assert_eq!(repo.meta.signer_fingerprint(), Some(pinned));
+    }
+
+    #[test]
+    fn edits_never_unpin() {
+        let repos = [synced()];
+        let pinned = repos[0].meta.signer_fingerprint();
+
+        for url in [URL, "https://mirror.example.org/fdroid/repo"] {
+            let repo = edit(&repos, url, "").unwrap();
+            assert_eq!(repo.meta.signer_fingerprint(), pinned, "{url}");
+            assert!(!repo.meta.trusts(b"any certificate"));
+        }
+    }
+
+    #[test]
+    fn new_fingerprints_replace_the_certificate() {
+        let repos = [synced()];
+        let repo = edit(&repos, URL, FINGERPRINT).unwrap();
+        assert!(repo.meta.pub_key.is_empty());
+        assert_eq!(repo.meta.signer_fingerprint().as_deref(), Some(FINGERPRINT));
+    }
+}
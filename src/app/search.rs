@@ -0,0 +1,129 @@
+use std::{
+    collections::BTreeSet,
+    sync::{atomic::Ordering, Arc},
+};
+
+use egui::{Context, Ui};
+use parking_lot::Mutex;
+
+use super::{Edroid, Screen};
+use crate::{
+    egui_custom::group_button,
+    repo::AntiFeature,
+    search::{SearchIndex, Searchable},
+};
+
+/// How many results are shown.
+const MAX_RESULTS: usize = 50;
+
+/// The search bar and the index answering it, rebuilt whenever the repos change.
+#[derive(Default)]
+pub struct Search {
+    pub query: String,
+    /// The index and the generation it was built for.
+    index: Arc<Mutex<(u64, SearchIndex)>>,
+    generation: u64,
+    /// The [Edroid::repos_version] the index is built from.
+    built_from: Option<u64>,
+    /// The anti-features hidden from the index.
+    built_hiding: BTreeSet<AntiFeature>,
+    results: Option<Results>,
+}
+
+/// The last search, as repo key and app id pairs.
+struct Results {
+    query: String,
+    generation: u64,
+    apps: Vec<(String, String)>,
+}
+
+impl Edroid {
+    /// Builds the search index again in the background if a repo was synced, loaded, toggled or
+    /// removed, or other anti-features are hidden.
+    pub(super) fn refresh_search(&mut self, ctx: &Context) {
+        let version = self.repos_version.load(Ordering::Relaxed);
+        if self.search.built_from == Some(version)
+            && self.hidden_anti_features == self.search.built_hiding
+        {
+            return;
+        }
+        let apps = Searchable::collect(&self.repos.lock(), &self.hidden_anti_features);
+
+        self.search.built_from = Some(version);
+        self.search.built_hiding = self.hidden_anti_features.clone();
+        self.search.generation += 1;
+        let generation = self.search.generation;
+        let index = self.search.index.clone();
+        let ctx = ctx.clone();
+        self.rt.spawn_blocking(move || {
+            let built = SearchIndex::build(apps);
+            let mut index = index.lock();
+            // A build started later may have finished first
+            if index.0 < generation {
+                *index = (generation, built);
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    pub(super) fn search_screen(&mut self, ui: &mut Ui) {
+        let search = &mut self.search;
+        {
+            let index = search.index.lock();
+            if index.1.is_empty() {
+                ui.label("No apps to search yet, sync the repos first");
+                return;
+            }
+
+            // Searching is too slow to do on every frame
+            let query = search.query.trim();
+            if search
+                .results
+                .as_ref()
+                .is_none_or(|r| r.query != query || r.generation != index.0)
+            {
+                search.results = Some(Results {
+                    query: query.to_string(),
+                    generation: index.0,
+                    apps: index
+                        .1
+                        .search(query, MAX_RESULTS)
+                        .into_iter()
+                        .map(|hit| (hit.document.repo.clone(), hit.document.id.clone()))
+                        .collect(),
+                });
+            }
+        }
+        let results = search
+            .results
+            .as_ref()
+            .map(|r| r.apps.as_slice())
+            .unwrap_or_default();
+        if results.is_empty() {
+            ui.label("No apps found");
+            return;
+        }
+
+        let repos = self.repos.lock();
+        let mut clicked = None;
+        for (repo, id) in results {
+            let found = repos
+                .iter()
+                .filter(|r| r.cache_key() == *repo)
+                .find_map(|r| Some((r, r.apps.as_ref()?.iter().find(|a| a.id == *id)?)));
+            if let Some((found_repo, app)) = found {
+                if group_button(ui, found_repo, app, true) {
+                    clicked = Some(Screen::App {
+                        repo: repo.clone(),
+                        id: id.clone(),
+                    });
+                }
+            }
+        }
+        drop(repos);
+
+        if let Some(screen) = clicked {
+            self.open(screen);
+        }
+    }
+}
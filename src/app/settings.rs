@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+
+use egui::{DragValue, Id, RichText, TextEdit, Ui};
+use itertools::Itertools;
+
+use super::Edroid;
+use crate::{device::KNOWN_ABIS, repo::AntiFeature};
+
+impl Edroid {
+    pub(super) fn settings_screen(&mut self, ui: &mut Ui) {
+        ui.heading("Settings");
+
+        ui.label(RichText::new("Hide apps with these anti-features").strong());
+        for anti_feature in AntiFeature::ALL {
+            let mut hidden = self.hidden_anti_features.contains(&anti_feature);
+            let description = anti_feature.description().to_string();
+            if ui.checkbox(&mut hidden, description).changed() {
+                if hidden {
+                    self.hidden_anti_features.insert(anti_feature);
+                } else {
+                    self.hidden_anti_features.remove(&anti_feature);
+                }
+            }
+        }
+        ui.separator();
+
+        ui.label(RichText::new("Background sync").strong());
+        {
+            let mut schedule = self.schedule.lock();
+            ui.checkbox(&mut schedule.enabled, "Sync the repos on a schedule");
+            ui.add_enabled_ui(schedule.enabled, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Every");
+                    ui.add(
+                        DragValue::new(&mut schedule.interval_hours)
+                            .range(1..=24 * 7)
+                            .suffix(" hours"),
+                    );
+                });
+                ui.checkbox(&mut schedule.on_metered, "Also on metered networks");
+                ui.checkbox(&mut schedule.only_charging, "Only while charging");
+            });
+        }
+        ui.separator();
+
+        ui.label(RichText::new("Updates").strong());
+        ui.checkbox(
+            &mut self.auto_update,
+            "Download and install updates automatically",
+        )
+        .on_hover_text("Apps can opt out of this on their page");
+        ui.separator();
+
+        ui.label(RichText::new("Device").strong());
+        if cfg!(target_os = "android") {
+            ui.label(format!("Android API level {}", self.device.sdk));
+            ui.label(format!("ABIs: {}", self.device.abis.join(", ")));
+        } else {
+            self.device_form(ui);
+        }
+    }
+
+    /// Lets the device packages are picked for be set by hand.
+    fn device_form(&mut self, ui: &mut Ui) {
+        let device = &mut self.device;
+
+        ui.horizontal(|ui| {
+            ui.label("Android API level");
+            ui.add(DragValue::new(&mut device.sdk).range(1..=99));
+        });
+
+        ui.label("ABIs");
+        ui.horizontal_wrapped(|ui| {
+            for abi in KNOWN_ABIS {
+                let mut supported = device.abis.iter().any(|a| a == abi);
+                if ui.checkbox(&mut supported, abi).changed() {
+                    if supported {
+                        device.abis.push(abi.to_string());
+                    } else {
+                        device.abis.retain(|a| a != abi);
+                    }
+                }
+            }
+        });
+
+        // The features are edited as text kept in egui's memory between frames
+        let id = Id::new("device_features");
+        let mut all_features = device.features.is_none();
+        if ui
+            .checkbox(&mut all_features, "Every feature is available")
+            .changed()
+        {
+            device.features = (!all_features).then(BTreeSet::new);
+            ui.data_mut(|d| d.remove::<String>(id));
+        }
+        if let Some(features) = &mut device.features {
+            ui.label("Available features, one per line");
+            let mut text = ui.data_mut(|d| {
+                d.get_temp_mut_or_insert_with(id, || features.iter().join("\n"))
+                    .clone()
+            });
+            if ui
+                .add(TextEdit::multiline(&mut text).hint_text("android.hardware.camera"))
+                .changed()
+            {
+                *features = text
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+            ui.data_mut(|d| d.insert_temp(id, text));
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::Ordering, Arc},
+};
+
+use egui::{Align, Context, Layout, RichText, Ui};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::{Edroid, Screen};
+use crate::{
+    device::Device,
+    download::{DownloadKey, DownloadState},
+    egui_custom::group_button,
+    installed::{Installed, InstalledApp},
+    repo::{Package, Repo},
+};
+
+/// What happens when a newer version of an installed app shows up.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum UpdatePolicy {
+    /// Downloaded and installed without asking, when auto-updates are on.
+    #[default]
+    Auto,
+    /// Listed in the updates, but left alone.
+    OnlyNotify,
+    /// Never listed in the updates.
+    Ignore,
+}
+
+impl UpdatePolicy {
+    pub(super) const ALL: [Self; 3] = [Self::Auto, Self::OnlyNotify, Self::Ignore];
+
+    pub(super) fn label(self) -> &'static str {
+        match self {
+            Self::Auto => "Update automatically",
+            Self::OnlyNotify => "Only notify",
+            Self::Ignore => "Ignore updates",
+        }
+    }
+}
+
+/// Per-app update settings, apps without any use the defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct UpdateSettings {
+    pub policy: UpdatePolicy,
+    /// Versions up to this `version_code` aren't offered as updates.
+    pub ignored_version: Option<u32>,
+}
+
+/// An installed app the repos have a newer version of.
+pub(super) struct Update {
+    /// [crate::repo::Repo::cache_key] of the repo with the newest version.
+    pub repo: String,
+    pub id: String,
+    pub name: String,
+    pub installed: String,
+    /// The newest package compatible with the device.
+    pub package: Package,
+    /// Mirrors of the repo to download the package from.
+    pub mirrors: Vec<String>,
+    pub policy: UpdatePolicy,
+    /// Whether the new version is signed by someone else, Android won't install it over the
+    /// installed one then.
+    pub other_signer: bool,
+}
+
+/// The updates, found again when the repos, the installed apps or what they're checked against
+/// changed instead of every frame.
+#[derive(Clone)]
+pub(super) struct UpdateCheck {
+    installed: Installed,
+    state: Arc<Mutex<CheckState>>,
+}
+
+#[derive(Default)]
+struct CheckState {
+    /// The [Edroid::repos_version] and [Installed::version] the updates were found for.
+    versions: Option<(u64, u64)>,
+    settings: BTreeMap<String, UpdateSettings>,
+    device: Device,
+    found: Arc<Vec<Update>>,
+}
+
+impl UpdateCheck {
+    pub(super) fn new(installed: Installed) -> Self {
+        Self {
+            installed,
+            state: Default::default(),
+        }
+    }
+
+    /// The updates found last.
+    pub(super) fn found(&self) -> Arc<Vec<Update>> {
+        self.state.lock().found.clone()
+    }
+
+    /// Finds the updates in `repos` again if they, the installed apps, the `settings` or the
+    /// `device` changed since the last time. Returns whether it did.
+    pub(super) fn refresh(
+        &self,
+        repos: &Mutex<Vec<Repo>>,
+        repos_version: u64,
+        settings: &BTreeMap<String, UpdateSettings>,
+        device: &Device,
+    ) -> bool {
+        let mut state = self.state.lock();
+        let versions = Some((repos_version, self.installed.version()));
+        if state.versions == versions && state.settings == *settings && state.device == *device {
+            return false;
+        }
+        state.versions = versions;
+        state.settings = settings.clone();
+        state.device = device.clone();
+        state.found = Arc::new(find_updates(
+            &repos.lock(),
+            &self.installed.apps(),
+            &state.settings,
+            &state.device,
+        ));
+        true
+    }
+
+    /// Like [UpdateCheck::refresh] with the settings and device it was given last, for when
+    /// they aren't at hand. Does nothing before the first refresh.
+    pub(super) fn recheck(&self, repos: &Mutex<Vec<Repo>>, repos_version: u64) {
+        let (settings, device) = {
+            let state = self.state.lock();
+            if state.versions.is_none() {
+                return;
+            }
+            (state.settings.clone(), state.device.clone())
+        };
+        self.refresh(repos, repos_version, &settings, &device);
+    }
+}
+
+/// Installed apps with a newer compatible package in one of the enabled repos, by name.
+/// Apps whose updates are ignored, and ignored versions, are left out.
+fn find_updates(
+    repos: &[Repo],
+    installed: &HashMap<String, InstalledApp>,
+    settings: &BTreeMap<String, UpdateSettings>,
+    device: &Device,
+) -> Vec<Update> {
+    if installed.is_empty() {
+        return Vec::new();
+    }
+
+    let mut updates: Vec<Update> = Vec::new();
+    for repo in repos.iter().filter(|r| r.config.enabled) {
+        for app in repo.apps.iter().flatten() {
+            let Some(local) = installed.get(&app.id) else {
+                continue;
+            };
+            let settings = settings.get(&app.id).cloned().unwrap_or_default();
+            if settings.policy == UpdatePolicy::Ignore {
+                continue;
+            }
+            let Some(package) = device.suggested(app) else {
+                continue;
+            };
+            if package.version_code <= local.version_code
+                || settings
+                    .ignored_version
+                    .is_some_and(|v| v >= package.version_code)
+            {
+                continue;
+            }
+            // The same app can be in several repos, the newest version wins
+            if let Some(i) = updates.iter().position(|u| u.id == app.id) {
+                if updates[i].package.version_code >= package.version_code {
+                    continue;
+                }
+                updates.swap_remove(i);
+            }
+
+            updates.push(Update {
+                repo: repo.cache_key(),
+                id: app.id.clone(),
+                name: app.name.clone(),
+                installed: local.version_name.clone(),
+                package: package.clone(),
+                mirrors: repo.mirrors(),
+                policy: settings.policy,
+                other_signer: local.same_signer(package) == Some(false),
+            });
+        }
+    }
+
+    updates.sort_by_cached_key(|u| u.name.to_lowercase());
+    updates
+}
+
+impl Edroid {
+    /// The updates as of the last [Edroid::refresh_updates].
+    pub(super) fn updates(&self) -> Arc<Vec<Update>> {
+        self.update_check.found()
+    }
+
+    /// Finds the updates again if the repos, the installed apps or the settings changed.
+    pub(super) fn refresh_updates(&self) {
+        let found = self.update_check.refresh(
+            &self.repos,
+            self.repos_version.load(Ordering::Relaxed),
+            &self.update_settings,
+            &self.device,
+        );
+        if found {
+            self.changed.notify_one();
+        }
+    }
+
+    /// Downloads the updates of the apps that are updated automatically, and installs them
+    /// one at a time once they're downloaded. The download and the installed app are both
+    /// checked against the package before anything is installed, see [crate::install::install].
+    pub(super) fn auto_update(&mut self, ctx: &Context) {
+        if !self.auto_update {
+            return;
+        }
+
+        for update in self.updates().iter() {
+            if update.policy != UpdatePolicy::Auto || update.other_signer {
+                continue;
+            }
+            let key = DownloadKey::new(&update.repo, &update.package);
+            match self.downloads.state(&key) {
+                // Only once per run, a failed or cancelled download waits for the user
+                None => {
+                    if self.auto_downloads.insert(key) {
+                        self.downloads.start(
+                            &update.repo,
+                            update.mirrors.clone(),
+                            &update.package,
+                            ctx,
+                        );
+                    }
+                }
+                // Without unattended installs the apk waits for the user, who is notified
+                Some(DownloadState::Done(apk)) => {
+                    if self.installs.unattended()
+                        && self.installs.state(&key).is_none()
+                        && self.installs.active() == 0
+                    {
+                        self.installs.start(
+                            key,
+                            &update.id,
+                            &update.package,
+                            apk,
+                            &self.device,
+                            true,
+                            ctx,
+                        );
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Stops offering updates of the app `id` up to `version_code`.
+    fn ignore_version(&mut self, id: &str, version_code: u32) {
+        self.update_settings
+            .entry(id.to_string())
+            .or_default()
+            .ignored_version = Some(version_code);
+    }
+
+    pub(super) fn updates_screen(&mut self, ui: &mut Ui) {
+        ui.heading("Updates");
+
+        let updates = self.updates();
+        if updates.is_empty() {
+            ui.label("Every installed app is up to date");
+            return;
+        }
+
+        let repos = self.repos.clone();
+        let repos = repos.lock();
+        let mut clicked = None;
+        let mut ignored = None;
+        for update in updates.iter() {
+            let Some((repo, app)) = repos
+                .iter()
+                .filter(|r| r.cache_key() == update.repo)
+                .find_map(|r| Some((r, r.apps.as_ref()?.iter().find(|a| a.id == update.id)?)))
+            else {
+                continue;
+            };
+
+            if group_button(ui, repo, app, true) {
+                clicked = Some(Screen::App {
+                    repo: update.repo.clone(),
+                    id: update.id.clone(),
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.label(format!("{} → {}", update.installed, update.package.version));
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    if update.other_signer {
+                        ui.label(
+                            RichText::new("Signed by someone else")
+                                .color(ui.visuals().warn_fg_color),
+                        )
+                        .on_hover_text(
+                            "The installed version comes from elsewhere, it has to be \
+                             uninstalled before this one can be installed",
+                        );
+                    } else {
+                        self.download_button(
+                            ui,
+                            &update.repo,
+                            &update.mirrors,
+                            &update.id,
+                            &update.package,
+                        );
+                    }
+                    if ui.button("Ignore this version").clicked() {
+                        ignored = Some((update.id.clone(), update.package.version_code));
+                    }
+                    if update.policy == UpdatePolicy::OnlyNotify {
+                        ui.label(RichText::new("Not updated automatically").weak());
+                    }
+                });
+            });
+        }
+        drop(repos);
+
+        if let Some((id, version_code)) = ignored {
+            self.ignore_version(&id, version_code);
+        }
+        if let Some(screen) = clicked {
+            self.open(screen);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::index_v1::IndexV1;
+
+    const ID: &str = "org.fdroid.fdroid";
+
+    /// The fixture repo at `url`, with the app in the `versions`.
+    fn repo(url: &str, versions: &[u32]) -> Repo {
+        let index: IndexV1 =
+            serde_json::from_str(include_str!("../../tests/fixtures/index-v1.json")).unwrap();
+        let mut repo = Repo::from(index);
+        repo.config.url = Some(url.to_string());
+        let app = &mut repo.apps.as_mut().unwrap()[0];
+        let template = app.packages[0].clone();
+        app.packages = versions
+            .iter()
+            .map(|&version_code| Package {
+                version_code,
+                version: version_code.to_string(),
+                apk_name: format!("{ID}_{version_code}.apk"),
+                ..template.clone()
+            })
+            .collect();
+        repo
+    }
+
+    fn installed(version_code: u32) -> HashMap<String, InstalledApp> {
+        let app = InstalledApp {
+            id: ID.to_string(),
+            version_code,
+            version_name: version_code.to_string(),
+            signer: None,
+        };
+        HashMap::from([(ID.to_string(), app)])
+    }
+
+    fn versions(updates: &[Update]) -> Vec<(&str, u32)> {
+        updates
+            .iter()
+            .map(|u| (u.repo.as_str(), u.package.version_code))
+            .collect()
+    }
+
+    fn find(repos: &[Repo], installed: &HashMap<String, InstalledApp>) -> Vec<Update> {
+        find_updates(repos, installed, &BTreeMap::new(), &Device::default())
+    }
+
+    #[test]
+    fn newer_versions_are_updates() {
+        let repos = [repo("https://a", &[1, 3, 2])];
+        let updates = find(&repos, &installed(1));
+        assert_eq!(versions(&updates), [(repos[0].cache_key().as_str(), 3)]);
+        assert_eq!(updates[0].installed, "1");
+        assert_eq!(updates[0].name, "F-Droid");
+        assert_eq!(updates[0].mirrors, repos[0].mirrors());
+        assert!(!updates[0].other_signer);
+
+        assert!(find(&repos, &installed(3)).is_empty());
+        assert!(find(&repos, &installed(4)).is_empty());
+        assert!(find(&repos, &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn the_newest_version_of_any_enabled_repo_wins() {
+        let mut repos = [repo("https://a", &[5]), repo("https://b", &[7])];
+        let b = repos[1].cache_key();
+        assert_eq!(versions(&find(&repos, &installed(1))), [(b.as_str(), 7)]);
+
+        repos[1].config.enabled = false;
+        let a = repos[0].cache_key();
+        assert_eq!(versions(&find(&repos, &installed(1))), [(a.as_str(), 5)]);
+    }
+
+    #[test]
+    fn incompatible_versions_are_skipped() {
+        let mut repos = [repo("https://a", &[2, 3])];
+        repos[0].apps.as_mut().unwrap()[0].packages[1].sdk_version = Some(99);
+        assert_eq!(find(&repos, &installed(1))[0].package.version_code, 2);
+    }
+
+    #[test]
+    fn ignored_apps_and_versions_are_left_out() {
+        let repos = [repo("https://a", &[3])];
+        let find_with = |settings: UpdateSettings| {
+            let settings = BTreeMap::from([(ID.to_string(), settings)]);
+            find_updates(&repos, &installed(1), &settings, &Device::default())
+        };
+
+        assert!(find_with(UpdateSettings {
+            policy: UpdatePolicy::Ignore,
+            ignored_version: None,
+        })
+        .is_empty());
+        assert!(find_with(UpdateSettings {
+            policy: UpdatePolicy::Auto,
+            ignored_version: Some(3),
+        })
+        .is_empty());
+
+        let updates = find_with(UpdateSettings {
+            policy: UpdatePolicy::OnlyNotify,
+            ignored_version: Some(2),
+        });
+        assert_eq!(updates[0].policy, UpdatePolicy::OnlyNotify);
+    }
+
+    #[test]
+    fn updates_signed_by_someone_else_are_marked() {
+        let mut repos = [repo("https://a", &[3])];
+        let package = &mut repos[0].apps.as_mut().unwrap()[0].packages[0];
+        package.signer = Some(hex::encode(Sha256::digest(b"certificate")));
+        let mut installed = installed(1);
+
+        installed.get_mut(ID).unwrap().signer = Some(b"certificate".to_vec());
+        assert!(!find(&repos, &installed)[0].other_signer);
+
+        installed.get_mut(ID).unwrap().signer = Some(b"another certificate".to_vec());
+        assert!(find(&repos, &installed)[0].other_signer);
+    }
+
+    #[test]
+    fn updates_are_only_found_again_after_a_change() {
+        let manager = Arc::new(crate::installed::FakePackageManager::default());
+        let installed_apps = Installed::new(manager.clone());
+        let check = UpdateCheck::new(installed_apps.clone());
+        let repos = Mutex::new(vec![repo("https://a", &[3])]);
+        let (settings, device) = (BTreeMap::new(), Device::default());
+
+        check.refresh(&repos, 1, &settings, &device);
+        let first = check.found();
+        assert!(first.is_empty());
+        check.refresh(&repos, 1, &settings, &device);
+        assert!(Arc::ptr_eq(&first, &check.found()));
+
+        // Read the installed apps again
+        *manager.apps.lock() = installed(1).into_values().collect();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        installed_apps.refresh(rt.handle(), &Context::default());
+        while installed_apps.version() == 0 {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        check.refresh(&repos, 1, &settings, &device);
+        assert_eq!(check.found().len(), 1);
+
+        repos.lock()[0].config.enabled = false;
+        check.refresh(&repos, 1, &settings, &device);
+        assert_eq!(check.found().len(), 1);
+        check.refresh(&repos, 2, &settings, &device);
+        assert!(check.found().is_empty());
+    }
+
+    #[test]
+    fn rechecks_keep_the_settings() {
+        let manager = Arc::new(crate::installed::FakePackageManager::default());
+        *manager.apps.lock() = installed(1).into_values().collect();
+        let installed_apps = Installed::new(manager);
+        installed_apps.reload();
+        let check = UpdateCheck::new(installed_apps);
+        let repos = Mutex::new(vec![repo("https://a", &[2])]);
+
+        // Nothing to go by before the first refresh
+        check.recheck(&repos, 1);
+        assert!(check.found().is_empty());
+
+        let settings = BTreeMap::from([(
+            ID.to_string(),
+            UpdateSettings {
+                policy: UpdatePolicy::Auto,
+                ignored_version: Some(2),
+            },
+        )]);
+        assert!(check.refresh(&repos, 1, &settings, &Device::default()));
+        assert!(check.found().is_empty());
+
+        repos.lock().push(repo("https://b", &[3]));
+        check.recheck(&repos, 2);
+        assert_eq!(check.found()[0].package.version_code, 3);
+        assert!(!check.refresh(&repos, 2, &settings, &Device::default()));
+    }
+}
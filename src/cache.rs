@@ -0,0 +1,93 @@
+//! Downloaded indexes live on disk, one file per repo, instead of in eframe's storage
+//! which is rewritten on every autosave.
+//!
+//! Repos synced from index-v2 only keep the raw index (it's needed for the next diff anyway)
+//! and rebuild their apps from it, other repos keep their apps as json.
+
+use std::{fs, path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    index_v2::IndexV2,
+    repo::{App, Repo},
+};
+
+fn base() -> PathBuf {
+    #[cfg(target_os = "android")]
+    let base = PathBuf::from(crate::app::Edroid::get_cache_path());
+    #[cfg(not(target_os = "android"))]
+    let base = dirs::cache_dir().unwrap_or_else(std::env::temp_dir);
+
+    base.join("edroid")
+}
+
+pub fn dir() -> PathBuf {
+    base().join("indexes")
+}
+
+/// Where [crate::download] puts apks, finished and partial ones.
+pub fn apk_dir() -> PathBuf {
+    base().join("apks")
+}
+
+/// What [load] found for a repo.
+pub struct Cached {
+    pub apps: Vec<App>,
+    pub raw_v2: Option<Arc<Value>>,
+}
+
+pub fn load(key: &str) -> Result<Cached> {
+    let dir = dir();
+
+    let v2 = dir.join(format!("{key}.v2.json"));
+    if v2.exists() {
+        let raw: Value = serde_json::from_slice(&fs::read(v2)?)?;
+        let repo: Repo = IndexV2::deserialize(&raw)?.into();
+        return Ok(Cached {
+            apps: repo.apps.unwrap_or_default(),
+            raw_v2: Some(Arc::new(raw)),
+        });
+    }
+
+    Ok(Cached {
+        apps: serde_json::from_slice(&fs::read(dir.join(format!("{key}.json")))?)?,
+        raw_v2: None,
+    })
+}
+
+pub fn save(repo: &Repo) -> Result<()> {
+    let dir = dir();
+    fs::create_dir_all(&dir)?;
+
+    let key = repo.cache_key();
+    let (path, stale, data) = match &repo.raw_v2 {
+        Some(raw) => (
+            format!("{key}.v2.json"),
+            format!("{key}.json"),
+            serde_json::to_vec(raw)?,
+        ),
+        None => (
+            format!("{key}.json"),
+            format!("{key}.v2.json"),
+            serde_json::to_vec(repo.apps.as_deref().unwrap_or_default())?,
+        ),
+    };
+
+    // Write to a temporary file first so a crash can't leave a half written index behind
+    let tmp = dir.join(format!("{path}.tmp"));
+    fs::write(&tmp, data)?;
+    fs::rename(tmp, dir.join(path))?;
+    let _ = fs::remove_file(dir.join(stale));
+
+    Ok(())
+}
+
+/// Forgets the index of a removed repo.
+pub fn remove(key: &str) {
+    let dir = dir();
+    let _ = fs::remove_file(dir.join(format!("{key}.json")));
+    let _ = fs::remove_file(dir.join(format!("{key}.v2.json")));
+}
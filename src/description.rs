@@ -0,0 +1,553 @@
+//! App descriptions come with a small subset of HTML (`<p>`, `<br>`, `<ul>`/`<ol>`/`<li>`,
+//! `<b>`, `<i>`, `<tt>` and `<a href>`), or as plain text with line breaks. [parse] turns either
+//! into blocks that [show] lays out as wrapped rich text with clickable links. [Parsed] keeps
+//! them around for the apps that were shown.
+
+use std::{collections::HashMap, sync::Arc};
+
+use egui::{text::LayoutJob, Label, RichText, TextFormat, TextStyle, Ui};
+
+use crate::repo::App;
+
+#[derive(Debug, PartialEq)]
+pub enum Block {
+    Paragraph(Vec<Span>),
+    ListItem {
+        /// `•` or the number of the item, like `2.`.
+        bullet: String,
+        /// 0 for items of a top level list.
+        depth: usize,
+        spans: Vec<Span>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+    pub link: Option<String>,
+}
+
+impl Span {
+    fn same_style(&self, other: &Span) -> bool {
+        self.bold == other.bold
+            && self.italic == other.italic
+            && self.code == other.code
+            && self.link == other.link
+    }
+}
+
+/// The parsed description and "what's new" of an app.
+#[derive(Debug)]
+pub struct Description {
+    pub desc: Vec<Block>,
+    pub whats_new: Option<Vec<Block>>,
+}
+
+/// Descriptions of apps keyed by repo and app id, parsed again once a sync changed the app.
+#[derive(Default)]
+pub struct Parsed {
+    /// With the `last_updated` of the app they were parsed from.
+    apps: HashMap<(String, String), (String, Arc<Description>)>,
+}
+
+impl Parsed {
+    /// The description of `app` in the repo `repo_key`.
+    pub fn get(&mut self, repo_key: &str, app: &App) -> Arc<Description> {
+        let key = (repo_key.to_string(), app.id.clone());
+        match self.apps.get(&key) {
+            Some((last_updated, parsed)) if *last_updated == app.last_updated => parsed.clone(),
+            _ => {
+                let parsed = Arc::new(Description {
+                    desc: parse(&app.desc),
+                    whats_new: app.whats_new.as_deref().map(parse),
+                });
+                self.apps
+                    .insert(key, (app.last_updated.clone(), parsed.clone()));
+                parsed
+            }
+        }
+    }
+}
+
+pub fn parse(html: &str) -> Vec<Block> {
+    let mut parser = Parser::default();
+
+    if !html.contains('<') {
+        for paragraph in html.trim().split("\n\n") {
+            for line in paragraph.lines() {
+                parser.text(line);
+                parser.line_break();
+            }
+            parser.flush();
+        }
+        return parser.blocks;
+    }
+
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        parser.text(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            // Not a tag after all
+            parser.text(&rest[start..]);
+            rest = "";
+            break;
+        };
+        parser.tag(&rest[start + 1..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    parser.text(rest);
+    parser.flush();
+
+    parser.blocks
+}
+
+pub fn show(ui: &mut Ui, blocks: &[Block]) {
+    for block in blocks {
+        match block {
+            Block::Paragraph(spans) => {
+                show_spans(ui, spans);
+                ui.add_space(ui.spacing().item_spacing.y);
+            }
+            Block::ListItem {
+                bullet,
+                depth,
+                spans,
+            } => {
+                ui.horizontal_top(|ui| {
+                    ui.add_space(ui.spacing().indent * (*depth + 1) as f32);
+                    ui.label(bullet);
+                    ui.vertical(|ui| show_spans(ui, spans));
+                });
+            }
+        }
+    }
+}
+
+fn show_spans(ui: &mut Ui, spans: &[Span]) {
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+
+        // Plain text goes into as few labels as possible so it wraps like a paragraph
+        let mut job = LayoutJob::default();
+        for span in spans {
+            match &span.link {
+                None => job.append(&span.text, 0.0, text_format(ui, span)),
+                Some(url) => {
+                    if !job.is_empty() {
+                        ui.add(Label::new(std::mem::take(&mut job)));
+                    }
+                    ui.hyperlink_to(rich_text(span), url).on_hover_text(url);
+                }
+            }
+        }
+        if !job.is_empty() {
+            ui.add(Label::new(job));
+        }
+    });
+}
+
+fn text_format(ui: &Ui, span: &Span) -> TextFormat {
+    let visuals = ui.visuals();
+    TextFormat {
+        font_id: if span.code {
+            TextStyle::Monospace
+        } else {
+            TextStyle::Body
+        }
+        .resolve(ui.style()),
+        color: if span.bold {
+            visuals.strong_text_color()
+        } else {
+            visuals.text_color()
+        },
+        background: if span.code {
+            visuals.code_bg_color
+        } else {
+            Default::default()
+        },
+        italics: span.italic,
+        ..Default::default()
+    }
+}
+
+fn rich_text(span: &Span) -> RichText {
+    let mut text = RichText::new(&span.text);
+    if span.bold {
+        text = text.strong();
+    }
+    if span.italic {
+        text = text.italics();
+    }
+    if span.code {
+        text = text.code();
+    }
+    text
+}
+
+#[derive(Default)]
+struct Parser {
+    blocks: Vec<Block>,
+    spans: Vec<Span>,
+    bold: usize,
+    italic: usize,
+    code: usize,
+    link: Option<String>,
+    /// Open lists, with the number of the next item for ordered ones.
+    lists: Vec<Option<usize>>,
+    /// Bullet and depth of the list item being read.
+    item: Option<(String, usize)>,
+}
+
+impl Parser {
+    fn tag(&mut self, tag: &str) {
+        let (name, attrs) = tag
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((tag.trim(), ""));
+        let name = name.trim_end_matches('/').to_ascii_lowercase();
+
+        match name.as_str() {
+            "p" | "/p" | "div" | "/div" => self.flush(),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.flush();
+                self.bold += 1;
+            }
+            "/h1" | "/h2" | "/h3" | "/h4" | "/h5" | "/h6" => {
+                self.flush();
+                self.bold = self.bold.saturating_sub(1);
+            }
+            "br" => self.line_break(),
+            "ul" | "ol" => {
+                self.flush();
+                self.lists.push((name == "ol").then_some(1));
+            }
+            "/ul" | "/ol" => {
+                self.flush();
+                self.lists.pop();
+                self.item = None;
+            }
+            "li" => {
+                self.flush();
+                let depth = self.lists.len().saturating_sub(1);
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(next)) => {
+                        *next += 1;
+                        format!("{}.", *next - 1)
+                    }
+                    _ => "•".to_string(),
+                };
+                self.item = Some((bullet, depth));
+            }
+            "/li" => {
+                self.flush();
+                self.item = None;
+            }
+            "b" | "strong" => self.bold += 1,
+            "/b" | "/strong" => self.bold = self.bold.saturating_sub(1),
+            "i" | "em" => self.italic += 1,
+            "/i" | "/em" => self.italic = self.italic.saturating_sub(1),
+            "tt" | "code" => self.code += 1,
+            "/tt" | "/code" => self.code = self.code.saturating_sub(1),
+            "a" => self.link = href(attrs),
+            "/a" => self.link = None,
+            _ => {}
+        }
+    }
+
+    /// Adds text with whitespace collapsed like HTML does.
+    fn text(&mut self, text: &str) {
+        let text = decode_entities(text);
+        let mut collapsed = String::with_capacity(text.len());
+        let mut space = self
+            .spans
+            .last()
+            .is_none_or(|s| s.text.ends_with(|c: char| c.is_whitespace()));
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !space {
+                    collapsed.push(' ');
+                }
+                space = true;
+            } else {
+                collapsed.push(c);
+                space = false;
+            }
+        }
+        self.push(collapsed);
+    }
+
+    fn line_break(&mut self) {
+        if let Some(last) = self.spans.last_mut() {
+            let trimmed = last.text.trim_end_matches(' ').len();
+            last.text.truncate(trimmed);
+        }
+        self.push("\n".to_string());
+    }
+
+    fn push(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        let span = Span {
+            text,
+            bold: self.bold > 0,
+            italic: self.italic > 0,
+            code: self.code > 0,
+            link: self.link.clone(),
+        };
+        match self.spans.last_mut() {
+            Some(last) if last.same_style(&span) => last.text.push_str(&span.text),
+            _ => self.spans.push(span),
+        }
+    }
+
+    /// Ends the paragraph or list item being read.
+    fn flush(&mut self) {
+        let mut spans = std::mem::take(&mut self.spans);
+        if let Some(first) = spans.first_mut() {
+            first.text = first.text.trim_start().to_string();
+        }
+        if let Some(last) = spans.last_mut() {
+            last.text = last.text.trim_end().to_string();
+        }
+        spans.retain(|s| !s.text.is_empty());
+        if spans.is_empty() {
+            return;
+        }
+
+        self.blocks.push(match &self.item {
+            Some((bullet, depth)) => Block::ListItem {
+                bullet: bullet.clone(),
+                depth: *depth,
+                spans,
+            },
+            None => Block::Paragraph(spans),
+        });
+    }
+}
+
+fn href(attrs: &str) -> Option<String> {
+    let start = attrs.to_ascii_lowercase().find("href=")? + "href=".len();
+    let value = &attrs[start..];
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split(char::is_whitespace).next()?,
+    };
+    Some(decode_entities(value))
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((entity(&rest[1..end + 1])?, end + 2)));
+        match entity {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn entity(name: &str) -> Option<char> {
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        _ => {
+            let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => name.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> Span {
+        Span {
+            text: text.to_string(),
+            bold: false,
+            italic: false,
+            code: false,
+            link: None,
+        }
+    }
+
+    fn paragraph(text: &str) -> Block {
+        Block::Paragraph(vec![plain(text)])
+    }
+
+    fn item(bullet: &str, depth: usize, text: &str) -> Block {
+        Block::ListItem {
+            bullet: bullet.to_string(),
+            depth,
+            spans: vec![plain(text)],
+        }
+    }
+
+    #[test]
+    fn fdroid_description() {
+        let html = "<p>F-Droid is an installable catalogue of FOSS (Free and Open Source \
+            Software) applications for the Android platform.</p>\n<p>You can:</p>\n<ul>\n\
+            <li>be notified when updates are available</li>\n\
+            <li>optionally download and install updates automatically</li>\n</ul>\n\
+            <p>For more information see <a href=\"https://f-droid.org/about\">the website</a>.</p>";
+
+        assert_eq!(
+            parse(html),
+            [
+                paragraph(
+                    "F-Droid is an installable catalogue of FOSS (Free and Open Source \
+                     Software) applications for the Android platform."
+                ),
+                paragraph("You can:"),
+                item("•", 0, "be notified when updates are available"),
+                item(
+                    "•",
+                    0,
+                    "optionally download and install updates automatically"
+                ),
+                Block::Paragraph(vec![
+                    plain("For more information see "),
+                    Span {
+                        link: Some("https://f-droid.org/about".to_string()),
+                        ..plain("the website")
+                    },
+                    plain("."),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ordered_and_nested_lists() {
+        let html = "<ol><li>Open the app</li><li>Pick a server<ul><li>official</li>\
+            <li>your own</li></ul></li><li>Log in</li></ol>";
+
+        assert_eq!(
+            parse(html),
+            [
+                item("1.", 0, "Open the app"),
+                item("2.", 0, "Pick a server"),
+                item("•", 1, "official"),
+                item("•", 1, "your own"),
+                item("3.", 0, "Log in"),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_breaks_stay_in_the_paragraph() {
+        let html = "<p>Version 2.0 <br/>\n  * new icons<br>* fixes </p>";
+        assert_eq!(
+            parse(html),
+            [paragraph("Version 2.0\n* new icons\n* fixes")]
+        );
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        let html = "<p>Tom &amp; Jerry&#8217;s &lt;b&gt; &#x2014; 5&nbsp;MB, &copy; &unknown</p>\
+            <p><a href='https://example.org/?a=1&amp;b=2'>link</a></p>";
+
+        let blocks = parse(html);
+        assert_eq!(
+            blocks[0],
+            paragraph("Tom & Jerry\u{2019}s <b> \u{2014} 5\u{a0}MB, &copy; &unknown")
+        );
+        let Block::Paragraph(spans) = &blocks[1] else {
+            panic!("{:?}", blocks[1]);
+        };
+        assert_eq!(
+            spans[0].link.as_deref(),
+            Some("https://example.org/?a=1&b=2")
+        );
+    }
+
+    #[test]
+    fn unclosed_tags_end_with_their_block() {
+        let html = "<ul><li>one<li><b>two</ul><p>a <i>slanted <tt>code</p>3 < 4";
+
+        let blocks = parse(html);
+        assert_eq!(blocks[0], item("•", 0, "one"));
+        assert_eq!(
+            blocks[1],
+            Block::ListItem {
+                bullet: "•".to_string(),
+                depth: 0,
+                spans: vec![Span {
+                    bold: true,
+                    ..plain("two")
+                }],
+            }
+        );
+        // Styles aren't tied to blocks, only the matching end tag resets them
+        let Block::Paragraph(spans) = &blocks[2] else {
+            panic!("{:?}", blocks[2]);
+        };
+        let text: String = spans.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(text, "a slanted code");
+        assert!(spans.iter().all(|s| s.bold));
+        assert_eq!(blocks.len(), 4);
+        let Block::Paragraph(spans) = &blocks[3] else {
+            panic!("{:?}", blocks[3]);
+        };
+        assert_eq!(spans.last().unwrap().text, "3 < 4");
+    }
+
+    #[test]
+    fn descriptions_are_parsed_again_once_the_app_changed() {
+        let index: crate::index_v1::IndexV1 =
+            serde_json::from_str(include_str!("../tests/fixtures/index-v1.json")).unwrap();
+        let mut app = crate::repo::Repo::from(index).apps.unwrap().remove(0);
+        let mut parsed = Parsed::default();
+
+        let first = parsed.get("repo", &app);
+        assert!(Arc::ptr_eq(&first, &parsed.get("repo", &app)));
+        assert!(!Arc::ptr_eq(&first, &parsed.get("other repo", &app)));
+
+        app.desc = "<p>Changed</p>".to_string();
+        app.last_updated.push('1');
+        assert_eq!(parsed.get("repo", &app).desc, [paragraph("Changed")]);
+    }
+
+    #[test]
+    fn plain_text_keeps_its_lines() {
+        let text = "First line\nsecond line\n\nAnother paragraph";
+        assert_eq!(
+            parse(text),
+            [
+                paragraph("First line\nsecond line"),
+                paragraph("Another paragraph")
+            ]
+        );
+    }
+}
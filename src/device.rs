@@ -0,0 +1,181 @@
+//! What the device can install: its API level, the ABIs it runs native code for and its
+//! hardware and software features.
+//!
+//! On Android the [Device] is read from the system, elsewhere it's set in the settings so the
+//! packages picked match the phone the apks are meant for.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::repo::{App, Package};
+
+/// Android 14 refuses to install apps targeting an API level below this.
+const MIN_INSTALLABLE_TARGET_SDK: u32 = 23;
+/// The first API level enforcing [MIN_INSTALLABLE_TARGET_SDK].
+const ENFORCED_TARGET_SDK_SINCE: u32 = 34;
+
+/// ABIs a device can support, the most capable first.
+pub const KNOWN_ABIS: [&str; 7] = [
+    "arm64-v8a",
+    "armeabi-v7a",
+    "armeabi",
+    "x86_64",
+    "x86",
+    "mips64",
+    "mips",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Device {
+    /// `Build.VERSION.SDK_INT`.
+    pub sdk: u32,
+    /// `Build.SUPPORTED_ABIS`.
+    pub abis: Vec<String>,
+    /// Names of the available features like `android.hardware.camera`, `None` if they aren't
+    /// known and every feature is assumed to be there.
+    pub features: Option<BTreeSet<String>>,
+}
+
+impl Default for Device {
+    /// A recent arm64 phone.
+    fn default() -> Self {
+        Self {
+            sdk: 34,
+            abis: vec!["arm64-v8a".to_string(), "armeabi-v7a".to_string()],
+            features: None,
+        }
+    }
+}
+
+/// Why a [Package] can't be installed on a [Device].
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Incompatibility {
+    #[error("needs Android API level {required}, this device has {device}")]
+    SdkTooLow { required: u32, device: u32 },
+    #[error("targets API level {target}, which is too old to be installed on API level {device}")]
+    TargetSdkTooLow { target: u32, device: u32 },
+    #[error("only runs on {}", .0.join(", "))]
+    Abi(Vec<String>),
+    #[error("needs {}", .0.join(", "))]
+    Features(Vec<String>),
+}
+
+impl Device {
+    /// Reads the profile of the device the app runs on.
+    #[cfg(target_os = "android")]
+    pub fn detect() -> anyhow::Result<Self> {
+        use jni::objects::{JObject, JObjectArray, JString};
+
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }?;
+        let mut env = vm.attach_current_thread()?;
+
+        let sdk = env
+            .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")?
+            .i()?;
+
+        let abis = JObjectArray::from(
+            env.get_static_field("android/os/Build", "SUPPORTED_ABIS", "[Ljava/lang/String;")?
+                .l()?,
+        );
+        let mut supported = Vec::new();
+        for i in 0..env.get_array_length(&abis)? {
+            let abi = JString::from(env.get_object_array_element(&abis, i)?);
+            supported.push(env.get_string(&abi)?.into());
+        }
+
+        let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+        let package_manager = env
+            .call_method(
+                activity,
+                "getPackageManager",
+                "()Landroid/content/pm/PackageManager;",
+                &[],
+            )?
+            .l()?;
+        let infos = JObjectArray::from(
+            env.call_method(
+                package_manager,
+                "getSystemAvailableFeatures",
+                "()[Landroid/content/pm/FeatureInfo;",
+                &[],
+            )?
+            .l()?,
+        );
+        let mut features = BTreeSet::new();
+        for i in 0..env.get_array_length(&infos)? {
+            // Devices list hundreds of features, don't run out of local references
+            let name = env.with_local_frame(16, |env| -> anyhow::Result<Option<String>> {
+                let info = env.get_object_array_element(&infos, i)?;
+                // The OpenGL ES version is listed without a name
+                let name = JString::from(env.get_field(info, "name", "Ljava/lang/String;")?.l()?);
+                Ok(if name.is_null() {
+                    None
+                } else {
+                    Some(env.get_string(&name)?.into())
+                })
+            })?;
+            features.extend(name);
+        }
+
+        Ok(Self {
+            sdk: sdk as u32,
+            abis: supported,
+            features: Some(features),
+        })
+    }
+
+    /// Everything keeping `package` from being installed, empty if it's compatible.
+    pub fn check(&self, package: &Package) -> Vec<Incompatibility> {
+        let mut problems = Vec::new();
+
+        if let Some(required) = package.sdk_version.filter(|&sdk| sdk > self.sdk) {
+            problems.push(Incompatibility::SdkTooLow {
+                required,
+                device: self.sdk,
+            });
+        }
+        if let Some(target) = package.target_sdk_version {
+            if self.sdk >= ENFORCED_TARGET_SDK_SINCE && target < MIN_INSTALLABLE_TARGET_SDK {
+                problems.push(Incompatibility::TargetSdkTooLow {
+                    target,
+                    device: self.sdk,
+                });
+            }
+        }
+
+        // Packages without native code run everywhere
+        if let Some(native_code) = package.native_code.as_ref().filter(|n| !n.is_empty()) {
+            if !native_code.iter().any(|abi| self.abis.contains(abi)) {
+                problems.push(Incompatibility::Abi(native_code.clone()));
+            }
+        }
+
+        if let (Some(required), Some(available)) = (&package.features, &self.features) {
+            let missing: Vec<String> = required
+                .iter()
+                .filter(|f| !available.contains(*f))
+                .cloned()
+                .collect();
+            if !missing.is_empty() {
+                problems.push(Incompatibility::Features(missing));
+            }
+        }
+
+        problems
+    }
+
+    pub fn is_compatible(&self, package: &Package) -> bool {
+        self.check(package).is_empty()
+    }
+
+    /// The newest package of `app` that can be installed here.
+    pub fn suggested<'a>(&self, app: &'a App) -> Option<&'a Package> {
+        app.packages
+            .iter()
+            .filter(|p| self.is_compatible(p))
+            .max_by_key(|p| p.version_code)
+    }
+}
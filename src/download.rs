@@ -0,0 +1,873 @@
+//! Downloads apks into [crate::cache::apk_dir], a few at a time.
+//!
+//! Apks are kept in a directory per repo, the same apk name can be another apk in another repo.
+//! Unfinished downloads stay around as `<apk>.part` and are resumed with a `Range` request the next
+//! time the same apk is downloaded, be it after a cancel, an error or a restart of the app.
+//!
+//! Finished apks are checked against [Package::hash] and the signer from the index, and only kept
+//! if they match.
+
+use std::{
+    collections::HashMap,
+    fs::File as StdFile,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use egui::Context;
+use futures_util::StreamExt;
+use parking_lot::Mutex;
+use reqwest::{
+    header::{CONTENT_RANGE, RANGE},
+    Client, Response, StatusCode,
+};
+use sha2::{Digest, Sha256, Sha512};
+use thiserror::Error;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    runtime::Handle,
+    sync::{Notify, Semaphore},
+    task::AbortHandle,
+};
+
+use crate::{
+    apk,
+    repo::{FileHash, Package},
+};
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("server answered {0}")]
+    HttpStatus(StatusCode),
+    #[error("can't write the apk: {0}")]
+    Io(#[from] io::Error),
+    /// The apk is corrupted, or not the one the index is signed for.
+    #[error("the apk doesn't match its {kind} in the index, expected {expected} but got {actual}")]
+    HashMismatch {
+        kind: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("can't check a {0} hash")]
+    UnsupportedHash(String),
+    #[error("bad apk signature: {0}")]
+    Signature(String),
+    /// The apk is signed properly, but not by who the index says.
+    #[error("the apk isn't signed by the certificate listed in the index")]
+    WrongSigner,
+    /// The index lists neither `sig` nor `signer`, so anyone could have signed the apk.
+    #[error("the index doesn't say who signs the apk")]
+    UnknownSigner,
+    #[error("no url to download the apk from")]
+    NoMirror,
+}
+
+type Result<T> = std::result::Result<T, DownloadError>;
+
+#[derive(Debug, Clone)]
+pub enum DownloadState {
+    /// Waiting for one of the other downloads to finish.
+    Queued,
+    Running {
+        /// Bytes on disk, including what was there before resuming.
+        done: u64,
+        total: Option<u64>,
+    },
+    Done(PathBuf),
+    Failed(Arc<DownloadError>),
+}
+
+impl DownloadState {
+    pub fn is_active(&self) -> bool {
+        matches!(self, Self::Queued | Self::Running { .. })
+    }
+
+    /// How far along the download is, from 0 to 1, if the size is known.
+    pub fn progress(&self) -> Option<f32> {
+        match self {
+            Self::Running {
+                done,
+                total: Some(total),
+            } if *total > 0 => Some(*done as f32 / *total as f32),
+            Self::Done(_) => Some(1.0),
+            _ => None,
+        }
+    }
+}
+
+/// Which apk a download is of, [Package::apk_name] in the repo with the [Repo::cache_key] `repo`.
+///
+/// [Repo::cache_key]: crate::repo::Repo::cache_key
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DownloadKey {
+    pub repo: String,
+    pub apk_name: String,
+}
+
+impl DownloadKey {
+    pub fn new(repo: &str, package: &Package) -> Self {
+        Self {
+            repo: repo.to_string(),
+            apk_name: package.apk_name.clone(),
+        }
+    }
+}
+
+struct Download {
+    state: DownloadState,
+    /// Tells the task of this download apart from the one of a cancelled download of the same apk.
+    id: u64,
+    task: AbortHandle,
+}
+
+pub struct Downloads {
+    client: Client,
+    rt: Handle,
+    dir: PathBuf,
+    permits: Arc<Semaphore>,
+    downloads: Arc<Mutex<HashMap<DownloadKey, Download>>>,
+    next_id: AtomicU64,
+    /// Signalled whenever a download changes.
+    changed: Arc<Notify>,
+}
+
+impl Downloads {
+    /// Downloads into `dir`, running at most `max_running` downloads at the same time on `rt`, the
+    /// others are queued. Every change of a download signals `changed`.
+    pub fn new(
+        client: Client,
+        rt: Handle,
+        dir: PathBuf,
+        max_running: usize,
+        changed: Arc<Notify>,
+    ) -> Self {
+        Self {
+            client,
+            rt,
+            dir,
+            permits: Arc::new(Semaphore::new(max_running)),
+            downloads: Default::default(),
+            next_id: AtomicU64::new(0),
+            changed,
+        }
+    }
+
+    /// Starts downloading `package` of the repo `repo` from the first of `mirrors` that has it,
+    /// unless it's already being downloaded.
+    pub fn start(&self, repo: &str, mirrors: Vec<String>, package: &Package, ctx: &Context) {
+        let key = DownloadKey::new(repo, package);
+        let package = package.clone();
+        let mut downloads = self.downloads.lock();
+        if downloads.get(&key).is_some_and(|d| d.state.is_active()) {
+            return;
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let client = self.client.clone();
+        let dir = self.dir.join(&key.repo);
+        let permits = self.permits.clone();
+        let shared = self.downloads.clone();
+        let changed = self.changed.clone();
+        let ctx = ctx.clone();
+        let task = self.rt.spawn({
+            let key = key.clone();
+            async move {
+                let Ok(_permit) = permits.acquire_owned().await else {
+                    return;
+                };
+                let set_state = |state| {
+                    if let Some(download) = shared.lock().get_mut(&key).filter(|d| d.id == id) {
+                        download.state = state;
+                    }
+                    changed.notify_one();
+                    ctx.request_repaint();
+                };
+                set_state(DownloadState::Running {
+                    done: 0,
+                    total: None,
+                });
+
+                let result = fetch(&client, &dir, &mirrors, &package, |done, total| {
+                    set_state(DownloadState::Running { done, total })
+                })
+                .await;
+                set_state(match result {
+                    Ok(path) => DownloadState::Done(path),
+                    Err(e) => {
+                        log::error!("{}: {e}", key.apk_name);
+                        DownloadState::Failed(Arc::new(e))
+                    }
+                });
+            }
+        });
+
+        downloads.insert(
+            key,
+            Download {
+                state: DownloadState::Queued,
+                id,
+                task: task.abort_handle(),
+            },
+        );
+        self.changed.notify_one();
+    }
+
+    /// Stops downloading `key` and forgets about it, what's downloaded so far is kept to resume.
+    pub fn cancel(&self, key: &DownloadKey) {
+        if let Some(download) = self.downloads.lock().remove(key) {
+            download.task.abort();
+            self.changed.notify_one();
+        }
+    }
+
+    pub fn state(&self, key: &DownloadKey) -> Option<DownloadState> {
+        self.downloads.lock().get(key).map(|d| d.state.clone())
+    }
+
+    /// Every download that wasn't cancelled, sorted by apk name.
+    pub fn all(&self) -> Vec<(DownloadKey, DownloadState)> {
+        let mut all: Vec<_> = self
+            .downloads
+            .lock()
+            .iter()
+            .map(|(key, d)| (key.clone(), d.state.clone()))
+            .collect();
+        all.sort_by(|a, b| (&a.0.apk_name, &a.0.repo).cmp(&(&b.0.apk_name, &b.0.repo)));
+        all
+    }
+
+    pub fn active(&self) -> usize {
+        self.downloads
+            .lock()
+            .values()
+            .filter(|d| d.state.is_active())
+            .count()
+    }
+}
+
+/// Downloads `package` into `dir` from the first of `mirrors` that has it, calling `progress` with
+/// the bytes done so far and the total size.
+async fn fetch(
+    client: &Client,
+    dir: &Path,
+    mirrors: &[String],
+    package: &Package,
+    progress: impl Fn(u64, Option<u64>),
+) -> Result<PathBuf> {
+    let apk_name = &package.apk_name;
+    fs::create_dir_all(dir).await?;
+    let file_name = Path::new(apk_name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| apk_name.replace('/', "_"));
+    let path = dir.join(&file_name);
+    let part = dir.join(format!("{file_name}.part"));
+
+    if fs::try_exists(&path).await? {
+        match verify_blocking(&path, package).await {
+            Ok(()) => return Ok(path),
+            Err(e) => {
+                log::warn!("{apk_name}: downloading again, {e}");
+                fs::remove_file(&path).await?;
+            }
+        }
+    }
+
+    let mut last_error = DownloadError::NoMirror;
+    for mirror in mirrors {
+        match fetch_from(client, &format!("{mirror}/{apk_name}"), &part, &progress).await {
+            Ok(()) => match verify_blocking(&part, package).await {
+                Ok(()) => {
+                    fs::rename(&part, &path).await?;
+                    return Ok(path);
+                }
+                // Another mirror may have the right one
+                Err(e) => {
+                    log::warn!("{mirror}/{apk_name}: {e}");
+                    fs::remove_file(&part).await?;
+                    last_error = e;
+                }
+            },
+            Err(e) => {
+                log::warn!("{mirror}/{apk_name}: {e}");
+                last_error = e;
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Downloads `url` into `part`, continuing after what's already in there if the server allows it.
+async fn fetch_from(
+    client: &Client,
+    url: &str,
+    part: &Path,
+    progress: &impl Fn(u64, Option<u64>),
+) -> Result<()> {
+    let offset = fs::metadata(part).await.map(|m| m.len()).unwrap_or(0);
+
+    let mut response = request(client, url, offset).await?;
+    // The partial file is as big as the apk or bigger, it's from something else
+    if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        response = request(client, url, 0).await?;
+    }
+    let response = check_status(response)?;
+
+    // A server that ignores the range sends the whole apk again
+    let resumed = offset > 0
+        && response.status() == StatusCode::PARTIAL_CONTENT
+        && range_start(&response) == Some(offset);
+    let (mut file, mut done) = if resumed {
+        (OpenOptions::new().append(true).open(part).await?, offset)
+    } else {
+        (File::create(part).await?, 0)
+    };
+    let total = response.content_length().map(|len| done + len);
+    progress(done, total);
+
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        done += chunk.len() as u64;
+        progress(done, total);
+    }
+    file.sync_all().await?;
+
+    Ok(())
+}
+
+/// Checks that the apk at `path` is the one listed as `package` in the index, by its hash and signer.
+pub fn verify(path: &Path, package: &Package) -> Result<()> {
+    verify_hash(path, &package.hash)?;
+    if package.sig.is_none() && package.signer.is_none() {
+        return Err(DownloadError::UnknownSigner);
+    }
+
+    let cert = apk::signer(path).map_err(|e| DownloadError::Signature(format!("{e:#}")))?;
+    let sig_matches = package
+        .sig
+        .as_ref()
+        .map(|sig| sig.eq_ignore_ascii_case(&apk::fdroid_sig(&cert)));
+    let signer_matches = package
+        .signer
+        .as_ref()
+        .map(|signer| signer.eq_ignore_ascii_case(&hex::encode(Sha256::digest(&cert))));
+    if sig_matches == Some(false) || signer_matches == Some(false) {
+        return Err(DownloadError::WrongSigner);
+    }
+
+    Ok(())
+}
+
+fn verify_hash(path: &Path, hash: &FileHash) -> Result<()> {
+    let mut file = StdFile::open(path)?;
+    let actual = match hash.kind.to_ascii_lowercase().as_str() {
+        "sha256" => digest::<Sha256>(&mut file)?,
+        "sha512" => digest::<Sha512>(&mut file)?,
+        kind => return Err(DownloadError::UnsupportedHash(kind.to_string())),
+    };
+
+    if actual.eq_ignore_ascii_case(hash.value.trim()) {
+        Ok(())
+    } else {
+        Err(DownloadError::HashMismatch {
+            kind: hash.kind.clone(),
+            expected: hash.value.clone(),
+            actual,
+        })
+    }
+}
+
+async fn verify_blocking(path: &Path, package: &Package) -> Result<()> {
+    let (path, package) = (path.to_path_buf(), package.clone());
+    tokio::task::spawn_blocking(move || verify(&path, &package))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn digest<D: Digest + io::Write>(file: &mut StdFile) -> io::Result<String> {
+    let mut digest = D::new();
+    io::copy(file, &mut digest)?;
+    Ok(hex::encode(digest.finalize()))
+}
+
+async fn request(client: &Client, url: &str, offset: u64) -> Result<Response> {
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+    }
+    Ok(request.send().await?)
+}
+
+/// The first byte of a partial response, from `Content-Range: bytes <start>-<end>/<size>`.
+fn range_start(response: &Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn check_status(response: Response) -> Result<Response> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(DownloadError::HttpStatus(response.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const APK: &[u8] = include_bytes!("../tests/fixtures/app-v2.apk");
+    const OTHER_APK: &[u8] = include_bytes!("../tests/fixtures/app-other-signer.apk");
+    const SIGNER: &str = "8f59aaa0c814d58c32496e513861afb1f22add89a403e1fb4caf7d9991001c2c";
+    /// Only signed with a v1 (JAR) signature, see `make-jars.py`.
+    const V1_APK: &[u8] = include_bytes!("../tests/fixtures/app-v1.apk");
+    const V1_SIGNER: &str = "eeba212c729c2e69a8b3bf740a3b3df5262f5b14ef156d139ac33bd5413c35ad";
+
+    /// The path and the `Range` header of a request.
+    type Request = (String, Option<String>);
+
+    /// Serves the fixture apk at `/ok/`, [OTHER_APK] at `/other/`, half of the apk and then
+    /// nothing at `/stall/` and 404 anywhere else. Every request is recorded with its `Range`.
+    struct Server {
+        url: String,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl Server {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+
+            let recorded = requests.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let recorded = recorded.clone();
+                    tokio::spawn(async move {
+                        let mut stream = BufReader::new(stream);
+                        let mut path = None;
+                        let mut range = None;
+                        let mut line = String::new();
+                        while stream.read_line(&mut line).await.unwrap() > 2 {
+                            if let Some(rest) = line.strip_prefix("GET ") {
+                                path = rest.split(' ').next().map(str::to_string);
+                            } else if let Some((name, value)) = line.split_once(':') {
+                                if name.eq_ignore_ascii_case("range") {
+                                    range = Some(value.trim().to_string());
+                                }
+                            }
+                            line.clear();
+                        }
+                        let path = path.unwrap();
+                        recorded.lock().push((path.clone(), range.clone()));
+
+                        let mut stream = stream.into_inner();
+                        let body = match path.split('/').nth(1) {
+                            Some("ok" | "stall") => APK,
+                            Some("other") => OTHER_APK,
+                            _ => {
+                                let head = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+                                            Connection: close\r\n\r\n";
+                                stream.write_all(head.as_bytes()).await.unwrap();
+                                return;
+                            }
+                        };
+                        let start: usize = range
+                            .as_deref()
+                            .and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok())
+                            .unwrap_or(0);
+                        let head = if start >= body.len() {
+                            "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n".into()
+                        } else if start > 0 {
+                            format!(
+                                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                                 Content-Range: bytes {start}-{}/{}\r\n",
+                                body.len() - start,
+                                body.len() - 1,
+                                body.len()
+                            )
+                        } else {
+                            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", body.len())
+                        };
+                        stream
+                            .write_all(format!("{head}Connection: close\r\n\r\n").as_bytes())
+                            .await
+                            .unwrap();
+                        let body = &body[start.min(body.len())..];
+                        if path.starts_with("/stall/") {
+                            let _ = stream.write_all(&body[..body.len() / 2]).await;
+                            let _ = stream.flush().await;
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                        } else {
+                            let _ = stream.write_all(body).await;
+                        }
+                    });
+                }
+            });
+
+            Self { url, requests }
+        }
+
+        fn mirror(&self, route: &str) -> String {
+            format!("{}/{route}", self.url)
+        }
+
+        fn requests(&self) -> Vec<Request> {
+            self.requests.lock().clone()
+        }
+    }
+
+    fn package() -> Package {
+        Package {
+            version: "1.0".to_string(),
+            version_code: 1,
+            apk_name: "app.apk".to_string(),
+            src_name: None,
+            hash: FileHash {
+                kind: "sha256".to_string(),
+                value: hex::encode(Sha256::digest(APK)),
+            },
+            size: APK.len() as u32,
+            sdk_version: None,
+            target_sdk_version: None,
+            added: String::new(),
+            sig: None,
+            signer: Some(SIGNER.to_string()),
+            permissions: None,
+            native_code: None,
+            features: None,
+        }
+    }
+
+    /// An empty directory for the apks of a test.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("edroid-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+        for _ in 0..500 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out waiting for {what}");
+    }
+
+    #[tokio::test]
+    async fn progress_goes_up_to_the_size() {
+        let server = Server::start().await;
+        let dir = temp_dir("progress");
+        let progress = Mutex::new(Vec::new());
+
+        let path = fetch(
+            &Client::new(),
+            &dir,
+            &[server.mirror("ok")],
+            &package(),
+            |done, total| progress.lock().push((done, total)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), APK);
+        let progress = progress.into_inner();
+        let total = Some(APK.len() as u64);
+        assert_eq!(progress.first(), Some(&(0, total)));
+        assert_eq!(progress.last(), Some(&(APK.len() as u64, total)));
+        assert!(progress.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[tokio::test]
+    async fn partial_downloads_are_resumed() {
+        let server = Server::start().await;
+        let dir = temp_dir("resume");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.apk.part"), &APK[..1000]).unwrap();
+
+        let path = fetch(
+            &Client::new(),
+            &dir,
+            &[server.mirror("ok")],
+            &package(),
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), APK);
+        assert!(!dir.join("app.apk.part").exists());
+        assert_eq!(
+            server.requests(),
+            [("/ok/app.apk".to_string(), Some("bytes=1000-".to_string()))]
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_partial_downloads_start_over() {
+        let server = Server::start().await;
+        let dir = temp_dir("oversized");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.apk.part"), [APK, APK].concat()).unwrap();
+
+        let path = fetch(
+            &Client::new(),
+            &dir,
+            &[server.mirror("ok")],
+            &package(),
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), APK);
+        let ranges: Vec<_> = server.requests().into_iter().map(|(_, r)| r).collect();
+        assert_eq!(ranges, [Some(format!("bytes={}-", 2 * APK.len())), None]);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_next_mirror() {
+        let server = Server::start().await;
+        let dir = temp_dir("mirrors");
+        let mirrors = [
+            server.mirror("missing"),
+            server.mirror("other"),
+            server.mirror("ok"),
+        ];
+
+        let path = fetch(&Client::new(), &dir, &mirrors, &package(), |_, _| {})
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), APK);
+        let paths: Vec<_> = server.requests().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(paths, ["/missing/app.apk", "/other/app.apk", "/ok/app.apk"]);
+        // The apk of the other mirror wasn't resumed from
+        assert!(server.requests().iter().all(|(_, range)| range.is_none()));
+    }
+
+    #[tokio::test]
+    async fn the_last_error_is_returned() {
+        let server = Server::start().await;
+        let dir = temp_dir("errors");
+        let mirrors = [server.mirror("other"), server.mirror("missing")];
+
+        let result = fetch(&Client::new(), &dir, &mirrors, &package(), |_, _| {}).await;
+
+        assert!(matches!(
+            result,
+            Err(DownloadError::HttpStatus(StatusCode::NOT_FOUND))
+        ));
+        let result = fetch(&Client::new(), &dir, &[], &package(), |_, _| {}).await;
+        assert!(matches!(result, Err(DownloadError::NoMirror)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancelled_downloads_are_resumed() {
+        let server = Server::start().await;
+        let dir = temp_dir("cancel");
+        let downloads = Downloads::new(
+            Client::new(),
+            Handle::current(),
+            dir.clone(),
+            2,
+            Default::default(),
+        );
+        let ctx = Context::default();
+        let key = DownloadKey::new("repo", &package());
+
+        downloads.start("repo", vec![server.mirror("stall")], &package(), &ctx);
+        let half = (APK.len() / 2) as u64;
+        wait_for("half of the apk", || {
+            matches!(downloads.state(&key), Some(DownloadState::Running { done, .. }) if done == half)
+        })
+        .await;
+        downloads.cancel(&key);
+        assert!(downloads.state(&key).is_none());
+        // Writes handed to tokio's blocking pool still land after the abort
+        wait_for("the partial apk", || {
+            std::fs::metadata(dir.join("repo/app.apk.part")).is_ok_and(|m| m.len() == half)
+        })
+        .await;
+
+        downloads.start("repo", vec![server.mirror("ok")], &package(), &ctx);
+        wait_for("the download", || {
+            matches!(downloads.state(&key), Some(DownloadState::Done(_)))
+        })
+        .await;
+        assert_eq!(std::fs::read(dir.join("repo/app.apk")).unwrap(), APK);
+        assert_eq!(
+            server.requests()[1],
+            ("/ok/app.apk".to_string(), Some(format!("bytes={half}-")))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_some_downloads_run_at_once() {
+        let server = Server::start().await;
+        let dir = temp_dir("limit");
+        let downloads = Downloads::new(
+            Client::new(),
+            Handle::current(),
+            dir.clone(),
+            2,
+            Default::default(),
+        );
+        let ctx = Context::default();
+
+        let keys: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|repo| {
+                downloads.start(repo, vec![server.mirror("stall")], &package(), &ctx);
+                DownloadKey::new(repo, &package())
+            })
+            .collect();
+        wait_for("two downloads", || server.requests().len() == 2).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.requests().len(), 2);
+        let queued: Vec<_> = keys
+            .iter()
+            .filter(|k| matches!(downloads.state(k), Some(DownloadState::Queued)))
+            .collect();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(downloads.active(), 3);
+
+        // The queued one gets the place of a cancelled one
+        let running = keys.iter().find(|k| !queued.contains(k)).unwrap();
+        downloads.cancel(running);
+        wait_for("the queued download", || server.requests().len() == 3).await;
+        assert!(matches!(
+            downloads.state(queued[0]),
+            Some(DownloadState::Running { .. })
+        ));
+    }
+
+    /// Writes `apk` into the temporary directory of `test`.
+    fn write_apk(test: &str, apk: &[u8]) -> PathBuf {
+        let dir = temp_dir(test);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.apk");
+        std::fs::write(&path, apk).unwrap();
+        path
+    }
+
+    #[test]
+    fn apks_are_checked_against_the_index() {
+        let path = write_apk("verify", APK);
+        verify(&path, &package()).unwrap();
+
+        let cert = apk::signer(&path).unwrap();
+        let by_sig = Package {
+            sig: Some(apk::fdroid_sig(&cert).to_uppercase()),
+            signer: None,
+            ..package()
+        };
+        verify(&path, &by_sig).unwrap();
+    }
+
+    #[test]
+    fn v1_signed_apks_are_checked_against_the_index() {
+        let path = write_apk("verify-v1", V1_APK);
+        let v1 = Package {
+            hash: FileHash {
+                kind: "sha256".to_string(),
+                value: hex::encode(Sha256::digest(V1_APK)),
+            },
+            signer: Some(V1_SIGNER.to_string()),
+            ..package()
+        };
+        verify(&path, &v1).unwrap();
+
+        let other = Package {
+            signer: Some(SIGNER.to_string()),
+            ..v1
+        };
+        assert!(matches!(
+            verify(&path, &other),
+            Err(DownloadError::WrongSigner)
+        ));
+    }
+
+    #[test]
+    fn apks_without_a_listed_signer_are_rejected() {
+        let path = write_apk("unknown-signer", APK);
+        let unsigned = Package {
+            sig: None,
+            signer: None,
+            ..package()
+        };
+        assert!(matches!(
+            verify(&path, &unsigned),
+            Err(DownloadError::UnknownSigner)
+        ));
+    }
+
+    #[test]
+    fn apks_of_someone_else_are_rejected() {
+        let path = write_apk("wrong-signer", OTHER_APK);
+        let other = Package {
+            hash: FileHash {
+                kind: "sha256".to_string(),
+                value: hex::encode(Sha256::digest(OTHER_APK)),
+            },
+            ..package()
+        };
+        assert!(matches!(
+            verify(&path, &other),
+            Err(DownloadError::WrongSigner)
+        ));
+
+        let path = write_apk("wrong-hash", OTHER_APK);
+        assert!(matches!(
+            verify(&path, &package()),
+            Err(DownloadError::HashMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn the_same_apk_of_another_repo_is_another_download() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let dir = temp_dir("keys");
+        let downloads = Downloads::new(
+            Client::new(),
+            rt.handle().clone(),
+            dir,
+            0,
+            Default::default(),
+        );
+        let ctx = Context::default();
+
+        downloads.start("a", vec![], &package(), &ctx);
+        downloads.start("b", vec![], &package(), &ctx);
+        downloads.start("a", vec![], &package(), &ctx);
+
+        let keys: Vec<_> = downloads.all().into_iter().map(|(k, _)| k.repo).collect();
+        assert_eq!(keys, ["a", "b"]);
+    }
+}
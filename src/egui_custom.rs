@@ -1,14 +1,20 @@
-use egui::{Response, RichText, Sense, Ui};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::repo::App;
+use egui::{RichText, Sense, Ui};
 
-pub fn group_button(ui: &mut Ui, app: &App, image_left: bool) {
-    if ui.group(|ui| {
+use crate::{
+    repo::{App, Repo},
+    sync::SyncStatus,
+};
+
+/// A card with the icon, name and summary of `app` from `repo`, returns whether it was clicked.
+pub fn group_button(ui: &mut Ui, repo: &Repo, app: &App, image_left: bool) -> bool {
+    let icon = app.icon_path().and_then(|icon| repo.file_url(&icon));
+    ui.group(|ui| {
         if image_left {
             ui.horizontal(|ui| {
-                if let Some(icon) = &app.icon {
-                    log::info!("{icon}");
-                    ui.image("https://f-droid.org/repo/icons/".to_string() + icon);
+                if let Some(icon) = &icon {
+                    ui.image(icon);
                 }
                 ui.vertical(|ui| {
                     ui.label(RichText::new(app.name.clone()).strong());
@@ -21,9 +27,8 @@ pub fn group_button(ui: &mut Ui, app: &App, image_left: bool) {
         } else {
             ui.vertical(|ui| {
                 ui.vertical_centered(|ui| {
-                    if let Some(icon) = &app.icon {
-                        log::info!("{icon}");
-                        ui.image("https://f-droid.org/repo/icons/".to_string() + icon);
+                    if let Some(icon) = &icon {
+                        ui.image(icon);
                     }
                     ui.label(RichText::new(app.name.clone()).strong());
                 });
@@ -34,7 +39,54 @@ pub fn group_button(ui: &mut Ui, app: &App, image_left: bool) {
             });
         }     
     })
-    .response.interact(Sense::click()).clicked() {
-        log::info!("app {} clicked", app.name);
+    .response.interact(Sense::click()).clicked()
+}
+
+/// A short label with how the last sync of `repo` went, details are in the hover text.
+pub fn sync_status(ui: &mut Ui, repo: &Repo) {
+    let name = &repo.meta.name;
+    match &repo.status {
+        SyncStatus::Idle => {}
+        SyncStatus::Syncing => {
+            ui.spinner().on_hover_text(format!("Syncing {name}"));
+        }
+        SyncStatus::Ok(at) => {
+            ui.label("✔")
+                .on_hover_text(format!("{name} synced {}", ago(*at)));
+        }
+        SyncStatus::Failed(e) => {
+            ui.colored_label(ui.visuals().error_fg_color, format!("✖ {name}"))
+                .on_hover_text(e.to_string());
+        }
+    }
+
+    if repo.meta.is_stale(now()) {
+        ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {name}"))
+            .on_hover_text(format!(
+                "The index of {name} is from {}, older than the {} days it should be used for",
+                ago(repo.meta.timestamp),
+                repo.meta.max_age
+            ));
+    }
+}
+
+/// Formats seconds since the epoch relative to now, like `5 min ago`.
+fn ago(secs: u64) -> String {
+    match now().saturating_sub(secs) {
+        s if s < 60 => "just now".to_string(),
+        s if s < 60 * 60 => format!("{} min ago", s / 60),
+        s if s < 60 * 60 * 24 => format!("{} h ago", s / 60 / 60),
+        s => format!("{} days ago", s / 60 / 60 / 24),
     }
 }
+
+pub fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / 1024.0 / 1024.0)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,342 @@
+//! The index-v1 format, `index-v1.json` inside of `index-v1.jar`.
+//!
+//! It carries everything from `index.xml` plus localized texts and graphics, and is mapped
+//! into the same [Repo] the xml index deserializes into.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::repo::{AntiFeature, App, Category, FileHash, Meta, Package, Repo};
+
+/// Locales tried, in order, before falling back to whatever the app provides.
+const PREFERRED_LOCALES: [&str; 3] = ["en-US", "en", "en-GB"];
+
+#[derive(Deserialize)]
+pub struct IndexV1 {
+    pub repo: RepoV1,
+    #[serde(default)]
+    pub apps: Vec<AppV1>,
+    #[serde(default)]
+    pub packages: HashMap<String, Vec<PackageV1>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoV1 {
+    pub name: String,
+    pub icon: String,
+    pub address: String,
+    pub description: Option<String>,
+    /// Milliseconds since the epoch.
+    pub timestamp: u64,
+    pub version: Option<u32>,
+    #[serde(rename = "maxage")]
+    pub max_age: Option<u32>,
+    pub mirrors: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppV1 {
+    pub package_name: String,
+    pub name: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub license: String,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub anti_features: Vec<String>,
+    pub web_site: Option<String>,
+    pub source_code: Option<String>,
+    pub issue_tracker: Option<String>,
+    pub changelog: Option<String>,
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    pub donate: Option<String>,
+    pub bitcoin: Option<String>,
+    pub open_collective: Option<String>,
+    pub suggested_version_name: Option<String>,
+    /// A string in the index, unlike the version codes of packages.
+    pub suggested_version_code: Option<String>,
+    pub added: Option<u64>,
+    pub last_updated: Option<u64>,
+    #[serde(default)]
+    pub localized: HashMap<String, LocalizedV1>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalizedV1 {
+    pub name: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub whats_new: Option<String>,
+    pub feature_graphic: Option<String>,
+    #[serde(default)]
+    pub phone_screenshots: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageV1 {
+    pub version_name: Option<String>,
+    pub version_code: u32,
+    pub apk_name: String,
+    #[serde(rename = "srcname")]
+    pub src_name: Option<String>,
+    pub hash: String,
+    pub hash_type: Option<String>,
+    pub size: u32,
+    pub min_sdk_version: Option<u32>,
+    pub target_sdk_version: Option<u32>,
+    pub added: Option<u64>,
+    pub sig: Option<String>,
+    /// `[name, maxSdkVersion]` pairs.
+    #[serde(rename = "uses-permission", default)]
+    pub uses_permission: Vec<(String, Option<u32>)>,
+    #[serde(rename = "nativecode")]
+    pub native_code: Option<Vec<String>>,
+    pub features: Option<Vec<String>>,
+}
+
+impl From<IndexV1> for Repo {
+    fn from(mut index: IndexV1) -> Self {
+        let meta = Meta {
+            icon: index.repo.icon,
+            max_age: index.repo.max_age.unwrap_or_default().to_string(),
+            name: index.repo.name,
+            pub_key: String::new(),
+            timestamp: index.repo.timestamp / 1000,
+            url: Some(index.repo.address),
+            version: index.repo.version,
+            desc: index.repo.description,
+            mirrors: index.repo.mirrors,
+            fingerprint: None,
+        };
+
+        let apps = index
+            .apps
+            .into_iter()
+            .map(|app| {
+                let packages = index.packages.remove(&app.package_name).unwrap_or_default();
+                app.into_app(packages)
+            })
+            .collect();
+
+        Self {
+            meta,
+            apps: Some(apps),
+            raw_v2: None,
+            config: Default::default(),
+            status: Default::default(),
+        }
+    }
+}
+
+impl AppV1 {
+    fn into_app(mut self, packages: Vec<PackageV1>) -> App {
+        let locale = pick_locale(&self.localized).cloned().unwrap_or_default();
+        let localized = self.localized.remove(&locale).unwrap_or_default();
+
+        let packages: Vec<Package> = packages.into_iter().map(Package::from).collect();
+        let categories: Vec<Category> = self
+            .categories
+            .iter()
+            .map(|c| Category::from(c.as_str()))
+            .collect();
+        let id = self.package_name;
+
+        App {
+            added: self.added.map(date).unwrap_or_default(),
+            last_updated: self.last_updated.map(date).unwrap_or_default(),
+            name: localized.name.or(self.name).unwrap_or_else(|| id.clone()),
+            summary: localized.summary.or(self.summary).unwrap_or_default(),
+            icon: self.icon,
+            desc: localized
+                .description
+                .or(self.description)
+                .unwrap_or_default(),
+            license: self.license,
+            category: categories
+                .first()
+                .cloned()
+                .unwrap_or(Category::Custom(String::new())),
+            categories,
+            anti_features: self
+                .anti_features
+                .iter()
+                .map(|a| AntiFeature::from(a.as_str()))
+                .collect(),
+            web: self.web_site,
+            source: self.source_code,
+            tracker: self.issue_tracker,
+            changelog: self.changelog,
+            author: self.author_name,
+            email: self.author_email,
+            donate: self.donate,
+            bitcoin: self.bitcoin,
+            open_collective: self.open_collective,
+            market_version: self
+                .suggested_version_name
+                .or_else(|| packages.first().map(|p| p.version.clone()))
+                .unwrap_or_default(),
+            market_version_code: self
+                .suggested_version_code
+                .and_then(|c| c.parse().ok())
+                .or_else(|| packages.iter().map(|p| p.version_code).max())
+                .unwrap_or_default(),
+            whats_new: localized.whats_new,
+            feature_graphic: localized
+                .feature_graphic
+                .map(|f| format!("{id}/{locale}/{f}")),
+            screenshots: localized
+                .phone_screenshots
+                .into_iter()
+                .map(|s| format!("{id}/{locale}/phoneScreenshots/{s}"))
+                .collect(),
+            packages,
+            id,
+        }
+    }
+}
+
+impl From<PackageV1> for Package {
+    fn from(package: PackageV1) -> Self {
+        Self {
+            version: package.version_name.unwrap_or_default(),
+            version_code: package.version_code,
+            apk_name: package.apk_name,
+            src_name: package.src_name,
+            hash: FileHash {
+                kind: package.hash_type.unwrap_or_else(|| "sha256".to_string()),
+                value: package.hash,
+            },
+            size: package.size,
+            sdk_version: package.min_sdk_version,
+            target_sdk_version: package.target_sdk_version,
+            added: package.added.map(date).unwrap_or_default(),
+            sig: package.sig,
+            signer: None,
+            permissions: Some(
+                package
+                    .uses_permission
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect(),
+            ),
+            native_code: package.native_code,
+            features: package.features,
+        }
+    }
+}
+
+/// The locale of `localized` that's shown to the user, see [PREFERRED_LOCALES].
+pub fn pick_locale<T>(localized: &HashMap<String, T>) -> Option<&String> {
+    PREFERRED_LOCALES
+        .iter()
+        .find_map(|l| localized.get_key_value(*l).map(|(k, _)| k))
+        .or_else(|| localized.keys().min())
+}
+
+/// Formats milliseconds since the epoch as `YYYY-MM-DD`, the way dates appear in `index.xml`.
+pub fn date(millis: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (millis / 1000 / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> Repo {
+        let index: IndexV1 =
+            serde_json::from_str(include_str!("../tests/fixtures/index-v1.json")).unwrap();
+        index.into()
+    }
+
+    #[test]
+    fn repo_is_converted() {
+        let meta = fixture().meta;
+        assert_eq!(meta.name, "F-Droid");
+        assert_eq!(meta.icon, "fdroid-icon.png");
+        assert_eq!(meta.url.as_deref(), Some("https://f-droid.org/repo"));
+        assert_eq!(meta.timestamp, 1_700_000_000);
+        assert_eq!(meta.version, Some(20002));
+        assert_eq!(meta.max_age, "14");
+        assert_eq!(
+            meta.mirrors.unwrap(),
+            [
+                "https://f-droid.org/repo",
+                "https://cloudflare.f-droid.org/repo"
+            ]
+        );
+        // Only the signature of the jar says who signed the index
+        assert!(meta.pub_key.is_empty());
+    }
+
+    #[test]
+    fn apps_are_converted_with_their_packages() {
+        let apps = fixture().apps.unwrap();
+        let [app] = apps.as_slice() else {
+            panic!("expected one app, got {}", apps.len());
+        };
+        assert_eq!(app.id, "org.fdroid.fdroid");
+        assert_eq!(app.name, "F-Droid");
+        assert_eq!(
+            app.summary,
+            "The app store that respects freedom and privacy"
+        );
+        assert_eq!(app.category, Category::System);
+        assert_eq!(app.license, "GPL-3.0-or-later");
+        assert_eq!(
+            app.source.as_deref(),
+            Some("https://gitlab.com/fdroid/fdroidclient")
+        );
+        assert_eq!(app.added, "2011-01-17");
+        assert_eq!(app.market_version, "1.18.0");
+        assert_eq!(app.market_version_code, 1018050);
+
+        let [package] = app.packages.as_slice() else {
+            panic!("expected one package, got {}", app.packages.len());
+        };
+        assert_eq!(package.version, "1.18.0");
+        assert_eq!(package.version_code, 1018050);
+        assert_eq!(package.apk_name, "org.fdroid.fdroid_1018050.apk");
+        assert_eq!(package.hash.kind, "sha256");
+        assert_eq!(package.sdk_version, Some(23));
+        assert_eq!(package.target_sdk_version, Some(33));
+        assert_eq!(
+            package.permissions.as_deref(),
+            Some(["android.permission.INTERNET".to_string()].as_slice())
+        );
+    }
+
+    #[test]
+    fn english_is_preferred() {
+        let localized: HashMap<String, ()> = ["de", "en-GB", "en"]
+            .into_iter()
+            .map(|l| (l.to_string(), ()))
+            .collect();
+        assert_eq!(pick_locale(&localized).unwrap(), "en");
+
+        let localized: HashMap<String, ()> = ["fr", "de"]
+            .into_iter()
+            .map(|l| (l.to_string(), ()))
+            .collect();
+        assert_eq!(pick_locale(&localized).unwrap(), "de");
+    }
+}
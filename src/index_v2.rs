@@ -0,0 +1,448 @@
+//! The index-v2 format: a small signed `entry.jar` pointing at `index-v2.json` and at diffs
+//! against older versions of it, which only need the sha256 from the entry to be trusted.
+//!
+//! Diffs are JSON merge patches (RFC 7386), so the raw index is kept around as a [Value] to
+//! apply the next one against.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    index_v1::{date, pick_locale},
+    repo::{AntiFeature, App, Category, FileHash, Meta, Package, Repo},
+};
+
+/// `entry.json`, the only signed part of the index.
+#[derive(Deserialize)]
+pub struct Entry {
+    /// Milliseconds since the epoch.
+    pub timestamp: u64,
+    pub version: u64,
+    pub index: FileV2,
+    /// Diffs to the current index, keyed by the timestamp of the index they apply to.
+    #[serde(default)]
+    pub diffs: HashMap<String, FileV2>,
+}
+
+#[derive(Deserialize)]
+pub struct FileV2 {
+    /// Path relative to the repo url, with a leading `/`.
+    pub name: String,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+}
+
+type Localized<T> = HashMap<String, T>;
+
+#[derive(Deserialize)]
+pub struct IndexV2 {
+    pub repo: RepoV2,
+    #[serde(default)]
+    pub packages: HashMap<String, PackageV2>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoV2 {
+    #[serde(default)]
+    pub name: Localized<String>,
+    #[serde(default)]
+    pub icon: Localized<FileV2>,
+    pub address: String,
+    #[serde(default)]
+    pub description: Localized<String>,
+    #[serde(default)]
+    pub mirrors: Vec<MirrorV2>,
+    /// Milliseconds since the epoch.
+    pub timestamp: u64,
+    pub max_age: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct MirrorV2 {
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+pub struct PackageV2 {
+    pub metadata: MetadataV2,
+    /// Keyed by the sha256 of the apk.
+    #[serde(default)]
+    pub versions: HashMap<String, VersionV2>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataV2 {
+    pub added: Option<u64>,
+    pub last_updated: Option<u64>,
+    #[serde(default)]
+    pub name: Localized<String>,
+    #[serde(default)]
+    pub summary: Localized<String>,
+    #[serde(default)]
+    pub description: Localized<String>,
+    #[serde(default)]
+    pub icon: Localized<FileV2>,
+    #[serde(default)]
+    pub feature_graphic: Localized<FileV2>,
+    #[serde(default)]
+    pub screenshots: ScreenshotsV2,
+    pub license: Option<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    pub web_site: Option<String>,
+    pub source_code: Option<String>,
+    pub issue_tracker: Option<String>,
+    pub changelog: Option<String>,
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    #[serde(default)]
+    pub donate: Vec<String>,
+    pub bitcoin: Option<String>,
+    pub open_collective: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ScreenshotsV2 {
+    #[serde(default)]
+    pub phone: Localized<Vec<FileV2>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionV2 {
+    pub added: Option<u64>,
+    pub file: FileV2,
+    pub src: Option<FileV2>,
+    pub manifest: ManifestV2,
+    #[serde(default)]
+    pub whats_new: Localized<String>,
+    /// Anti-feature names to the reason they apply.
+    #[serde(default)]
+    pub anti_features: HashMap<String, Localized<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestV2 {
+    pub version_name: Option<String>,
+    pub version_code: u32,
+    pub uses_sdk: Option<UsesSdkV2>,
+    #[serde(default)]
+    pub uses_permission: Vec<NamedV2>,
+    #[serde(rename = "nativecode")]
+    pub native_code: Option<Vec<String>>,
+    pub features: Option<Vec<NamedV2>>,
+    pub signer: Option<SignerV2>,
+}
+
+#[derive(Deserialize)]
+pub struct SignerV2 {
+    /// SHA-256 of the signing certificates.
+    #[serde(default)]
+    pub sha256: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsesSdkV2 {
+    pub min_sdk_version: Option<u32>,
+    pub target_sdk_version: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct NamedV2 {
+    pub name: String,
+}
+
+impl From<IndexV2> for Repo {
+    fn from(index: IndexV2) -> Self {
+        let repo = index.repo;
+        let meta = Meta {
+            icon: localized(&repo.icon)
+                .map(|f| relative(&f.name))
+                .unwrap_or_default(),
+            max_age: repo.max_age.map(|a| a.to_string()).unwrap_or_default(),
+            name: localized(&repo.name).cloned().unwrap_or_default(),
+            pub_key: String::new(),
+            timestamp: repo.timestamp / 1000,
+            url: Some(repo.address),
+            version: None,
+            desc: localized(&repo.description).cloned(),
+            mirrors: (!repo.mirrors.is_empty())
+                .then(|| repo.mirrors.into_iter().map(|m| m.url).collect()),
+            fingerprint: None,
+        };
+
+        let apps = index
+            .packages
+            .into_iter()
+            .map(|(id, package)| package.into_app(id))
+            .collect();
+
+        Self {
+            meta,
+            apps: Some(apps),
+            raw_v2: None,
+            config: Default::default(),
+            status: Default::default(),
+        }
+    }
+}
+
+impl PackageV2 {
+    fn into_app(self, id: String) -> App {
+        let metadata = self.metadata;
+
+        let mut versions: Vec<VersionV2> = self.versions.into_values().collect();
+        versions.sort_by_key(|v| std::cmp::Reverse(v.manifest.version_code));
+        let whats_new = versions
+            .first()
+            .and_then(|v| localized(&v.whats_new).cloned());
+        // Anti-features are per version in index-v2, the newest one is what gets installed
+        let mut anti_features: Vec<AntiFeature> = versions
+            .first()
+            .map(|v| {
+                v.anti_features
+                    .keys()
+                    .map(|a| AntiFeature::from(a.as_str()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        anti_features.sort();
+        let packages: Vec<Package> = versions.into_iter().map(Package::from).collect();
+
+        let categories: Vec<Category> = metadata
+            .categories
+            .iter()
+            .map(|c| Category::from(c.as_str()))
+            .collect();
+
+        App {
+            added: metadata.added.map(date).unwrap_or_default(),
+            last_updated: metadata.last_updated.map(date).unwrap_or_default(),
+            name: localized(&metadata.name)
+                .cloned()
+                .unwrap_or_else(|| id.clone()),
+            summary: localized(&metadata.summary).cloned().unwrap_or_default(),
+            icon: localized(&metadata.icon).map(|f| relative(&f.name)),
+            desc: localized(&metadata.description)
+                .cloned()
+                .unwrap_or_default(),
+            license: metadata.license.unwrap_or_default(),
+            category: categories
+                .first()
+                .cloned()
+                .unwrap_or(Category::Custom(String::new())),
+            categories,
+            anti_features,
+            web: metadata.web_site,
+            source: metadata.source_code,
+            tracker: metadata.issue_tracker,
+            changelog: metadata.changelog,
+            author: metadata.author_name,
+            email: metadata.author_email,
+            donate: metadata.donate.into_iter().next(),
+            bitcoin: metadata.bitcoin,
+            open_collective: metadata.open_collective,
+            market_version: packages
+                .first()
+                .map(|p| p.version.clone())
+                .unwrap_or_default(),
+            market_version_code: packages.first().map(|p| p.version_code).unwrap_or_default(),
+            whats_new,
+            feature_graphic: localized(&metadata.feature_graphic).map(|f| relative(&f.name)),
+            screenshots: localized(&metadata.screenshots.phone)
+                .map(|s| s.iter().map(|f| relative(&f.name)).collect())
+                .unwrap_or_default(),
+            packages,
+            id,
+        }
+    }
+}
+
+impl From<VersionV2> for Package {
+    fn from(version: VersionV2) -> Self {
+        let manifest = version.manifest;
+        let uses_sdk = manifest.uses_sdk.as_ref();
+
+        Self {
+            version: manifest.version_name.unwrap_or_default(),
+            version_code: manifest.version_code,
+            apk_name: relative(&version.file.name),
+            src_name: version.src.map(|f| relative(&f.name)),
+            hash: FileHash::sha256(version.file.sha256.unwrap_or_default()),
+            size: version.file.size.unwrap_or_default() as u32,
+            sdk_version: uses_sdk.and_then(|s| s.min_sdk_version),
+            target_sdk_version: uses_sdk.and_then(|s| s.target_sdk_version),
+            added: version.added.map(date).unwrap_or_default(),
+            sig: None,
+            signer: manifest.signer.and_then(|s| s.sha256.into_iter().next()),
+            permissions: Some(
+                manifest
+                    .uses_permission
+                    .into_iter()
+                    .map(|p| p.name)
+                    .collect(),
+            ),
+            native_code: manifest.native_code,
+            features: manifest
+                .features
+                .map(|f| f.into_iter().map(|f| f.name).collect()),
+        }
+    }
+}
+
+/// Applies a JSON merge patch ([RFC 7386](https://www.rfc-editor.org/rfc/rfc7386)) to `target`.
+pub fn merge(target: &mut Value, patch: Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(Default::default());
+            }
+            let Value::Object(target) = target else {
+                unreachable!()
+            };
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    merge(target.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
+/// The timestamp (in milliseconds) of a raw index-v2, which is what diffs are keyed by.
+pub fn timestamp(raw: &Value) -> Option<u64> {
+    raw.get("repo")?.get("timestamp")?.as_u64()
+}
+
+fn localized<T>(map: &Localized<T>) -> Option<&T> {
+    pick_locale(map).and_then(|l| map.get(l))
+}
+
+fn relative(name: &str) -> String {
+    name.trim_start_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn merged(mut target: Value, patch: Value) -> Value {
+        merge(&mut target, patch);
+        target
+    }
+
+    #[test]
+    fn null_deletes() {
+        assert_eq!(
+            merged(json!({"a": "b", "c": "d"}), json!({"a": null})),
+            json!({"c": "d"})
+        );
+        assert_eq!(
+            merged(json!({"a": "b"}), json!({"x": null})),
+            json!({"a": "b"})
+        );
+    }
+
+    #[test]
+    fn nested_objects_are_merged() {
+        assert_eq!(
+            merged(
+                json!({"a": {"b": "c", "d": {"e": "f"}}, "g": 1}),
+                json!({"a": {"b": "x", "d": {"e": null, "h": "i"}}})
+            ),
+            json!({"a": {"b": "x", "d": {"h": "i"}}, "g": 1})
+        );
+        // Patching into something that isn't an object yet starts from an empty one
+        assert_eq!(
+            merged(json!({"a": "b"}), json!({"a": {"c": "d", "e": null}})),
+            json!({"a": {"c": "d"}})
+        );
+    }
+
+    #[test]
+    fn arrays_are_replaced() {
+        assert_eq!(
+            merged(json!({"a": [1, 2, 3]}), json!({"a": [4]})),
+            json!({"a": [4]})
+        );
+        assert_eq!(
+            merged(json!({"a": [{"b": "c"}]}), json!({"a": [{"d": "e"}]})),
+            json!({"a": [{"d": "e"}]})
+        );
+        assert_eq!(merged(json!({"a": "b"}), json!(["c"])), json!(["c"]));
+    }
+
+    #[test]
+    fn index_is_converted() {
+        let raw: Value =
+            serde_json::from_str(include_str!("../tests/fixtures/index-v2.json")).unwrap();
+        assert_eq!(timestamp(&raw), Some(1_700_000_000_000));
+        let repo: Repo = IndexV2::deserialize(&raw).unwrap().into();
+
+        let meta = repo.meta;
+        assert_eq!(meta.name, "F-Droid");
+        assert_eq!(meta.icon, "icons/fdroid-icon.png");
+        assert_eq!(meta.url.as_deref(), Some("https://f-droid.org/repo"));
+        assert_eq!(meta.timestamp, 1_700_000_000);
+        assert_eq!(meta.max_age, "14");
+        assert_eq!(
+            meta.mirrors.unwrap(),
+            [
+                "https://f-droid.org/repo",
+                "https://cloudflare.f-droid.org/repo"
+            ]
+        );
+
+        let apps = repo.apps.unwrap();
+        let [app] = apps.as_slice() else {
+            panic!("expected one app, got {}", apps.len());
+        };
+        assert_eq!(app.id, "org.fdroid.fdroid");
+        assert_eq!(
+            app.summary,
+            "The app store that respects freedom and privacy"
+        );
+        assert_eq!(
+            app.icon.as_deref(),
+            Some("org.fdroid.fdroid/en-US/icon.png")
+        );
+        assert_eq!(
+            app.screenshots,
+            ["org.fdroid.fdroid/en-US/phoneScreenshots/1.png"]
+        );
+        assert_eq!(app.category, Category::System);
+        assert_eq!(app.donate.as_deref(), Some("https://f-droid.org/donate"));
+        assert_eq!(app.whats_new.as_deref(), Some("Faster syncs"));
+        // Only the anti-features of the newest version count
+        assert!(app.anti_features.is_empty());
+        assert_eq!(app.market_version, "1.18.0");
+        assert_eq!(app.market_version_code, 1018050);
+
+        let versions: Vec<u32> = app.packages.iter().map(|p| p.version_code).collect();
+        assert_eq!(versions, [1018050, 1017050]);
+        let package = &app.packages[0];
+        assert_eq!(package.apk_name, "org.fdroid.fdroid_1018050.apk");
+        assert_eq!(
+            package.hash.value,
+            "8c5f1bcbd7a0c39a2b2e6a1ae4b7c6f0f8a9d3e2b1c0f9e8d7c6b5a4f3e2d1c0"
+        );
+        assert_eq!(package.size, 12345678);
+        assert_eq!(package.sdk_version, Some(23));
+        assert_eq!(
+            package.signer.as_deref(),
+            Some("43238d512c1e5eb2d6569f4a3afbf5523418b82e0a3ed1552770abb9a9c9ccab")
+        );
+        assert_eq!(app.packages[1].sdk_version, Some(22));
+    }
+}
@@ -0,0 +1,1188 @@
+//! Installing downloaded apks through `PackageInstaller` sessions.
+//!
+//! An install goes [InstallState::Verifying] → [InstallState::Writing] →
+//! [InstallState::Committed] → [InstallState::Installed] or [InstallState::Failed]. The
+//! platform part is behind [Installer], the desktop build uses a [FakeInstaller] that installs
+//! into a [FakePackageManager].
+//!
+//! Uninstalling goes [UninstallState::Waiting] → [UninstallState::Uninstalled] or
+//! [UninstallState::Failed].
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use egui::Context;
+use parking_lot::Mutex;
+use thiserror::Error;
+use tokio::{runtime::Handle, sync::Notify};
+
+use crate::{
+    apk,
+    device::Device,
+    download::{self, DownloadError, DownloadKey},
+    installed::{FakePackageManager, Installed, InstalledApp, PackageManager},
+    repo::Package,
+};
+
+/// How often a committed session or an uninstall is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the user has to confirm an install before the session is abandoned.
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(600);
+/// How long the user has to confirm an uninstall before it counts as cancelled.
+const UNINSTALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone)]
+pub enum InstallState {
+    /// Checking the apk against the index, the device and the installed version.
+    Verifying,
+    /// Streaming the apk into the session.
+    Writing {
+        done: u64,
+        total: u64,
+    },
+    /// Waiting for the user to confirm and the system to install it.
+    Committed,
+    Installed,
+    Failed(Arc<InstallError>),
+}
+
+impl InstallState {
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            Self::Verifying | Self::Writing { .. } | Self::Committed
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum UninstallState {
+    /// Waiting for the user to confirm and the system to remove it.
+    Waiting,
+    Uninstalled,
+    Failed(Arc<InstallError>),
+}
+
+#[derive(Error, Debug)]
+pub enum InstallError {
+    #[error("cancelled")]
+    Cancelled,
+    #[error("{0} isn't installed")]
+    NotInstalled(String),
+    /// Android would refuse it as an update of the installed app.
+    #[error("conflicts with the installed app: {0}")]
+    Conflict(String),
+    #[error("incompatible with this device: {0}")]
+    Incompatible(String),
+    #[error(transparent)]
+    Invalid(#[from] DownloadError),
+    /// An install nobody is watching would have to ask the user.
+    #[error("needs you to confirm it")]
+    NeedsConfirmation,
+    /// The system found the apk broken.
+    #[error("the system rejected the apk: {0}")]
+    Rejected(String),
+    #[error("not enough storage: {0}")]
+    Storage(String),
+    /// A policy of the device or the user doesn't allow it.
+    #[error("blocked: {0}")]
+    Blocked(String),
+    #[error("{0:#}")]
+    Other(#[from] anyhow::Error),
+}
+
+/// What became of a committed session, the `STATUS_*` of `PackageInstaller`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionStatus {
+    /// Still being installed.
+    Pending,
+    /// Waiting for [Installer::confirm].
+    PendingUserAction,
+    Success,
+    /// The user declined.
+    Aborted,
+    Blocked(String),
+    Conflict(String),
+    Incompatible(String),
+    Invalid(String),
+    Storage(String),
+    Failure(String),
+}
+
+impl SessionStatus {
+    /// The `EXTRA_STATUS` the system sends for a session or an uninstall, with its
+    /// `EXTRA_STATUS_MESSAGE`.
+    pub fn from_code(code: i32, message: Option<String>) -> Self {
+        let message = message.unwrap_or_default();
+        match code {
+            -1 => Self::PendingUserAction,
+            0 => Self::Success,
+            2 => Self::Blocked(message),
+            3 => Self::Aborted,
+            4 => Self::Invalid(message),
+            5 => Self::Conflict(message),
+            6 => Self::Storage(message),
+            7 => Self::Incompatible(message),
+            // STATUS_FAILURE, STATUS_FAILURE_TIMEOUT and whatever comes after them
+            _ if message.is_empty() => Self::Failure(format!("failed with status {code}")),
+            _ => Self::Failure(message),
+        }
+    }
+
+    /// The error of a failed session, `None` if it didn't fail.
+    fn error(self) -> Option<InstallError> {
+        Some(match self {
+            Self::Pending | Self::PendingUserAction | Self::Success => return None,
+            Self::Aborted => InstallError::Cancelled,
+            Self::Blocked(message) => InstallError::Blocked(message),
+            Self::Conflict(message) => InstallError::Conflict(message),
+            Self::Incompatible(message) => InstallError::Incompatible(message),
+            Self::Invalid(message) => InstallError::Rejected(message),
+            Self::Storage(message) => InstallError::Storage(message),
+            Self::Failure(message) => InstallError::Other(anyhow::anyhow!(message)),
+        })
+    }
+}
+
+pub trait Installer: Send + Sync {
+    /// Whether updates of the apps it installed go in without asking the user.
+    fn unattended(&self) -> bool;
+
+    /// Creates a session for `package` of the app `id` and writes `apk` into it, calling
+    /// `progress` with the bytes written and the size. Returns the id of the session.
+    fn write(
+        &self,
+        id: &str,
+        package: &Package,
+        apk: &Path,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<i32>;
+
+    /// Hands the session over to the system, which may need the user to [Installer::confirm].
+    fn commit(&self, session: i32) -> Result<()>;
+
+    /// Asks the user to confirm a session that is [SessionStatus::PendingUserAction].
+    fn confirm(&self, session: i32) -> Result<()>;
+
+    fn status(&self, session: i32) -> Result<SessionStatus>;
+
+    fn abandon(&self, session: i32) -> Result<()>;
+
+    /// Asks the system to remove the app `id`. Returns the id of the request, its status works
+    /// like the one of a session.
+    fn uninstall(&self, id: &str) -> Result<i32>;
+
+    /// Asks the user to confirm an uninstall that is [SessionStatus::PendingUserAction].
+    fn confirm_uninstall(&self, request: i32) -> Result<()>;
+
+    fn uninstall_status(&self, request: i32) -> Result<SessionStatus>;
+}
+
+/// The `PackageInstaller` of the Android context the app runs in, whose statuses arrive
+/// through [crate::receiver].
+#[cfg(target_os = "android")]
+#[derive(Default)]
+pub struct AndroidInstaller {
+    next_uninstall: AtomicI32,
+}
+
+#[cfg(target_os = "android")]
+impl AndroidInstaller {
+    /// `PackageInstaller.SessionParams.MODE_FULL_INSTALL`.
+    const MODE_FULL_INSTALL: i32 = 1;
+    /// `PackageInstaller.SessionParams.USER_ACTION_NOT_REQUIRED`.
+    const USER_ACTION_NOT_REQUIRED: i32 = 2;
+    /// Android 12, since when sessions can go without the user.
+    const UNATTENDED_SDK: i32 = 31;
+    /// `PackageManager.VERSION_CODE_HIGHEST`, any version of the app.
+    const VERSION_CODE_HIGHEST: i32 = -1;
+    const INSTALL_STATUS: &'static str = "me.avidor.edroid.INSTALL_STATUS";
+    const UNINSTALL_STATUS: &'static str = "me.avidor.edroid.UNINSTALL_STATUS";
+    /// Size of the pieces the apk is streamed into a session in.
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    /// `Build.VERSION.SDK_INT`.
+    fn sdk(env: &mut jni::JNIEnv) -> Result<i32> {
+        Ok(env
+            .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")?
+            .i()?)
+    }
+
+    fn with_installer<T>(
+        f: impl FnOnce(&mut jni::JNIEnv, &jni::objects::JObject) -> Result<T>,
+    ) -> Result<T> {
+        crate::installed::AndroidPackageManager::with_package_manager(|env, package_manager| {
+            let installer = env
+                .call_method(
+                    package_manager,
+                    "getPackageInstaller",
+                    "()Landroid/content/pm/PackageInstaller;",
+                    &[],
+                )?
+                .l()?;
+            f(env, &installer)
+        })
+    }
+
+    fn open_session<'local>(
+        env: &mut jni::JNIEnv<'local>,
+        installer: &jni::objects::JObject,
+        session: i32,
+    ) -> Result<jni::objects::JObject<'local>> {
+        use jni::objects::JValue;
+
+        Ok(env
+            .call_method(
+                installer,
+                "openSession",
+                "(I)Landroid/content/pm/PackageInstaller$Session;",
+                &[JValue::Int(session)],
+            )?
+            .l()?)
+    }
+
+    /// What the system last said about `request`, a final status is only read once.
+    fn read_status(action: &str, request: i32) -> SessionStatus {
+        let Some(status) = crate::receiver::status(action, request) else {
+            return SessionStatus::Pending;
+        };
+        let status = SessionStatus::from_code(status.code, status.message);
+        if status != SessionStatus::PendingUserAction {
+            crate::receiver::forget(action, request);
+        }
+        status
+    }
+
+    /// Starts what the system sent along with `STATUS_PENDING_USER_ACTION` for `request`.
+    fn start_confirmation(action: &str, request: i32) -> Result<()> {
+        use anyhow::Context;
+        use jni::objects::{JObject, JValue};
+
+        let confirm = crate::receiver::status(action, request)
+            .and_then(|s| s.confirm)
+            .with_context(|| format!("nothing to confirm for {action} {request}"))?;
+
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }?;
+        let mut env = vm.attach_current_thread()?;
+        let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+        env.call_method(
+            activity,
+            "startActivity",
+            "(Landroid/content/Intent;)V",
+            &[JValue::Object(confirm.as_obj())],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "android")]
+impl Installer for AndroidInstaller {
+    fn unattended(&self) -> bool {
+        let sdk = || -> Result<i32> {
+            let ctx = ndk_context::android_context();
+            let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }?;
+            let mut env = vm.attach_current_thread()?;
+            Self::sdk(&mut env)
+        };
+        sdk().is_ok_and(|sdk| sdk >= Self::UNATTENDED_SDK)
+    }
+
+    fn write(
+        &self,
+        id: &str,
+        _package: &Package,
+        apk: &Path,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<i32> {
+        use std::{fs::File, io::Read};
+
+        use jni::objects::{JObject, JValue};
+
+        let mut file = File::open(apk)?;
+        let total = file.metadata()?.len();
+
+        Self::with_installer(|env, installer| {
+            let params = env.new_object(
+                "android/content/pm/PackageInstaller$SessionParams",
+                "(I)V",
+                &[JValue::Int(Self::MODE_FULL_INSTALL)],
+            )?;
+            let id = JObject::from(env.new_string(id)?);
+            env.call_method(
+                &params,
+                "setAppPackageName",
+                "(Ljava/lang/String;)V",
+                &[JValue::Object(&id)],
+            )?;
+            env.call_method(&params, "setSize", "(J)V", &[JValue::Long(total as i64)])?;
+            if Self::sdk(env)? >= Self::UNATTENDED_SDK {
+                env.call_method(
+                    &params,
+                    "setRequireUserAction",
+                    "(I)V",
+                    &[JValue::Int(Self::USER_ACTION_NOT_REQUIRED)],
+                )?;
+            }
+            let session_id = env
+                .call_method(
+                    installer,
+                    "createSession",
+                    "(Landroid/content/pm/PackageInstaller$SessionParams;)I",
+                    &[JValue::Object(&params)],
+                )?
+                .i()?;
+
+            let written = (|| -> Result<()> {
+                let session = Self::open_session(env, installer, session_id)?;
+                let name = JObject::from(env.new_string("base.apk")?);
+                let out = env
+                    .call_method(
+                        &session,
+                        "openWrite",
+                        "(Ljava/lang/String;JJ)Ljava/io/OutputStream;",
+                        &[
+                            JValue::Object(&name),
+                            JValue::Long(0),
+                            JValue::Long(total as i64),
+                        ],
+                    )?
+                    .l()?;
+
+                let buffer = env.new_byte_array(Self::CHUNK_SIZE as i32)?;
+                let mut chunk = vec![0u8; Self::CHUNK_SIZE];
+                let mut done = 0;
+                loop {
+                    let read = file.read(&mut chunk)?;
+                    if read == 0 {
+                        break;
+                    }
+                    let bytes: Vec<i8> = chunk[..read].iter().map(|&b| b as i8).collect();
+                    env.set_byte_array_region(&buffer, 0, &bytes)?;
+                    env.call_method(
+                        &out,
+                        "write",
+                        "([BII)V",
+                        &[
+                            JValue::Object(&buffer),
+                            JValue::Int(0),
+                            JValue::Int(read as i32),
+                        ],
+                    )?;
+                    done += read as u64;
+                    progress(done, total);
+                }
+
+                env.call_method(
+                    &session,
+                    "fsync",
+                    "(Ljava/io/OutputStream;)V",
+                    &[JValue::Object(&out)],
+                )?;
+                env.call_method(&out, "close", "()V", &[])?;
+                env.call_method(&session, "close", "()V", &[])?;
+                Ok(())
+            })();
+            if let Err(e) = written {
+                if env.exception_check()? {
+                    env.exception_clear()?;
+                }
+                env.call_method(
+                    installer,
+                    "abandonSession",
+                    "(I)V",
+                    &[JValue::Int(session_id)],
+                )?;
+                return Err(e);
+            }
+
+            Ok(session_id)
+        })
+    }
+
+    fn commit(&self, session: i32) -> Result<()> {
+        use jni::objects::JValue;
+
+        Self::with_installer(|env, installer| {
+            let opened = Self::open_session(env, installer, session)?;
+            let pending = crate::receiver::pending_intent(env, Self::INSTALL_STATUS, session)?;
+            let sender = env
+                .call_method(
+                    &pending,
+                    "getIntentSender",
+                    "()Landroid/content/IntentSender;",
+                    &[],
+                )?
+                .l()?;
+            env.call_method(
+                &opened,
+                "commit",
+                "(Landroid/content/IntentSender;)V",
+                &[JValue::Object(&sender)],
+            )?;
+            env.call_method(&opened, "close", "()V", &[])?;
+            Ok(())
+        })
+    }
+
+    fn confirm(&self, session: i32) -> Result<()> {
+        Self::start_confirmation(Self::INSTALL_STATUS, session)
+    }
+
+    fn status(&self, session: i32) -> Result<SessionStatus> {
+        Ok(Self::read_status(Self::INSTALL_STATUS, session))
+    }
+
+    fn abandon(&self, session: i32) -> Result<()> {
+        use jni::objects::JValue;
+
+        crate::receiver::forget(Self::INSTALL_STATUS, session);
+        Self::with_installer(|env, installer| {
+            env.call_method(installer, "abandonSession", "(I)V", &[JValue::Int(session)])?;
+            Ok(())
+        })
+    }
+
+    fn uninstall(&self, id: &str) -> Result<i32> {
+        use jni::objects::{JObject, JValue};
+
+        let request = self.next_uninstall.fetch_add(1, Ordering::Relaxed);
+        Self::with_installer(|env, installer| {
+            let pending = crate::receiver::pending_intent(env, Self::UNINSTALL_STATUS, request)?;
+            let sender = env
+                .call_method(
+                    &pending,
+                    "getIntentSender",
+                    "()Landroid/content/IntentSender;",
+                    &[],
+                )?
+                .l()?;
+            let name = JObject::from(env.new_string(id)?);
+            // VersionedPackage is there since Android 8
+            if Self::sdk(env)? >= 26 {
+                let package = env.new_object(
+                    "android/content/pm/VersionedPackage",
+                    "(Ljava/lang/String;I)V",
+                    &[
+                        JValue::Object(&name),
+                        JValue::Int(Self::VERSION_CODE_HIGHEST),
+                    ],
+                )?;
+                env.call_method(
+                    installer,
+                    "uninstall",
+                    "(Landroid/content/pm/VersionedPackage;Landroid/content/IntentSender;)V",
+                    &[JValue::Object(&package), JValue::Object(&sender)],
+                )?;
+            } else {
+                env.call_method(
+                    installer,
+                    "uninstall",
+                    "(Ljava/lang/String;Landroid/content/IntentSender;)V",
+                    &[JValue::Object(&name), JValue::Object(&sender)],
+                )?;
+            }
+            Ok(())
+        })?;
+        Ok(request)
+    }
+
+    fn confirm_uninstall(&self, request: i32) -> Result<()> {
+        Self::start_confirmation(Self::UNINSTALL_STATUS, request)
+    }
+
+    fn uninstall_status(&self, request: i32) -> Result<SessionStatus> {
+        Ok(Self::read_status(Self::UNINSTALL_STATUS, request))
+    }
+}
+
+/// Installs right away into a [FakePackageManager], for platforms without a PackageInstaller.
+pub struct FakeInstaller {
+    package_manager: Arc<FakePackageManager>,
+    next_session: AtomicI32,
+    /// Every session with what it installs.
+    sessions: Mutex<HashMap<i32, (InstalledApp, SessionStatus)>>,
+    /// Every uninstall with the app it removes.
+    uninstalls: Mutex<HashMap<i32, (String, SessionStatus)>>,
+    /// Makes the user cancel every install and uninstall.
+    pub cancel: AtomicBool,
+    /// Makes every install and uninstall wait for the user to confirm it.
+    pub ask: AtomicBool,
+    /// What the system says to every install instead of doing it.
+    pub fail: Mutex<Option<SessionStatus>>,
+}
+
+impl FakeInstaller {
+    pub fn new(package_manager: Arc<FakePackageManager>) -> Self {
+        Self {
+            package_manager,
+            next_session: AtomicI32::new(1),
+            sessions: Default::default(),
+            uninstalls: Default::default(),
+            cancel: AtomicBool::new(false),
+            ask: AtomicBool::new(false),
+            fail: Mutex::new(None),
+        }
+    }
+
+    fn finish(&self, session: i32) -> Result<()> {
+        let mut sessions = self.sessions.lock();
+        let (app, status) = sessions
+            .get_mut(&session)
+            .ok_or_else(|| anyhow::anyhow!("no session {session}"))?;
+        *status = if self.cancel.load(Ordering::Relaxed) {
+            SessionStatus::Aborted
+        } else if let Some(failed) = self.fail.lock().clone() {
+            failed
+        } else {
+            let mut installed = self.package_manager.apps.lock();
+            installed.retain(|a| a.id != app.id);
+            installed.push(app.clone());
+            SessionStatus::Success
+        };
+        Ok(())
+    }
+
+    fn finish_uninstall(&self, request: i32) -> Result<()> {
+        let mut uninstalls = self.uninstalls.lock();
+        let (id, status) = uninstalls
+            .get_mut(&request)
+            .ok_or_else(|| anyhow::anyhow!("no uninstall {request}"))?;
+        *status = if self.cancel.load(Ordering::Relaxed) {
+            SessionStatus::Aborted
+        } else {
+            self.package_manager.apps.lock().retain(|a| a.id != *id);
+            SessionStatus::Success
+        };
+        Ok(())
+    }
+}
+
+impl Installer for FakeInstaller {
+    fn unattended(&self) -> bool {
+        true
+    }
+
+    fn write(
+        &self,
+        id: &str,
+        package: &Package,
+        apk: &Path,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<i32> {
+        let total = std::fs::metadata(apk)?.len();
+        progress(total, total);
+
+        let session = self.next_session.fetch_add(1, Ordering::Relaxed);
+        let app = InstalledApp {
+            id: id.to_string(),
+            version_code: package.version_code,
+            version_name: package.version.clone(),
+            signer: apk::signer(apk).ok(),
+        };
+        self.sessions
+            .lock()
+            .insert(session, (app, SessionStatus::Pending));
+        Ok(session)
+    }
+
+    fn commit(&self, session: i32) -> Result<()> {
+        if self.ask.load(Ordering::Relaxed) {
+            let mut sessions = self.sessions.lock();
+            let (_, status) = sessions
+                .get_mut(&session)
+                .ok_or_else(|| anyhow::anyhow!("no session {session}"))?;
+            *status = SessionStatus::PendingUserAction;
+            return Ok(());
+        }
+        self.finish(session)
+    }
+
+    fn confirm(&self, session: i32) -> Result<()> {
+        self.finish(session)
+    }
+
+    fn status(&self, session: i32) -> Result<SessionStatus> {
+        self.sessions
+            .lock()
+            .get(&session)
+            .map(|(_, status)| status.clone())
+            .ok_or_else(|| anyhow::anyhow!("no session {session}"))
+    }
+
+    fn abandon(&self, session: i32) -> Result<()> {
+        self.sessions.lock().remove(&session);
+        Ok(())
+    }
+
+    fn uninstall(&self, id: &str) -> Result<i32> {
+        let request = self.next_session.fetch_add(1, Ordering::Relaxed);
+        let status = if self.ask.load(Ordering::Relaxed) {
+            SessionStatus::PendingUserAction
+        } else {
+            SessionStatus::Pending
+        };
+        self.uninstalls
+            .lock()
+            .insert(request, (id.to_string(), status));
+        if !self.ask.load(Ordering::Relaxed) {
+            self.finish_uninstall(request)?;
+        }
+        Ok(request)
+    }
+
+    fn confirm_uninstall(&self, request: i32) -> Result<()> {
+        self.finish_uninstall(request)
+    }
+
+    fn uninstall_status(&self, request: i32) -> Result<SessionStatus> {
+        self.uninstalls
+            .lock()
+            .get(&request)
+            .map(|(_, status)| status.clone())
+            .ok_or_else(|| anyhow::anyhow!("no uninstall {request}"))
+    }
+}
+
+/// The PackageManager and installer of the platform.
+pub fn platform() -> (Arc<dyn PackageManager>, Arc<dyn Installer>) {
+    #[cfg(target_os = "android")]
+    return (
+        Arc::new(crate::installed::AndroidPackageManager),
+        Arc::new(AndroidInstaller::default()),
+    );
+
+    #[cfg(not(target_os = "android"))]
+    {
+        let package_manager = Arc::new(FakePackageManager::default());
+        (
+            package_manager.clone(),
+            Arc::new(FakeInstaller::new(package_manager)),
+        )
+    }
+}
+
+/// Installs `apk` of `package` as the app `id`, reporting every step to `set_state`. An
+/// `unattended` install gives up instead of asking the user to confirm it.
+#[allow(clippy::too_many_arguments)]
+pub fn install(
+    installer: &dyn Installer,
+    package_manager: &dyn PackageManager,
+    device: &Device,
+    id: &str,
+    package: &Package,
+    apk: &Path,
+    unattended: bool,
+    set_state: &mut dyn FnMut(InstallState),
+) -> Result<(), InstallError> {
+    set_state(InstallState::Verifying);
+    download::verify(apk, package)?;
+    if let Some(problem) = device.check(package).first() {
+        return Err(InstallError::Incompatible(problem.to_string()));
+    }
+    if let Some(installed) = package_manager.installed_app(id)? {
+        if installed.version_code > package.version_code {
+            return Err(InstallError::Conflict(format!(
+                "version {} is installed, downgrades aren't possible",
+                installed.version_name
+            )));
+        }
+        if let Some(cert) = &installed.signer {
+            if *cert != apk::signer(apk)? {
+                return Err(InstallError::Conflict(
+                    "it's signed with a different certificate".to_string(),
+                ));
+            }
+        }
+    }
+
+    let session = installer.write(id, package, apk, &mut |done, total| {
+        set_state(InstallState::Writing { done, total })
+    })?;
+    if let Err(e) = installer.commit(session) {
+        installer.abandon(session)?;
+        return Err(e.into());
+    }
+    set_state(InstallState::Committed);
+
+    let start = Instant::now();
+    let mut asked = false;
+    loop {
+        let status = installer.status(session)?;
+        if let Some(e) = status.clone().error() {
+            return Err(e);
+        }
+        match status {
+            SessionStatus::Success => return Ok(()),
+            SessionStatus::PendingUserAction if unattended => {
+                installer.abandon(session)?;
+                return Err(InstallError::NeedsConfirmation);
+            }
+            SessionStatus::PendingUserAction if !asked => {
+                installer.confirm(session)?;
+                asked = true;
+            }
+            _ => {}
+        }
+        if start.elapsed() > INSTALL_TIMEOUT {
+            installer.abandon(session)?;
+            return Err(InstallError::Cancelled);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Removes the app `id`, reporting every step to `set_state`.
+pub fn uninstall(
+    installer: &dyn Installer,
+    package_manager: &dyn PackageManager,
+    id: &str,
+    set_state: &mut dyn FnMut(UninstallState),
+) -> Result<(), InstallError> {
+    if package_manager.installed_app(id)?.is_none() {
+        return Err(InstallError::NotInstalled(id.to_string()));
+    }
+    let request = installer.uninstall(id)?;
+    set_state(UninstallState::Waiting);
+
+    let start = Instant::now();
+    let mut asked = false;
+    loop {
+        let status = installer.uninstall_status(request)?;
+        if let Some(e) = status.clone().error() {
+            return Err(e);
+        }
+        match status {
+            SessionStatus::Success => return Ok(()),
+            SessionStatus::PendingUserAction if !asked => {
+                installer.confirm_uninstall(request)?;
+                asked = true;
+            }
+            _ => {}
+        }
+        // An uninstall can't be called off, the user may still confirm it after this
+        if start.elapsed() > UNINSTALL_TIMEOUT {
+            return Err(InstallError::Cancelled);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Installs keyed by the download of the apk and uninstalls keyed by app id.
+pub struct Installs {
+    installer: Arc<dyn Installer>,
+    installed: Installed,
+    rt: Handle,
+    states: Arc<Mutex<HashMap<DownloadKey, InstallState>>>,
+    uninstalls: Arc<Mutex<HashMap<String, UninstallState>>>,
+    /// Signalled whenever an install or uninstall changes.
+    changed: Arc<Notify>,
+}
+
+impl Installs {
+    pub fn new(
+        installer: Arc<dyn Installer>,
+        installed: Installed,
+        rt: Handle,
+        changed: Arc<Notify>,
+    ) -> Self {
+        Self {
+            installer,
+            installed,
+            rt,
+            states: Default::default(),
+            uninstalls: Default::default(),
+            changed,
+        }
+    }
+
+    /// Installs the downloaded `apk` of `package` as the app `id` in the background, the
+    /// installed apps are read again before it's done. An `unattended` install fails with
+    /// [InstallError::NeedsConfirmation] rather than interrupt the user.
+    #[allow(clippy::too_many_arguments)]
+    pub fn start(
+        &self,
+        key: DownloadKey,
+        id: &str,
+        package: &Package,
+        apk: PathBuf,
+        device: &Device,
+        unattended: bool,
+        ctx: &Context,
+    ) {
+        {
+            let mut states = self.states.lock();
+            if states.get(&key).is_some_and(InstallState::is_active) {
+                return;
+            }
+            states.insert(key.clone(), InstallState::Verifying);
+        }
+
+        let installer = self.installer.clone();
+        let installed = self.installed.clone();
+        let states = self.states.clone();
+        let changed = self.changed.clone();
+        let (id, package, device, ctx) =
+            (id.to_string(), package.clone(), device.clone(), ctx.clone());
+        self.rt.spawn_blocking(move || {
+            let mut set_state = |state| {
+                states.lock().insert(key.clone(), state);
+                changed.notify_one();
+                ctx.request_repaint();
+            };
+            let result = install(
+                installer.as_ref(),
+                installed.manager().as_ref(),
+                &device,
+                &id,
+                &package,
+                &apk,
+                unattended,
+                &mut set_state,
+            );
+            installed.reload();
+            match result {
+                Ok(()) => set_state(InstallState::Installed),
+                Err(e) => {
+                    log::error!("{id}: {e}");
+                    set_state(InstallState::Failed(Arc::new(e)));
+                }
+            }
+        });
+    }
+
+    pub fn state(&self, key: &DownloadKey) -> Option<InstallState> {
+        self.states.lock().get(key).cloned()
+    }
+
+    /// Whether [Installs::start] can install updates without asking the user.
+    pub fn unattended(&self) -> bool {
+        self.installer.unattended()
+    }
+
+    pub fn active(&self) -> usize {
+        self.states
+            .lock()
+            .values()
+            .filter(|s| s.is_active())
+            .count()
+    }
+
+    /// Uninstalls the app `id` in the background, the installed apps are read again before it's
+    /// done.
+    pub fn uninstall(&self, id: &str, ctx: &Context) {
+        {
+            let mut uninstalls = self.uninstalls.lock();
+            if matches!(uninstalls.get(id), Some(UninstallState::Waiting)) {
+                return;
+            }
+            uninstalls.insert(id.to_string(), UninstallState::Waiting);
+        }
+
+        let installer = self.installer.clone();
+        let installed = self.installed.clone();
+        let uninstalls = self.uninstalls.clone();
+        let changed = self.changed.clone();
+        let (id, ctx) = (id.to_string(), ctx.clone());
+        self.rt.spawn_blocking(move || {
+            let mut set_state = |state| {
+                uninstalls.lock().insert(id.clone(), state);
+                changed.notify_one();
+                ctx.request_repaint();
+            };
+            let result = uninstall(
+                installer.as_ref(),
+                installed.manager().as_ref(),
+                &id,
+                &mut set_state,
+            );
+            installed.reload();
+            match result {
+                Ok(()) => set_state(UninstallState::Uninstalled),
+                Err(e) => {
+                    log::error!("{id}: can't uninstall, {e}");
+                    set_state(UninstallState::Failed(Arc::new(e)));
+                }
+            }
+        });
+    }
+
+    pub fn uninstall_state(&self, id: &str) -> Option<UninstallState> {
+        self.uninstalls.lock().get(id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::repo::FileHash;
+
+    const SIGNER: &str = "8f59aaa0c814d58c32496e513861afb1f22add89a403e1fb4caf7d9991001c2c";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn package(version_code: u32) -> Package {
+        let apk = std::fs::read(fixture("app-v2.apk")).unwrap();
+        Package {
+            version: format!("1.{version_code}"),
+            version_code,
+            apk_name: "app.apk".to_string(),
+            src_name: None,
+            hash: FileHash {
+                kind: "sha256".to_string(),
+                value: hex::encode(Sha256::digest(&apk)),
+            },
+            size: apk.len() as u32,
+            sdk_version: None,
+            target_sdk_version: None,
+            added: String::new(),
+            sig: None,
+            signer: Some(SIGNER.to_string()),
+            permissions: None,
+            native_code: None,
+            features: None,
+        }
+    }
+
+    struct Fakes {
+        package_manager: Arc<FakePackageManager>,
+        installer: FakeInstaller,
+    }
+
+    impl Fakes {
+        /// With `installed` as the installed version of the app, signed like `apk`.
+        fn new(installed: Option<(u32, &str)>) -> Self {
+            let package_manager = Arc::new(FakePackageManager::default());
+            if let Some((version_code, apk)) = installed {
+                package_manager.apps.lock().push(InstalledApp {
+                    id: "org.example.app".to_string(),
+                    version_code,
+                    version_name: format!("1.{version_code}"),
+                    signer: Some(apk::signer(&fixture(apk)).unwrap()),
+                });
+            }
+            let installer = FakeInstaller::new(package_manager.clone());
+            Self {
+                package_manager,
+                installer,
+            }
+        }
+
+        fn install(&self, package: &Package, apk: &str) -> (Result<(), InstallError>, Vec<String>) {
+            let mut states = Vec::new();
+            let result = install(
+                &self.installer,
+                self.package_manager.as_ref(),
+                &Device::default(),
+                "org.example.app",
+                package,
+                &fixture(apk),
+                false,
+                &mut |state| states.push(format!("{state:?}")),
+            );
+            (result, states)
+        }
+
+        fn installed_version(&self) -> Option<u32> {
+            self.package_manager
+                .installed_app("org.example.app")
+                .unwrap()
+                .map(|app| app.version_code)
+        }
+    }
+
+    #[test]
+    fn apks_are_installed() {
+        let fakes = Fakes::new(None);
+        let (result, states) = fakes.install(&package(2), "app-v2.apk");
+        result.unwrap();
+        assert_eq!(fakes.installed_version(), Some(2));
+        assert_eq!(states.first().unwrap(), "Verifying");
+        assert!(states.iter().any(|s| s.starts_with("Writing")));
+        assert_eq!(states.last().unwrap(), "Committed");
+    }
+
+    #[test]
+    fn updates_are_installed_after_the_user_confirms() {
+        let fakes = Fakes::new(Some((1, "app-v2.apk")));
+        fakes.installer.ask.store(true, Ordering::Relaxed);
+        fakes.install(&package(2), "app-v2.apk").0.unwrap();
+        assert_eq!(fakes.installed_version(), Some(2));
+    }
+
+    #[test]
+    fn unattended_installs_never_ask() {
+        let fakes = Fakes::new(Some((1, "app-v2.apk")));
+        let install = |fakes: &Fakes| {
+            install(
+                &fakes.installer,
+                fakes.package_manager.as_ref(),
+                &Device::default(),
+                "org.example.app",
+                &package(2),
+                &fixture("app-v2.apk"),
+                true,
+                &mut |_| {},
+            )
+        };
+        fakes.installer.ask.store(true, Ordering::Relaxed);
+        let result = install(&fakes);
+        assert!(
+            matches!(result, Err(InstallError::NeedsConfirmation)),
+            "{result:?}"
+        );
+        assert_eq!(fakes.installed_version(), Some(1));
+        assert!(fakes.installer.sessions.lock().is_empty());
+
+        fakes.installer.ask.store(false, Ordering::Relaxed);
+        install(&fakes).unwrap();
+        assert_eq!(fakes.installed_version(), Some(2));
+    }
+
+    #[test]
+    fn cancelled_installs_change_nothing() {
+        let fakes = Fakes::new(Some((1, "app-v2.apk")));
+        fakes.installer.cancel.store(true, Ordering::Relaxed);
+        let (result, _) = fakes.install(&package(2), "app-v2.apk");
+        assert!(matches!(result, Err(InstallError::Cancelled)), "{result:?}");
+        assert_eq!(fakes.installed_version(), Some(1));
+    }
+
+    #[test]
+    fn downgrades_conflict() {
+        let fakes = Fakes::new(Some((3, "app-v2.apk")));
+        let (result, states) = fakes.install(&package(2), "app-v2.apk");
+        assert!(
+            matches!(result, Err(InstallError::Conflict(_))),
+            "{result:?}"
+        );
+        assert_eq!(states, ["Verifying"]);
+        assert_eq!(fakes.installed_version(), Some(3));
+    }
+
+    #[test]
+    fn other_signers_conflict() {
+        let fakes = Fakes::new(Some((1, "app-other-signer.apk")));
+        let (result, _) = fakes.install(&package(2), "app-v2.apk");
+        assert!(
+            matches!(result, Err(InstallError::Conflict(_))),
+            "{result:?}"
+        );
+        assert_eq!(fakes.installed_version(), Some(1));
+    }
+
+    #[test]
+    fn incompatible_apks_are_refused() {
+        let fakes = Fakes::new(None);
+        let mut package = package(2);
+        package.sdk_version = Some(99);
+        let (result, _) = fakes.install(&package, "app-v2.apk");
+        assert!(
+            matches!(result, Err(InstallError::Incompatible(_))),
+            "{result:?}"
+        );
+        assert_eq!(fakes.installed_version(), None);
+    }
+
+    #[test]
+    fn apks_not_matching_the_index_are_refused() {
+        let fakes = Fakes::new(None);
+        let (result, _) = fakes.install(&package(2), "app-other-signer.apk");
+        assert!(
+            matches!(
+                result,
+                Err(InstallError::Invalid(DownloadError::HashMismatch { .. }))
+            ),
+            "{result:?}"
+        );
+        assert_eq!(fakes.installed_version(), None);
+    }
+
+    fn uninstall(fakes: &Fakes) -> Result<(), InstallError> {
+        super::uninstall(
+            &fakes.installer,
+            fakes.package_manager.as_ref(),
+            "org.example.app",
+            &mut |_| {},
+        )
+    }
+
+    #[test]
+    fn apps_are_uninstalled_after_the_user_confirms() {
+        let fakes = Fakes::new(Some((1, "app-v2.apk")));
+        uninstall(&fakes).unwrap();
+        assert_eq!(fakes.installed_version(), None);
+
+        let fakes = Fakes::new(Some((1, "app-v2.apk")));
+        fakes.installer.ask.store(true, Ordering::Relaxed);
+        uninstall(&fakes).unwrap();
+        assert_eq!(fakes.installed_version(), None);
+    }
+
+    #[test]
+    fn cancelled_uninstalls_keep_the_app() {
+        let fakes = Fakes::new(Some((1, "app-v2.apk")));
+        fakes.installer.ask.store(true, Ordering::Relaxed);
+        fakes.installer.cancel.store(true, Ordering::Relaxed);
+        let result = uninstall(&fakes);
+        assert!(matches!(result, Err(InstallError::Cancelled)), "{result:?}");
+        assert_eq!(fakes.installed_version(), Some(1));
+
+        let result = uninstall(&Fakes::new(None));
+        assert!(
+            matches!(result, Err(InstallError::NotInstalled(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn statuses_are_read_from_their_codes() {
+        use SessionStatus::*;
+
+        let why = || Some("why".to_string());
+        assert_eq!(SessionStatus::from_code(-1, None), PendingUserAction);
+        assert_eq!(SessionStatus::from_code(0, None), Success);
+        assert_eq!(SessionStatus::from_code(3, why()), Aborted);
+        for (code, status) in [
+            (2, Blocked("why".to_string())),
+            (4, Invalid("why".to_string())),
+            (5, Conflict("why".to_string())),
+            (6, Storage("why".to_string())),
+            (7, Incompatible("why".to_string())),
+            (8, Failure("why".to_string())),
+        ] {
+            assert_eq!(SessionStatus::from_code(code, why()), status);
+        }
+        assert_eq!(
+            SessionStatus::from_code(1, None),
+            Failure("failed with status 1".to_string())
+        );
+    }
+
+    #[test]
+    fn failed_sessions_report_why() {
+        let fakes = Fakes::new(None);
+        *fakes.installer.fail.lock() = Some(SessionStatus::Storage("full".to_string()));
+        let (result, _) = fakes.install(&package(2), "app-v2.apk");
+        assert!(
+            matches!(result, Err(InstallError::Storage(_))),
+            "{result:?}"
+        );
+        assert_eq!(fakes.installed_version(), None);
+    }
+}
@@ -0,0 +1,311 @@
+//! Apps installed on the device, from the Android PackageManager.
+//!
+//! The PackageManager is behind [PackageManager] so everything else works, and can be tried
+//! out, on desktop with a [FakePackageManager], see [crate::install::platform].
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use egui::Context;
+use parking_lot::{Mutex, MutexGuard};
+use sha2::{Digest, Sha256};
+use tokio::runtime::Handle;
+
+use crate::{apk, repo::Package};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstalledApp {
+    pub id: String,
+    pub version_code: u32,
+    pub version_name: String,
+    /// The certificate the app is signed with.
+    pub signer: Option<Vec<u8>>,
+}
+
+impl InstalledApp {
+    /// Whether `package` is signed like the installed app, so Android will take it as an update.
+    /// `None` if either signer is unknown.
+    pub fn same_signer(&self, package: &Package) -> Option<bool> {
+        let cert = self.signer.as_ref()?;
+        if let Some(signer) = &package.signer {
+            return Some(hex::encode(Sha256::digest(cert)) == signer.to_ascii_lowercase());
+        }
+        let sig = package.sig.as_ref()?;
+        Some(apk::fdroid_sig(cert) == sig.to_ascii_lowercase())
+    }
+}
+
+pub trait PackageManager: Send + Sync {
+    /// Every app installed for the user.
+    fn installed(&self) -> Result<Vec<InstalledApp>>;
+
+    /// The app `id`, `None` if it isn't installed.
+    fn installed_app(&self, id: &str) -> Result<Option<InstalledApp>>;
+}
+
+/// The PackageManager of the Android context the app runs in.
+#[cfg(target_os = "android")]
+pub struct AndroidPackageManager;
+
+#[cfg(target_os = "android")]
+impl AndroidPackageManager {
+    /// `PackageManager.GET_SIGNATURES`.
+    const GET_SIGNATURES: i32 = 0x40;
+
+    pub(crate) fn with_package_manager<T>(
+        f: impl FnOnce(&mut jni::JNIEnv, &jni::objects::JObject) -> Result<T>,
+    ) -> Result<T> {
+        use jni::objects::JObject;
+
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }?;
+        let mut env = vm.attach_current_thread()?;
+
+        let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+        let package_manager = env
+            .call_method(
+                activity,
+                "getPackageManager",
+                "()Landroid/content/pm/PackageManager;",
+                &[],
+            )?
+            .l()?;
+        f(&mut env, &package_manager)
+    }
+
+    fn read_info(env: &mut jni::JNIEnv, info: &jni::objects::JObject) -> Result<InstalledApp> {
+        use jni::objects::{JByteArray, JObjectArray, JString};
+
+        let id = JString::from(
+            env.get_field(info, "packageName", "Ljava/lang/String;")?
+                .l()?,
+        );
+        let id: String = env.get_string(&id)?.into();
+        let version_code = env.get_field(info, "versionCode", "I")?.i()?;
+        let version_name = JString::from(
+            env.get_field(info, "versionName", "Ljava/lang/String;")?
+                .l()?,
+        );
+        let version_name = if version_name.is_null() {
+            String::new()
+        } else {
+            env.get_string(&version_name)?.into()
+        };
+
+        let signatures = JObjectArray::from(
+            env.get_field(info, "signatures", "[Landroid/content/pm/Signature;")?
+                .l()?,
+        );
+        let signer = if signatures.is_null() || env.get_array_length(&signatures)? == 0 {
+            None
+        } else {
+            let signature = env.get_object_array_element(&signatures, 0)?;
+            let cert = JByteArray::from(
+                env.call_method(signature, "toByteArray", "()[B", &[])?
+                    .l()?,
+            );
+            Some(env.convert_byte_array(cert)?)
+        };
+
+        Ok(InstalledApp {
+            id,
+            version_code: version_code as u32,
+            version_name,
+            signer,
+        })
+    }
+}
+
+#[cfg(target_os = "android")]
+impl PackageManager for AndroidPackageManager {
+    fn installed(&self) -> Result<Vec<InstalledApp>> {
+        use jni::objects::JValue;
+
+        Self::with_package_manager(|env, package_manager| {
+            let list = env
+                .call_method(
+                    package_manager,
+                    "getInstalledPackages",
+                    "(I)Ljava/util/List;",
+                    &[JValue::Int(Self::GET_SIGNATURES)],
+                )?
+                .l()?;
+            let len = env.call_method(&list, "size", "()I", &[])?.i()?;
+
+            let mut apps = Vec::with_capacity(len as usize);
+            for i in 0..len {
+                // The list can be long, don't run out of local references
+                let app = env.with_local_frame(16, |env| -> Result<_> {
+                    let info = env
+                        .call_method(&list, "get", "(I)Ljava/lang/Object;", &[JValue::Int(i)])?
+                        .l()?;
+                    Self::read_info(env, &info)
+                })?;
+                apps.push(app);
+            }
+            Ok(apps)
+        })
+    }
+
+    fn installed_app(&self, id: &str) -> Result<Option<InstalledApp>> {
+        use jni::objects::{JObject, JValue};
+
+        Self::with_package_manager(|env, package_manager| {
+            let id = env.new_string(id)?;
+            let info = match env.call_method(
+                package_manager,
+                "getPackageInfo",
+                "(Ljava/lang/String;I)Landroid/content/pm/PackageInfo;",
+                &[
+                    JValue::Object(&JObject::from(id)),
+                    JValue::Int(Self::GET_SIGNATURES),
+                ],
+            ) {
+                // NameNotFoundException
+                Err(jni::errors::Error::JavaException) => {
+                    env.exception_clear()?;
+                    return Ok(None);
+                }
+                info => info?.l()?,
+            };
+            Ok(Some(Self::read_info(env, &info)?))
+        })
+    }
+}
+
+/// A PackageManager with a made up list of apps, for platforms without one.
+#[derive(Default)]
+pub struct FakePackageManager {
+    pub apps: Mutex<Vec<InstalledApp>>,
+}
+
+impl PackageManager for FakePackageManager {
+    fn installed(&self) -> Result<Vec<InstalledApp>> {
+        Ok(self.apps.lock().clone())
+    }
+
+    fn installed_app(&self, id: &str) -> Result<Option<InstalledApp>> {
+        Ok(self.apps.lock().iter().find(|a| a.id == id).cloned())
+    }
+}
+
+/// The installed apps keyed by id, read again with [Installed::refresh].
+#[derive(Clone)]
+pub struct Installed {
+    manager: Arc<dyn PackageManager>,
+    apps: Arc<Mutex<HashMap<String, InstalledApp>>>,
+    /// Goes up every time the apps were read.
+    version: Arc<AtomicU64>,
+}
+
+impl Installed {
+    pub fn new(manager: Arc<dyn PackageManager>) -> Self {
+        Self {
+            manager,
+            apps: Default::default(),
+            version: Default::default(),
+        }
+    }
+
+    pub fn manager(&self) -> &Arc<dyn PackageManager> {
+        &self.manager
+    }
+
+    /// Reads the installed apps again in the background.
+    pub fn refresh(&self, rt: &Handle, ctx: &Context) {
+        let installed = self.clone();
+        let ctx = ctx.clone();
+        rt.spawn_blocking(move || {
+            installed.reload();
+            ctx.request_repaint();
+        });
+    }
+
+    /// Reads the installed apps again, blocking.
+    pub fn reload(&self) {
+        match self.manager.installed() {
+            Ok(installed) => {
+                *self.apps.lock() = installed.into_iter().map(|a| (a.id.clone(), a)).collect();
+                self.version.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => log::error!("can't list the installed apps: {e:#}"),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    pub fn get(&self, id: &str) -> Option<InstalledApp> {
+        self.apps.lock().get(id).cloned()
+    }
+
+    pub fn apps(&self) -> MutexGuard<'_, HashMap<String, InstalledApp>> {
+        self.apps.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{index_v1::IndexV1, repo::Repo};
+
+    fn package() -> Package {
+        let index: IndexV1 =
+            serde_json::from_str(include_str!("../tests/fixtures/index-v1.json")).unwrap();
+        let mut package = Repo::from(index).apps.unwrap().remove(0).packages.remove(0);
+        package.sig = None;
+        package
+    }
+
+    fn installed(signer: Option<Vec<u8>>) -> InstalledApp {
+        InstalledApp {
+            id: "org.fdroid.fdroid".to_string(),
+            version_code: 1,
+            version_name: "1".to_string(),
+            signer,
+        }
+    }
+
+    #[test]
+    fn signers_are_compared_by_sha256_or_fdroid_sig() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/app-v2.apk");
+        let cert = apk::signer(&fixture).unwrap();
+        let app = installed(Some(cert.clone()));
+
+        let by_signer = Package {
+            signer: Some(hex::encode_upper(Sha256::digest(&cert))),
+            ..package()
+        };
+        assert_eq!(app.same_signer(&by_signer), Some(true));
+        let by_sig = Package {
+            sig: Some(apk::fdroid_sig(&cert)),
+            ..package()
+        };
+        assert_eq!(app.same_signer(&by_sig), Some(true));
+
+        let other = installed(Some(b"another certificate".to_vec()));
+        assert_eq!(other.same_signer(&by_signer), Some(false));
+        assert_eq!(other.same_signer(&by_sig), Some(false));
+    }
+
+    #[test]
+    fn unknown_signers_are_neither_same_nor_other() {
+        let cert = b"certificate".to_vec();
+        assert_eq!(installed(Some(cert)).same_signer(&package()), None);
+
+        let by_signer = Package {
+            signer: Some(hex::encode(Sha256::digest(b"certificate"))),
+            ..package()
+        };
+        assert_eq!(installed(None).same_signer(&by_signer), None);
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek},
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use cms::{cert::CertificateChoices, content_info::ContentInfo, signed_data::SignedData};
+use der::{
+    asn1::{ObjectIdentifier, OctetString},
+    Decode, Encode,
+};
+use itertools::Itertools;
+use rsa::{pkcs8::DecodePublicKey, Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use zip::ZipArchive;
+
+const OID_SHA1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+const OID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const OID_SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.3");
+const OID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+
+/// A file read from a jar whose v1 (JAR) signature has been checked.
+pub struct SignedEntry {
+    pub data: Vec<u8>,
+    /// DER encoded X.509 certificate of the signer.
+    pub signer: Vec<u8>,
+}
+
+/// Reads `name` from a signed jar, checking the whole chain from the signature block
+/// (`META-INF/*.RSA`) over the signature file (`META-INF/*.SF`) and the manifest down
+/// to the digest of the entry itself.
+///
+/// This doesn't say anything about *who* signed the jar, compare [SignedEntry::signer]
+/// against a pinned certificate for that.
+pub fn read_signed<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<SignedEntry> {
+    let (signer, sf, manifest) = read_signature(archive)?;
+    let data = read_entry(archive, name)?;
+
+    verify_signature_file(&sf, &manifest, name)?;
+
+    let section = sections(&manifest)
+        .into_iter()
+        .find(|s| s.attrs.get("Name").map(String::as_str) == Some(name))
+        .with_context(|| format!("{name} is not listed in the manifest"))?;
+    ensure!(
+        section.digest_matches("Digest", &data),
+        "digest of {name} doesn't match the manifest"
+    );
+
+    Ok(SignedEntry { data, signer })
+}
+
+/// Checks the signature of every file in a signed jar (or an apk with only a v1 signature) and
+/// returns the signer certificate.
+pub fn verify_all<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Vec<u8>> {
+    let (signer, sf, manifest) = read_signature(archive)?;
+
+    let names: Vec<String> = archive
+        .file_names()
+        .filter(|n| !n.ends_with('/') && !is_signature_file(n))
+        .map(str::to_string)
+        .collect();
+    ensure!(!names.is_empty(), "jar has nothing signed in it");
+
+    let whole_manifest = sections(&sf)
+        .first()
+        .is_some_and(|main| main.digest_matches("Digest-Manifest", &manifest));
+    let manifest_sections: HashMap<String, Section> = sections(&manifest)
+        .into_iter()
+        .filter_map(|s| Some((s.attrs.get("Name")?.clone(), s)))
+        .collect();
+
+    for name in names {
+        if !whole_manifest {
+            verify_signature_file(&sf, &manifest, &name)?;
+        }
+        let section = manifest_sections
+            .get(name.as_str())
+            .with_context(|| format!("{name} is not listed in the manifest"))?;
+        ensure!(
+            section.digest_matches("Digest", &read_entry(archive, &name)?),
+            "digest of {name} doesn't match the manifest"
+        );
+    }
+
+    Ok(signer)
+}
+
+/// Reads and checks the signature block, returns the signer certificate, the signature file and
+/// the manifest.
+fn read_signature<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let block_name = archive
+        .file_names()
+        .filter(|n| {
+            n.starts_with("META-INF/") && [".RSA", ".DSA", ".EC"].iter().any(|ext| n.ends_with(ext))
+        })
+        .map(str::to_string)
+        .exactly_one()
+        .map_err(|_| anyhow!("jar must have exactly one signature block"))?;
+    let sf_name = format!("{}.SF", &block_name[..block_name.rfind('.').unwrap()]);
+
+    let block = read_entry(archive, &block_name)?;
+    let sf = read_entry(archive, &sf_name)?;
+    let manifest = read_entry(archive, "META-INF/MANIFEST.MF")?;
+
+    let signer = verify_block(&block, &sf)?;
+    Ok((signer, sf, manifest))
+}
+
+/// Whether `name` is part of the signature itself rather than signed.
+fn is_signature_file(name: &str) -> bool {
+    name == "META-INF/MANIFEST.MF"
+        || name.starts_with("META-INF/")
+            && [".SF", ".RSA", ".DSA", ".EC"]
+                .iter()
+                .any(|ext| name.ends_with(ext))
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>> {
+    let mut file = archive
+        .by_name(name)
+        .with_context(|| format!("missing {name} in jar"))?;
+    let mut buf = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Checks the PKCS#7 signature block over the signature file and returns the signer certificate.
+fn verify_block(block: &[u8], sf: &[u8]) -> Result<Vec<u8>> {
+    let signed_data: SignedData = ContentInfo::from_der(block)?.content.decode_as()?;
+
+    let cert = match signed_data
+        .certificates
+        .as_ref()
+        .map(|c| c.0.as_slice())
+        .unwrap_or_default()
+    {
+        [CertificateChoices::Certificate(cert)] => cert,
+        _ => bail!("signature block must contain exactly one certificate"),
+    };
+    let signer_info = match signed_data.signer_infos.0.as_slice() {
+        [info] => info,
+        _ => bail!("signature block must contain exactly one signer"),
+    };
+
+    let digest_oid = signer_info.digest_alg.oid;
+    let signed = match &signer_info.signed_attrs {
+        Some(attrs) => {
+            let digest = attrs
+                .iter()
+                .find(|a| a.oid == OID_MESSAGE_DIGEST)
+                .and_then(|a| a.values.get(0))
+                .and_then(|v| v.decode_as::<OctetString>().ok())
+                .context("signed attributes lack a message digest")?;
+            ensure!(
+                digest.as_bytes() == hash(digest_oid, sf)?,
+                "signature file doesn't match its signed attributes"
+            );
+            attrs.to_der()?
+        }
+        None => sf.to_vec(),
+    };
+
+    let cert_der = cert.to_der()?;
+    let key =
+        RsaPublicKey::from_public_key_der(&cert.tbs_certificate.subject_public_key_info.to_der()?)
+            .context("only RSA signed jars are supported")?;
+    let scheme = match digest_oid {
+        OID_SHA1 => Pkcs1v15Sign::new::<Sha1>(),
+        OID_SHA256 => Pkcs1v15Sign::new::<Sha256>(),
+        OID_SHA512 => Pkcs1v15Sign::new::<Sha512>(),
+        oid => bail!("unsupported digest algorithm {oid}"),
+    };
+    key.verify(
+        scheme,
+        &hash(digest_oid, &signed)?,
+        signer_info.signature.as_bytes(),
+    )
+    .map_err(|e| anyhow!("bad jar signature: {e}"))?;
+
+    Ok(cert_der)
+}
+
+/// Checks that the signature file covers the manifest, either as a whole or for the section of `name`.
+fn verify_signature_file(sf: &[u8], manifest: &[u8], name: &str) -> Result<()> {
+    let sf_sections = sections(sf);
+    let main = sf_sections.first().context("empty signature file")?;
+    if main.digest_matches("Digest-Manifest", manifest) {
+        return Ok(());
+    }
+
+    let manifest_section = sections(manifest)
+        .into_iter()
+        .find(|s| s.attrs.get("Name").map(String::as_str) == Some(name))
+        .with_context(|| format!("{name} is not listed in the manifest"))?;
+    let sf_section = sf_sections
+        .iter()
+        .find(|s| s.attrs.get("Name").map(String::as_str) == Some(name))
+        .with_context(|| format!("{name} is not listed in the signature file"))?;
+    ensure!(
+        sf_section.digest_matches("Digest", manifest_section.raw),
+        "signature file doesn't match the manifest"
+    );
+    Ok(())
+}
+
+fn hash(oid: ObjectIdentifier, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(match oid {
+        OID_SHA1 => Sha1::digest(data).to_vec(),
+        OID_SHA256 => Sha256::digest(data).to_vec(),
+        OID_SHA512 => Sha512::digest(data).to_vec(),
+        oid => bail!("unsupported digest algorithm {oid}"),
+    })
+}
+
+/// A section of a manifest or signature file, `raw` includes the terminating empty line.
+struct Section<'a> {
+    raw: &'a [u8],
+    attrs: HashMap<String, String>,
+}
+
+impl<'a> Section<'a> {
+    fn new(raw: &'a [u8], lines: &mut Vec<String>) -> Self {
+        let attrs = lines
+            .drain(..)
+            .filter_map(|l| {
+                l.split_once(": ")
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+            })
+            .collect();
+        Self { raw, attrs }
+    }
+
+    /// Looks for a `<ALG>-<suffix>` attribute with a known algorithm, preferring the strongest one.
+    fn digest_matches(&self, suffix: &str, data: &[u8]) -> bool {
+        let expected = |alg: &str| {
+            self.attrs
+                .get(&format!("{alg}-{suffix}"))
+                .and_then(|v| STANDARD.decode(v).ok())
+        };
+        if let Some(expected) = expected("SHA-512") {
+            expected == Sha512::digest(data).as_slice()
+        } else if let Some(expected) = expected("SHA-256") {
+            expected == Sha256::digest(data).as_slice()
+        } else if let Some(expected) = expected("SHA1") {
+            expected == Sha1::digest(data).as_slice()
+        } else {
+            false
+        }
+    }
+}
+
+fn sections(bytes: &[u8]) -> Vec<Section<'_>> {
+    let mut sections = Vec::new();
+    let mut lines: Vec<String> = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let end = bytes[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |i| pos + i + 1);
+        let line = String::from_utf8_lossy(&bytes[pos..end]);
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.is_empty() {
+            if !lines.is_empty() {
+                sections.push(Section::new(&bytes[start..end], &mut lines));
+            }
+            start = end;
+        } else if let Some(continued) = line.strip_prefix(' ') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(continued);
+            }
+        } else {
+            lines.push(line.to_string());
+        }
+        pos = end;
+    }
+    if !lines.is_empty() {
+        sections.push(Section::new(&bytes[start..], &mut lines));
+    }
+
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    const JAR: &[u8] = include_bytes!("../tests/fixtures/index-v1.jar");
+    const INDEX_V1: &[u8] = include_bytes!("../tests/fixtures/index-v1.json");
+    /// SHA-256 of the certificate the jar is signed with, see `make-jars.py`.
+    const SIGNER: &str = "eeba212c729c2e69a8b3bf740a3b3df5262f5b14ef156d139ac33bd5413c35ad";
+
+    /// The fixture jar with every entry passed through `change`, which drops it by returning `None`.
+    fn rewritten(change: impl Fn(&str, Vec<u8>) -> Option<Vec<u8>>) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut original = ZipArchive::new(Cursor::new(JAR)).unwrap();
+        let mut jar = ZipWriter::new(Cursor::new(Vec::new()));
+        for i in 0..original.len() {
+            let name = original.name_for_index(i).unwrap().to_string();
+            let data = read_entry(&mut original, &name).unwrap();
+            if let Some(data) = change(&name, data) {
+                jar.start_file(name, SimpleFileOptions::default()).unwrap();
+                jar.write_all(&data).unwrap();
+            }
+        }
+        ZipArchive::new(jar.finish().unwrap()).unwrap()
+    }
+
+    fn replaced(data: Vec<u8>, from: &str, to: &str) -> Vec<u8> {
+        String::from_utf8(data)
+            .unwrap()
+            .replace(from, to)
+            .into_bytes()
+    }
+
+    #[test]
+    fn signed_jars_are_read() {
+        let mut jar = ZipArchive::new(Cursor::new(JAR)).unwrap();
+        let entry = read_signed(&mut jar, "index-v1.json").unwrap();
+        assert_eq!(entry.data, INDEX_V1);
+        assert_eq!(hex::encode(Sha256::digest(&entry.signer)), SIGNER);
+
+        let signer = verify_all(&mut jar).unwrap();
+        assert_eq!(signer, entry.signer);
+    }
+
+    #[test]
+    fn tampered_entries_are_rejected() {
+        let mut jar = rewritten(|name, data| {
+            Some(match name {
+                "index-v1.json" => replaced(data, "F-Droid", "F-Dr0id"),
+                _ => data,
+            })
+        });
+        assert!(read_signed(&mut jar, "index-v1.json").is_err());
+        assert!(verify_all(&mut jar).is_err());
+    }
+
+    #[test]
+    fn tampered_signature_files_are_rejected() {
+        let mut jar = rewritten(|name, data| {
+            Some(match name {
+                "META-INF/CERT.SF" => {
+                    let data = replaced(data, "Digest-Manifest: ", "Digest-Manifest: AAAA");
+                    replaced(data, "Digest: ", "Digest: AAAA")
+                }
+                _ => data,
+            })
+        });
+        assert!(read_signed(&mut jar, "index-v1.json").is_err());
+        assert!(verify_all(&mut jar).is_err());
+    }
+
+    #[test]
+    fn tampered_manifests_are_rejected() {
+        let mut jar = rewritten(|name, data| {
+            Some(match name {
+                "META-INF/MANIFEST.MF" => replaced(data, "Name: index-v1.json", "Name: other.json"),
+                _ => data,
+            })
+        });
+        assert!(read_signed(&mut jar, "index-v1.json").is_err());
+        assert!(verify_all(&mut jar).is_err());
+    }
+
+    #[test]
+    fn unsigned_jars_are_rejected() {
+        let mut jar = rewritten(|name, data| (!name.starts_with("META-INF/")).then_some(data));
+        assert!(read_signed(&mut jar, "index-v1.json").is_err());
+        assert!(verify_all(&mut jar).is_err());
+    }
+}
@@ -1,11 +1,27 @@
+pub mod apk;
 pub mod app;
+pub mod cache;
+pub mod description;
+pub mod device;
+pub mod download;
 pub mod egui_custom;
+pub mod index_v1;
+pub mod index_v2;
+pub mod install;
+pub mod installed;
+pub mod jar;
+pub mod notify;
+#[cfg(target_os = "android")]
+pub mod receiver;
 pub mod repo;
+pub mod schedule;
+pub mod search;
+pub mod sync;
 
 #[cfg(target_os = "android")]
 #[no_mangle]
 fn android_main(app: winit::platform::android::activity::AndroidApp) {
-    use android_logger::{Config, FilterBuilder};
+    use android_logger::Config;
     use log::LevelFilter;
 
     android_logger::init_once(Config::default().with_max_level(LevelFilter::Info));
@@ -15,10 +31,12 @@ fn android_main(app: winit::platform::android::activity::AndroidApp) {
         ..Default::default()
     };
 
-    eframe::run_native(
+    // Fails for a second activity in the same process, which can't start the app again
+    if let Err(e) = eframe::run_native(
         "Edroid",
         options,
         Box::new(|cc| Ok(Box::new(app::Edroid::new(cc)))),
-    )
-    .unwrap();
+    ) {
+        log::error!("can't run the app: {e}");
+    }
 }
@@ -1,9 +1,23 @@
 use eframe::NativeOptions;
 use log::Level;
 
+pub mod apk;
 pub mod app;
+pub mod cache;
+pub mod description;
+pub mod device;
+pub mod download;
 pub mod egui_custom;
+pub mod index_v1;
+pub mod index_v2;
+pub mod install;
+pub mod installed;
+pub mod jar;
+pub mod notify;
 pub mod repo;
+pub mod schedule;
+pub mod search;
+pub mod sync;
 
 fn main() {
     simple_logger::init_with_level(Level::Info).unwrap();
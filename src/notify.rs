@@ -0,0 +1,614 @@
+//! Notifications about available updates and downloads, posted through the Android
+//! NotificationManager.
+//!
+//! What to show is worked out whenever it may have changed and handed to [Notifications], which
+//! only bothers the [Notifier] with what changed. Tapping a notification opens the app on its
+//! [Link].
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use reqwest::Url;
+
+/// Scheme of the links notifications open, read back from the intent when they start the app.
+const LINK_SCHEME: &str = "edroid";
+
+/// A screen of the app a notification links to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Link {
+    Updates,
+    Downloads,
+    /// The app `id` from the repo with the [crate::repo::Repo::cache_key] `repo`.
+    App {
+        repo: String,
+        id: String,
+    },
+}
+
+impl Link {
+    pub fn to_uri(&self) -> String {
+        match self {
+            Self::Updates => format!("{LINK_SCHEME}://updates"),
+            Self::Downloads => format!("{LINK_SCHEME}://downloads"),
+            Self::App { repo, id } => {
+                let mut url = Url::parse(&format!("{LINK_SCHEME}://app")).expect("valid url");
+                url.query_pairs_mut()
+                    .append_pair("repo", repo)
+                    .append_pair("id", id);
+                url.into()
+            }
+        }
+    }
+
+    /// Reads a link made by [Link::to_uri], `None` for any other uri.
+    pub fn parse(uri: &str) -> Option<Self> {
+        let url = Url::parse(uri).ok()?;
+        if url.scheme() != LINK_SCHEME {
+            return None;
+        }
+        match url.host_str()? {
+            "updates" => Some(Self::Updates),
+            "downloads" => Some(Self::Downloads),
+            "app" => {
+                let query: HashMap<_, _> = url.query_pairs().collect();
+                Some(Self::App {
+                    repo: query.get("repo")?.to_string(),
+                    id: query.get("id")?.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Notification channels, the user can mute each of them in the system settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Updates,
+    Downloads,
+}
+
+impl Channel {
+    pub const ALL: [Self; 2] = [Self::Updates, Self::Downloads];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Updates => "updates",
+            Self::Downloads => "downloads",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Updates => "Available updates",
+            Self::Downloads => "Downloads",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// Notifications with the same tag replace each other.
+    pub tag: String,
+    pub channel: Channel,
+    pub title: String,
+    pub text: String,
+    /// Whether something is still going on, the notification can't be swiped away then.
+    pub ongoing: bool,
+    /// How far along it is, in percent, if that's known.
+    pub percent: Option<u8>,
+    pub link: Link,
+}
+
+pub trait Notifier: Send + Sync {
+    /// Shows `notification`, replacing the one with the same tag.
+    fn show(&self, notification: &Notification) -> Result<()>;
+
+    /// Takes down the notification with `tag`.
+    fn cancel(&self, tag: &str) -> Result<()>;
+
+    /// Whether the user tapped `notification` since it was shown.
+    fn tapped(&self, notification: &Notification) -> Result<bool>;
+}
+
+/// Notifications of the NotificationManager, in the channels of [Channel].
+#[cfg(target_os = "android")]
+pub struct AndroidNotifier;
+
+#[cfg(target_os = "android")]
+impl AndroidNotifier {
+    /// The first API level with notification channels.
+    const CHANNELS_SINCE: i32 = 26;
+    /// `NotificationManager.IMPORTANCE_LOW`, shown without a sound.
+    const IMPORTANCE_LOW: i32 = 2;
+    /// `NotificationManager.IMPORTANCE_DEFAULT`.
+    const IMPORTANCE_DEFAULT: i32 = 3;
+    /// `Intent.FLAG_ACTIVITY_NEW_TASK | Intent.FLAG_ACTIVITY_SINGLE_TOP`, which brings the
+    /// running activity to the front. Starting it again would start the app a second time in
+    /// the same process, which can't be done.
+    const BRING_TO_FRONT: i32 = 0x1000_0000 | 0x2000_0000;
+    /// `PendingIntent.FLAG_ONE_SHOT | PendingIntent.FLAG_UPDATE_CURRENT |
+    /// PendingIntent.FLAG_IMMUTABLE`, gone once the notification is tapped.
+    const PENDING_INTENT_FLAGS: i32 = 0x4000_0000 | 0x0800_0000 | 0x0400_0000;
+    /// `PendingIntent.FLAG_NO_CREATE`.
+    const NO_CREATE: i32 = 0x2000_0000;
+
+    /// Creates the notification channels, which does nothing for the ones that exist already.
+    pub fn new() -> Result<Self> {
+        use jni::objects::{JObject, JValue};
+
+        Self::with_manager(|env, _, manager| {
+            if Self::sdk(env)? < Self::CHANNELS_SINCE {
+                return Ok(());
+            }
+            for channel in Channel::ALL {
+                let importance = match channel {
+                    Channel::Updates => Self::IMPORTANCE_DEFAULT,
+                    Channel::Downloads => Self::IMPORTANCE_LOW,
+                };
+                let id = JObject::from(env.new_string(channel.id())?);
+                let name = JObject::from(env.new_string(channel.name())?);
+                let channel = env.new_object(
+                    "android/app/NotificationChannel",
+                    "(Ljava/lang/String;Ljava/lang/CharSequence;I)V",
+                    &[
+                        JValue::Object(&id),
+                        JValue::Object(&name),
+                        JValue::Int(importance),
+                    ],
+                )?;
+                env.call_method(
+                    manager,
+                    "createNotificationChannel",
+                    "(Landroid/app/NotificationChannel;)V",
+                    &[JValue::Object(&channel)],
+                )?;
+            }
+            Ok(())
+        })?;
+        Ok(Self)
+    }
+
+    fn sdk(env: &mut jni::JNIEnv) -> Result<i32> {
+        Ok(env
+            .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")?
+            .i()?)
+    }
+
+    fn with_manager<T>(
+        f: impl FnOnce(&mut jni::JNIEnv, &jni::objects::JObject, &jni::objects::JObject) -> Result<T>,
+    ) -> Result<T> {
+        use jni::objects::{JObject, JValue};
+
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }?;
+        let mut env = vm.attach_current_thread()?;
+
+        let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+        let service_name = JObject::from(env.new_string("notification")?);
+        let manager = env
+            .call_method(
+                &activity,
+                "getSystemService",
+                "(Ljava/lang/String;)Ljava/lang/Object;",
+                &[JValue::Object(&service_name)],
+            )?
+            .l()?;
+        anyhow::ensure!(!manager.is_null(), "no notification service");
+        f(&mut env, &activity, &manager)
+    }
+
+    /// The intent `notification` opens the app on its link with, one per tag. The running
+    /// activity doesn't see the intents it's brought to the front with, so [Notifier::tapped]
+    /// looks up the intent with `flags` [Self::NO_CREATE] instead: it's gone once it was used.
+    fn pending_intent<'local>(
+        env: &mut jni::JNIEnv<'local>,
+        activity: &jni::objects::JObject,
+        notification: &Notification,
+        flags: i32,
+    ) -> Result<jni::objects::JObject<'local>> {
+        use jni::objects::{JObject, JValue};
+
+        let package_name = env
+            .call_method(activity, "getPackageName", "()Ljava/lang/String;", &[])?
+            .l()?;
+        let package_manager = env
+            .call_method(
+                activity,
+                "getPackageManager",
+                "()Landroid/content/pm/PackageManager;",
+                &[],
+            )?
+            .l()?;
+        let intent = env
+            .call_method(
+                package_manager,
+                "getLaunchIntentForPackage",
+                "(Ljava/lang/String;)Landroid/content/Intent;",
+                &[JValue::Object(&package_name)],
+            )?
+            .l()?;
+        anyhow::ensure!(!intent.is_null(), "no launch intent");
+
+        let uri = JObject::from(env.new_string(notification.link.to_uri())?);
+        let uri = env
+            .call_static_method(
+                "android/net/Uri",
+                "parse",
+                "(Ljava/lang/String;)Landroid/net/Uri;",
+                &[JValue::Object(&uri)],
+            )?
+            .l()?;
+        env.call_method(
+            &intent,
+            "setData",
+            "(Landroid/net/Uri;)Landroid/content/Intent;",
+            &[JValue::Object(&uri)],
+        )?;
+        env.call_method(
+            &intent,
+            "addFlags",
+            "(I)Landroid/content/Intent;",
+            &[JValue::Int(Self::BRING_TO_FRONT)],
+        )?;
+
+        // Like String.hashCode, the intents are told apart by this and the link
+        let request_code = notification.tag.bytes().fold(0i32, |hash, b| {
+            hash.wrapping_mul(31).wrapping_add(i32::from(b))
+        });
+
+        Ok(env
+            .call_static_method(
+                "android/app/PendingIntent",
+                "getActivity",
+                "(Landroid/content/Context;ILandroid/content/Intent;I)Landroid/app/PendingIntent;",
+                &[
+                    JValue::Object(activity),
+                    JValue::Int(request_code),
+                    JValue::Object(&intent),
+                    JValue::Int(flags),
+                ],
+            )?
+            .l()?)
+    }
+}
+
+#[cfg(target_os = "android")]
+impl Notifier for AndroidNotifier {
+    fn show(&self, notification: &Notification) -> Result<()> {
+        use jni::objects::{JObject, JValue};
+
+        const BUILDER: &str = "android/app/Notification$Builder";
+        const RETURNS_BUILDER: &str = "Landroid/app/Notification$Builder;";
+
+        Self::with_manager(|env, activity, manager| {
+            let builder = if Self::sdk(env)? >= Self::CHANNELS_SINCE {
+                let channel = JObject::from(env.new_string(notification.channel.id())?);
+                env.new_object(
+                    BUILDER,
+                    "(Landroid/content/Context;Ljava/lang/String;)V",
+                    &[JValue::Object(activity), JValue::Object(&channel)],
+                )?
+            } else {
+                env.new_object(
+                    BUILDER,
+                    "(Landroid/content/Context;)V",
+                    &[JValue::Object(activity)],
+                )?
+            };
+
+            let icon = if notification.ongoing {
+                "stat_sys_download"
+            } else {
+                "stat_sys_download_done"
+            };
+            let icon = env.get_static_field("android/R$drawable", icon, "I")?.i()?;
+            env.call_method(
+                &builder,
+                "setSmallIcon",
+                format!("(I){RETURNS_BUILDER}"),
+                &[JValue::Int(icon)],
+            )?;
+
+            let title = JObject::from(env.new_string(&notification.title)?);
+            env.call_method(
+                &builder,
+                "setContentTitle",
+                format!("(Ljava/lang/CharSequence;){RETURNS_BUILDER}"),
+                &[JValue::Object(&title)],
+            )?;
+            let text = JObject::from(env.new_string(&notification.text)?);
+            env.call_method(
+                &builder,
+                "setContentText",
+                format!("(Ljava/lang/CharSequence;){RETURNS_BUILDER}"),
+                &[JValue::Object(&text)],
+            )?;
+
+            if notification.ongoing {
+                let (max, progress, indeterminate) = match notification.percent {
+                    Some(percent) => (100, i32::from(percent), false),
+                    None => (0, 0, true),
+                };
+                env.call_method(
+                    &builder,
+                    "setProgress",
+                    format!("(IIZ){RETURNS_BUILDER}"),
+                    &[
+                        JValue::Int(max),
+                        JValue::Int(progress),
+                        JValue::Bool(indeterminate.into()),
+                    ],
+                )?;
+            }
+            for (method, value) in [
+                ("setOngoing", notification.ongoing),
+                ("setAutoCancel", !notification.ongoing),
+                // Progress updates don't make a sound every time
+                ("setOnlyAlertOnce", true),
+            ] {
+                env.call_method(
+                    &builder,
+                    method,
+                    format!("(Z){RETURNS_BUILDER}"),
+                    &[JValue::Bool(value.into())],
+                )?;
+            }
+
+            let intent =
+                Self::pending_intent(env, activity, notification, Self::PENDING_INTENT_FLAGS)?;
+            env.call_method(
+                &builder,
+                "setContentIntent",
+                format!("(Landroid/app/PendingIntent;){RETURNS_BUILDER}"),
+                &[JValue::Object(&intent)],
+            )?;
+
+            let built = env
+                .call_method(&builder, "build", "()Landroid/app/Notification;", &[])?
+                .l()?;
+            let tag = JObject::from(env.new_string(&notification.tag)?);
+            env.call_method(
+                manager,
+                "notify",
+                "(Ljava/lang/String;ILandroid/app/Notification;)V",
+                &[JValue::Object(&tag), JValue::Int(0), JValue::Object(&built)],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn cancel(&self, tag: &str) -> Result<()> {
+        use jni::objects::{JObject, JValue};
+
+        Self::with_manager(|env, _, manager| {
+            let tag = JObject::from(env.new_string(tag)?);
+            env.call_method(
+                manager,
+                "cancel",
+                "(Ljava/lang/String;I)V",
+                &[JValue::Object(&tag), JValue::Int(0)],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn tapped(&self, notification: &Notification) -> Result<bool> {
+        Self::with_manager(|env, activity, _| {
+            let flags = Self::PENDING_INTENT_FLAGS | Self::NO_CREATE;
+            Ok(Self::pending_intent(env, activity, notification, flags)?.is_null())
+        })
+    }
+}
+
+/// Writes the notifications to the log, for platforms without a NotificationManager.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn show(&self, notification: &Notification) -> Result<()> {
+        match notification.percent {
+            Some(percent) => {
+                log::info!("{}: {} ({percent}%)", notification.title, notification.text)
+            }
+            None => log::info!("{}: {}", notification.title, notification.text),
+        }
+        Ok(())
+    }
+
+    fn cancel(&self, _tag: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn tapped(&self, _notification: &Notification) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// The notifier of the platform.
+pub fn notifier() -> Box<dyn Notifier> {
+    #[cfg(target_os = "android")]
+    match AndroidNotifier::new() {
+        Ok(notifier) => return Box::new(notifier),
+        Err(e) => log::error!("can't create the notification channels: {e:#}"),
+    }
+
+    Box::new(LogNotifier)
+}
+
+/// The notifications shown, keyed by tag.
+pub struct Notifications {
+    notifier: Box<dyn Notifier>,
+    shown: HashMap<String, Notification>,
+    /// Tags of the shown notifications [Notifications::tapped] reported already.
+    tapped: HashSet<String>,
+}
+
+impl Notifications {
+    pub fn new(notifier: Box<dyn Notifier>) -> Self {
+        Self {
+            notifier,
+            shown: HashMap::new(),
+            tapped: HashSet::new(),
+        }
+    }
+
+    /// Shows `wanted` and takes down every other notification. The ones already shown as they
+    /// are aren't posted again, so a notification the user swiped away stays away until it
+    /// changes.
+    pub fn show_only(&mut self, wanted: Vec<Notification>) {
+        let tags: Vec<String> = self
+            .shown
+            .keys()
+            .filter(|tag| !wanted.iter().any(|n| &n.tag == *tag))
+            .cloned()
+            .collect();
+        for tag in tags {
+            if let Err(e) = self.notifier.cancel(&tag) {
+                log::error!("can't take down the notification {tag}: {e:#}");
+            }
+            self.shown.remove(&tag);
+            self.tapped.remove(&tag);
+        }
+
+        for notification in wanted {
+            if self.shown.get(&notification.tag) == Some(&notification) {
+                continue;
+            }
+            // Kept even if it failed, so it isn't tried again every frame
+            if let Err(e) = self.notifier.show(&notification) {
+                log::error!("can't show the notification {}: {e:#}", notification.tag);
+            }
+            self.tapped.remove(&notification.tag);
+            self.shown.insert(notification.tag.clone(), notification);
+        }
+    }
+
+    /// The link of a notification the user tapped since the last call, if any.
+    pub fn tapped(&mut self) -> Option<Link> {
+        let tapped = self.shown.values().find(|n| {
+            !self.tapped.contains(&n.tag)
+                && self.notifier.tapped(n).unwrap_or_else(|e| {
+                    log::error!("can't tell whether {} was tapped: {e:#}", n.tag);
+                    false
+                })
+        })?;
+        let (tag, link) = (tapped.tag.clone(), tapped.link.clone());
+        if tapped.ongoing {
+            // It stays up, posted again it can be tapped again
+            self.shown.remove(&tag);
+        } else {
+            self.tapped.insert(tag);
+        }
+        Some(link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    /// Records what it's asked to do, the notifications with a tag in `tapped` were tapped.
+    #[derive(Default, Clone)]
+    struct FakeNotifier {
+        calls: Arc<Mutex<Vec<String>>>,
+        tapped: Arc<Mutex<HashSet<String>>>,
+    }
+
+    impl Notifier for FakeNotifier {
+        fn show(&self, notification: &Notification) -> Result<()> {
+            self.calls.lock().push(format!("show {}", notification.tag));
+            Ok(())
+        }
+
+        fn cancel(&self, tag: &str) -> Result<()> {
+            self.calls.lock().push(format!("cancel {tag}"));
+            Ok(())
+        }
+
+        fn tapped(&self, notification: &Notification) -> Result<bool> {
+            Ok(self.tapped.lock().contains(&notification.tag))
+        }
+    }
+
+    impl FakeNotifier {
+        fn calls(&self) -> Vec<String> {
+            std::mem::take(&mut self.calls.lock())
+        }
+    }
+
+    fn notification(tag: &str, ongoing: bool) -> Notification {
+        Notification {
+            tag: tag.to_string(),
+            channel: Channel::Downloads,
+            title: tag.to_string(),
+            text: String::new(),
+            ongoing,
+            percent: None,
+            link: Link::App {
+                repo: "example.org/repo".to_string(),
+                id: tag.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn links_are_read_back() {
+        for link in [
+            Link::Updates,
+            Link::Downloads,
+            Link::App {
+                repo: "example.org/fdroid/repo?a=1&b".to_string(),
+                id: "org.example.app".to_string(),
+            },
+        ] {
+            assert_eq!(Link::parse(&link.to_uri()), Some(link));
+        }
+        assert_eq!(Link::parse("https://updates"), None);
+        assert_eq!(Link::parse("edroid://app?repo=a"), None);
+        assert_eq!(Link::parse("edroid://nowhere"), None);
+    }
+
+    #[test]
+    fn only_changes_are_posted() {
+        let notifier = FakeNotifier::default();
+        let mut notifications = Notifications::new(Box::new(notifier.clone()));
+
+        notifications.show_only(vec![notification("a", false), notification("b", true)]);
+        assert_eq!(notifier.calls(), ["show a", "show b"]);
+
+        let mut progressed = notification("b", true);
+        progressed.percent = Some(50);
+        notifications.show_only(vec![notification("a", false), progressed.clone()]);
+        assert_eq!(notifier.calls(), ["show b"]);
+
+        notifications.show_only(vec![progressed]);
+        assert_eq!(notifier.calls(), ["cancel a"]);
+    }
+
+    #[test]
+    fn taps_are_reported_once() {
+        let notifier = FakeNotifier::default();
+        let mut notifications = Notifications::new(Box::new(notifier.clone()));
+        let shown = vec![notification("done", false), notification("running", true)];
+        notifications.show_only(shown.clone());
+        notifier.calls();
+        assert_eq!(notifications.tapped(), None);
+
+        notifier.tapped.lock().insert("done".to_string());
+        assert_eq!(notifications.tapped(), Some(shown[0].link.clone()));
+        assert_eq!(notifications.tapped(), None);
+        // Swiped away by the tap, it isn't posted again
+        notifications.show_only(shown.clone());
+        assert!(notifier.calls().is_empty());
+
+        notifier.tapped.lock().insert("running".to_string());
+        assert_eq!(notifications.tapped(), Some(shown[1].link.clone()));
+        // Still up, it's posted again to be tapped again
+        notifications.show_only(shown);
+        assert_eq!(notifier.calls(), ["show running"]);
+    }
+}
@@ -0,0 +1,325 @@
+//! Statuses the system sends back to the app, like the outcome of a `PackageInstaller` session.
+//!
+//! They only ever reach a `BroadcastReceiver`, which a native activity doesn't have. The one in
+//! `android/status-receiver.dex` is loaded at runtime and its `onReceive` is [on_receive], which
+//! keeps the last status of every request until it's read with [status].
+
+use std::{collections::BTreeMap, ffi::c_void};
+
+use anyhow::{Context, Result};
+use jni::{
+    objects::{GlobalRef, JClass, JObject, JString, JValue},
+    JNIEnv, NativeMethod,
+};
+use parking_lot::Mutex;
+
+/// `me.avidor.edroid.StatusReceiver`, see `android/make-dex.py`.
+const DEX: &[u8] = include_bytes!("../android/status-receiver.dex");
+const RECEIVER_CLASS: &str = "me.avidor.edroid.StatusReceiver";
+/// The id of the request a status is about, put in the intent by [pending_intent].
+const EXTRA_REQUEST: &str = "me.avidor.edroid.extra.REQUEST";
+const EXTRA_STATUS: &str = "android.content.pm.extra.STATUS";
+const EXTRA_STATUS_MESSAGE: &str = "android.content.pm.extra.STATUS_MESSAGE";
+/// `Intent.EXTRA_INTENT`, what to start for the user to confirm.
+const EXTRA_INTENT: &str = "android.intent.extra.INTENT";
+/// `PackageInstaller.STATUS_FAILURE`, for a status without one.
+const STATUS_FAILURE: i32 = 1;
+/// `PendingIntent.FLAG_UPDATE_CURRENT | PendingIntent.FLAG_MUTABLE`, the system fills in the
+/// status every time it sends one.
+const PENDING_INTENT_FLAGS: i32 = 0x08000000 | 0x02000000;
+/// `Context.RECEIVER_NOT_EXPORTED`, only the app itself sends the statuses.
+const RECEIVER_NOT_EXPORTED: i32 = 4;
+/// Android 8, since when dex files can be loaded from memory.
+const IN_MEMORY_DEX_SDK: i32 = 26;
+/// Android 13, since when receivers say whether other apps can reach them.
+const EXPORTED_FLAG_SDK: i32 = 33;
+
+/// `PackageInstaller.EXTRA_STATUS` of a request and what came with it.
+#[derive(Clone)]
+pub struct Status {
+    pub code: i32,
+    pub message: Option<String>,
+    /// The intent to start for `STATUS_PENDING_USER_ACTION`.
+    pub confirm: Option<GlobalRef>,
+}
+
+/// The last status of every request, by action and request id.
+static RECEIVED: Mutex<BTreeMap<(String, i32), Status>> = Mutex::new(BTreeMap::new());
+
+/// The receiver once it's loaded, with the actions it's registered for.
+static RECEIVER: Mutex<Option<(GlobalRef, Vec<String>)>> = Mutex::new(None);
+
+/// The intent the system sends the status of `request` to, `action` tells apart the kinds of
+/// requests.
+pub fn pending_intent<'local>(
+    env: &mut JNIEnv<'local>,
+    action: &str,
+    request: i32,
+) -> Result<JObject<'local>> {
+    listen(env, action)?;
+
+    let ctx = ndk_context::android_context();
+    let context = unsafe { JObject::from_raw(ctx.context().cast()) };
+    let action = JObject::from(env.new_string(action)?);
+    let intent = env.new_object(
+        "android/content/Intent",
+        "(Ljava/lang/String;)V",
+        &[JValue::Object(&action)],
+    )?;
+    // Only this app gets to read it
+    let package = env
+        .call_method(&context, "getPackageName", "()Ljava/lang/String;", &[])?
+        .l()?;
+    env.call_method(
+        &intent,
+        "setPackage",
+        "(Ljava/lang/String;)Landroid/content/Intent;",
+        &[JValue::Object(&package)],
+    )?;
+    let extra = JObject::from(env.new_string(EXTRA_REQUEST)?);
+    env.call_method(
+        &intent,
+        "putExtra",
+        "(Ljava/lang/String;I)Landroid/content/Intent;",
+        &[JValue::Object(&extra), JValue::Int(request)],
+    )?;
+
+    Ok(env
+        .call_static_method(
+            "android/app/PendingIntent",
+            "getBroadcast",
+            "(Landroid/content/Context;ILandroid/content/Intent;I)Landroid/app/PendingIntent;",
+            &[
+                JValue::Object(&context),
+                JValue::Int(request),
+                JValue::Object(&intent),
+                JValue::Int(PENDING_INTENT_FLAGS),
+            ],
+        )?
+        .l()?)
+}
+
+/// The last status the system sent for `request`, `None` if it didn't say anything yet.
+pub fn status(action: &str, request: i32) -> Option<Status> {
+    RECEIVED.lock().get(&(action.to_string(), request)).cloned()
+}
+
+/// Drops what was received for a request that's over.
+pub fn forget(action: &str, request: i32) {
+    RECEIVED.lock().remove(&(action.to_string(), request));
+}
+
+/// Registers the receiver for `action`, loading it first if it isn't yet.
+fn listen(env: &mut JNIEnv, action: &str) -> Result<()> {
+    let mut loaded = RECEIVER.lock();
+    let (receiver, actions) = match &mut *loaded {
+        Some(loaded) => loaded,
+        empty => empty.insert((load(env)?, Vec::new())),
+    };
+    if actions.iter().any(|a| a == action) {
+        return Ok(());
+    }
+
+    let ctx = ndk_context::android_context();
+    let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+    // The application outlives the activity, and with it the requests
+    let context = env
+        .call_method(
+            activity,
+            "getApplicationContext",
+            "()Landroid/content/Context;",
+            &[],
+        )?
+        .l()?;
+    let name = JObject::from(env.new_string(action)?);
+    let filter = env.new_object(
+        "android/content/IntentFilter",
+        "(Ljava/lang/String;)V",
+        &[JValue::Object(&name)],
+    )?;
+    if sdk(env)? >= EXPORTED_FLAG_SDK {
+        env.call_method(
+            &context,
+            "registerReceiver",
+            "(Landroid/content/BroadcastReceiver;Landroid/content/IntentFilter;I)Landroid/content/Intent;",
+            &[
+                JValue::Object(receiver.as_obj()),
+                JValue::Object(&filter),
+                JValue::Int(RECEIVER_NOT_EXPORTED),
+            ],
+        )?;
+    } else {
+        env.call_method(
+            &context,
+            "registerReceiver",
+            "(Landroid/content/BroadcastReceiver;Landroid/content/IntentFilter;)Landroid/content/Intent;",
+            &[JValue::Object(receiver.as_obj()), JValue::Object(&filter)],
+        )?;
+    }
+
+    actions.push(action.to_string());
+    Ok(())
+}
+
+/// Loads [DEX], binds `onReceive` to [on_receive] and creates the receiver.
+fn load(env: &mut JNIEnv) -> Result<GlobalRef> {
+    let ctx = ndk_context::android_context();
+    let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+    let parent = env
+        .call_method(
+            &activity,
+            "getClassLoader",
+            "()Ljava/lang/ClassLoader;",
+            &[],
+        )?
+        .l()?;
+
+    let loader = if sdk(env)? >= IN_MEMORY_DEX_SDK {
+        let bytes = env.byte_array_from_slice(DEX)?;
+        let buffer = env
+            .call_static_method(
+                "java/nio/ByteBuffer",
+                "wrap",
+                "([B)Ljava/nio/ByteBuffer;",
+                &[JValue::Object(&bytes)],
+            )?
+            .l()?;
+        env.new_object(
+            "dalvik/system/InMemoryDexClassLoader",
+            "(Ljava/nio/ByteBuffer;Ljava/lang/ClassLoader;)V",
+            &[JValue::Object(&buffer), JValue::Object(&parent)],
+        )?
+    } else {
+        let dir = env
+            .call_method(&activity, "getCodeCacheDir", "()Ljava/io/File;", &[])?
+            .l()?;
+        let dir = JString::from(
+            env.call_method(dir, "getAbsolutePath", "()Ljava/lang/String;", &[])?
+                .l()?,
+        );
+        let dir: String = env.get_string(&dir)?.into();
+        let path = format!("{dir}/status-receiver.dex");
+        std::fs::write(&path, DEX).with_context(|| format!("can't write {path}"))?;
+
+        let path = JObject::from(env.new_string(path)?);
+        let dir = JObject::from(env.new_string(dir)?);
+        env.new_object(
+            "dalvik/system/DexClassLoader",
+            "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/ClassLoader;)V",
+            &[
+                JValue::Object(&path),
+                JValue::Object(&dir),
+                JValue::Object(&JObject::null()),
+                JValue::Object(&parent),
+            ],
+        )?
+    };
+
+    let name = JObject::from(env.new_string(RECEIVER_CLASS)?);
+    let class = JClass::from(
+        env.call_method(
+            &loader,
+            "loadClass",
+            "(Ljava/lang/String;)Ljava/lang/Class;",
+            &[JValue::Object(&name)],
+        )?
+        .l()?,
+    );
+    env.register_native_methods(
+        &class,
+        &[NativeMethod {
+            name: "onReceive".into(),
+            sig: "(Landroid/content/Context;Landroid/content/Intent;)V".into(),
+            fn_ptr: on_receive as *mut c_void,
+        }],
+    )?;
+    let receiver = env.new_object(&class, "()V", &[])?;
+    Ok(env.new_global_ref(receiver)?)
+}
+
+/// `StatusReceiver.onReceive`, called on the main thread for every status.
+extern "system" fn on_receive<'local>(
+    mut env: JNIEnv<'local>,
+    _receiver: JObject<'local>,
+    _context: JObject<'local>,
+    intent: JObject<'local>,
+) {
+    if let Err(e) = receive(&mut env, &intent) {
+        log::error!("can't read a status: {e:#}");
+        // An exception would crash the app once this returns
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+fn receive(env: &mut JNIEnv, intent: &JObject) -> Result<()> {
+    let action = JString::from(
+        env.call_method(intent, "getAction", "()Ljava/lang/String;", &[])?
+            .l()?,
+    );
+    let action: String = env.get_string(&action)?.into();
+
+    let mut int_extra = |name: &str, default: i32| -> Result<i32> {
+        let name = JObject::from(env.new_string(name)?);
+        Ok(env
+            .call_method(
+                intent,
+                "getIntExtra",
+                "(Ljava/lang/String;I)I",
+                &[JValue::Object(&name), JValue::Int(default)],
+            )?
+            .i()?)
+    };
+    let request = int_extra(EXTRA_REQUEST, -1)?;
+    let code = int_extra(EXTRA_STATUS, STATUS_FAILURE)?;
+
+    let name = JObject::from(env.new_string(EXTRA_STATUS_MESSAGE)?);
+    let message = JString::from(
+        env.call_method(
+            intent,
+            "getStringExtra",
+            "(Ljava/lang/String;)Ljava/lang/String;",
+            &[JValue::Object(&name)],
+        )?
+        .l()?,
+    );
+    let message = if message.is_null() {
+        None
+    } else {
+        Some(env.get_string(&message)?.into())
+    };
+
+    let name = JObject::from(env.new_string(EXTRA_INTENT)?);
+    let confirm = env
+        .call_method(
+            intent,
+            "getParcelableExtra",
+            "(Ljava/lang/String;)Landroid/os/Parcelable;",
+            &[JValue::Object(&name)],
+        )?
+        .l()?;
+    let confirm = if confirm.is_null() {
+        None
+    } else {
+        Some(env.new_global_ref(confirm)?)
+    };
+
+    log::info!("{action} {request}: status {code} {message:?}");
+    RECEIVED.lock().insert(
+        (action, request),
+        Status {
+            code,
+            message,
+            confirm,
+        },
+    );
+    Ok(())
+}
+
+/// `Build.VERSION.SDK_INT`.
+fn sdk(env: &mut JNIEnv) -> Result<i32> {
+    Ok(env
+        .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")?
+        .i()?)
+}
@@ -1,17 +1,131 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
+use anyhow::{bail, ensure, Context};
+use itertools::Itertools;
+use reqwest::Url;
 use serde::{
     de::{self, value::StrDeserializer, IntoDeserializer},
-    Deserialize, Deserializer, Serialize,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
 
+use crate::sync::SyncStatus;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Repo {
     #[serde(rename = "repo")]
     pub meta: Meta,
-    #[serde(rename = "application")]
+    /// Not persisted with the rest of the repo, see [crate::cache].
+    #[serde(rename = "application", skip_serializing)]
     pub apps: Option<Vec<App>>,
+    /// The index-v2 the apps were read from, kept to apply the next diff against.
+    #[serde(skip)]
+    pub raw_v2: Option<Arc<serde_json::Value>>,
+    #[serde(default)]
+    pub config: RepoConfig,
+    #[serde(skip)]
+    pub status: SyncStatus,
+}
+
+impl Repo {
+    /// A repo that hasn't been synced yet, pinned to `fingerprint` if there is one.
+    pub fn new(url: &str, fingerprint: Option<String>) -> Self {
+        let url = url.trim_end_matches('/');
+        Self {
+            meta: Meta {
+                icon: String::new(),
+                max_age: String::new(),
+                name: url.split_once("://").map_or(url, |(_, rest)| rest).into(),
+                pub_key: String::new(),
+                timestamp: 0,
+                url: Some(url.into()),
+                version: None,
+                desc: None,
+                mirrors: None,
+                fingerprint,
+            },
+            apps: None,
+            raw_v2: None,
+            config: RepoConfig {
+                url: Some(url.into()),
+                ..Default::default()
+            },
+            status: Default::default(),
+        }
+    }
+
+    /// Identifies the repo on disk, see [crate::cache].
+    ///
+    /// It's derived from the url the repo was added with, which stays the same when a synced
+    /// index has another address.
+    pub fn cache_key(&self) -> String {
+        let id = self
+            .config
+            .url
+            .as_deref()
+            .or(self.meta.url.as_deref())
+            .unwrap_or(&self.meta.name);
+        hex::encode(&Sha256::digest(id.trim_end_matches('/'))[..8])
+    }
+
+    /// Urls to download the index from, in the order they should be tried.
+    pub fn mirrors(&self) -> Vec<String> {
+        self.config
+            .preferred_mirrors
+            .iter()
+            .chain(&self.config.last_mirror)
+            .chain(&self.meta.url)
+            .chain(&self.config.url)
+            .chain(self.meta.mirrors.iter().flatten())
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unique()
+            .collect()
+    }
+
+    /// Url of a file in the repo, like an icon, on the mirror that's tried first.
+    pub fn file_url(&self, path: &str) -> Option<String> {
+        let mirror = self.mirrors().into_iter().next()?;
+        Some(format!("{mirror}/{}", path.trim_start_matches('/')))
+    }
+}
+
+/// What we keep about a repo besides its index, carried over when a new index replaces it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RepoConfig {
+    /// The url the repo was added with, [Meta::url] is replaced with the address in the index.
+    pub url: Option<String>,
+    /// Disabled repos aren't synced and their apps aren't shown.
+    pub enabled: bool,
+    /// Mirrors the user wants tried before anything else, in order.
+    pub preferred_mirrors: Vec<String>,
+    /// The url the index was last downloaded from.
+    pub last_mirror: Option<String>,
+    pub validators: Option<Validators>,
+    /// Seconds since the epoch of the last successful sync.
+    pub last_synced: Option<u64>,
+}
+
+impl Default for RepoConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            enabled: true,
+            preferred_mirrors: Vec::new(),
+            last_mirror: None,
+            validators: None,
+            last_synced: None,
+        }
+    }
+}
+
+/// HTTP cache validators of the index jar the current index came from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Validators {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 
 impl Default for Repo {
@@ -26,14 +140,21 @@ impl Default for Repo {
                 url: Some("https://f-droid.org/repo/".into()),
                 version: None,
                 desc: None,
-                mirrors: None
+                mirrors: None,
+                fingerprint: None,
+            },
+            apps: None,
+            raw_v2: None,
+            config: RepoConfig {
+                url: Some("https://f-droid.org/repo".into()),
+                ..Default::default()
             },
-            apps: None
+            status: Default::default(),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Meta {
     #[serde(rename = "@icon")]
     pub icon: String,
@@ -53,6 +174,90 @@ pub struct Meta {
     pub desc: Option<String>,
     #[serde(rename = "mirror")]
     pub mirrors: Option<Vec<String>>,
+    /// SHA-256 of the signing certificate, used to pin a repo before its `pub_key` is known.
+    #[serde(rename = "@fingerprint", default)]
+    pub fingerprint: Option<String>,
+}
+
+impl Meta {
+    /// Whether the index is older than the `max_age` (in days) the repo asks clients to enforce.
+    pub fn is_stale(&self, now: u64) -> bool {
+        match self.max_age.parse::<u64>() {
+            Ok(days) if days > 0 && self.timestamp > 0 => {
+                now.saturating_sub(self.timestamp) > days * 24 * 60 * 60
+            }
+            _ => false,
+        }
+    }
+
+    /// SHA-256 of the signing certificate, from the pinned key if we have it.
+    pub fn signer_fingerprint(&self) -> Option<String> {
+        match hex::decode(&self.pub_key) {
+            Ok(cert) if !cert.is_empty() => Some(hex::encode(Sha256::digest(cert))),
+            _ => self
+                .fingerprint
+                .as_deref()
+                .and_then(|f| normalize_fingerprint(f).ok()),
+        }
+    }
+
+    /// Whether `cert` (DER encoded) is the certificate this repo is pinned to.
+    ///
+    /// A repo without `pub_key` or `fingerprint` trusts the first certificate it sees.
+    pub fn trusts(&self, cert: &[u8]) -> bool {
+        if !self.pub_key.is_empty() {
+            self.pub_key.eq_ignore_ascii_case(&hex::encode(cert))
+        } else if let Some(fingerprint) = &self.fingerprint {
+            normalize_fingerprint(fingerprint).is_ok_and(|f| f == hex::encode(Sha256::digest(cert)))
+        } else {
+            true
+        }
+    }
+}
+
+/// Parses a shared repo link into its url and pinned fingerprint, like
+/// `fdroidrepos://example.org/fdroid/repo?fingerprint=ABCD...` or the same with `https://`.
+///
+/// `fdroidrepos://` stands for `https://`, `fdroidrepo://` for `http://`.
+pub fn parse_repo_link(link: &str) -> anyhow::Result<(String, Option<String>)> {
+    let mut url = Url::parse(link.trim()).context("not a valid link")?;
+    let scheme = match url.scheme() {
+        "fdroidrepos" | "https" => "https",
+        "fdroidrepo" | "http" => "http",
+        scheme => bail!("unsupported link scheme {scheme}://"),
+    };
+    if url.host_str().is_none_or(str::is_empty) {
+        bail!("the link has no host");
+    }
+
+    let fingerprint = url
+        .query_pairs()
+        .find(|(key, _)| key.eq_ignore_ascii_case("fingerprint"))
+        .map(|(_, value)| normalize_fingerprint(&value))
+        .transpose()?;
+
+    url.set_query(None);
+    url.set_fragment(None);
+    // The scheme of special urls (http) can't be changed to or from a non special one (fdroidrepo)
+    let rest = &url.as_str()[url.scheme().len()..];
+    let url = format!("{scheme}{rest}").trim_end_matches('/').to_string();
+
+    Ok((url, fingerprint))
+}
+
+/// Lowercases a fingerprint and strips the separators it's often shared with (`AB:CD`, `AB CD`,
+/// `AB-CD`), anything else that isn't the 64 hex digits of a SHA-256 is an error.
+pub fn normalize_fingerprint(fingerprint: &str) -> anyhow::Result<String> {
+    let fingerprint: String = fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, ':' | '-'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    ensure!(
+        fingerprint.len() == 64 && fingerprint.chars().all(|c| c.is_ascii_hexdigit()),
+        "a fingerprint is the SHA-256 of the signing certificate, 64 hex digits"
+    );
+    Ok(fingerprint)
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -65,13 +270,21 @@ pub struct App {
     pub name: String,
     // 80 limit
     pub summary: String,
+    /// File name in the repo's `icons/` directory, or a path relative to the repo if it has a `/`.
     pub icon: Option<String>,
     // limit 4000
     pub desc: String,
     pub license: String,
-    #[serde(deserialize_with = "split_by_comma")]
+    #[serde(deserialize_with = "split_by_comma", serialize_with = "join_by_comma")]
     pub categories: Vec<Category>,
     pub category: Category,
+    #[serde(
+        rename = "antifeatures",
+        default,
+        deserialize_with = "split_by_comma",
+        serialize_with = "join_by_comma"
+    )]
+    pub anti_features: Vec<AntiFeature>,
     pub web: Option<String>,
     pub source: Option<String>,
     pub tracker: Option<String>,
@@ -88,9 +301,33 @@ pub struct App {
     pub market_version_code: u32,
     #[serde(rename = "package")]
     pub packages: Vec<Package>,
+    // Only provided by index-v1 and later, paths are relative to the repo url
+    #[serde(default)]
+    pub whats_new: Option<String>,
+    #[serde(default)]
+    pub feature_graphic: Option<String>,
+    #[serde(default)]
+    pub screenshots: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+impl App {
+    /// Path of the icon relative to the repo url.
+    pub fn icon_path(&self) -> Option<String> {
+        let icon = self.icon.as_ref()?;
+        Some(if icon.contains('/') {
+            icon.clone()
+        } else {
+            format!("icons/{icon}")
+        })
+    }
+
+    /// Whether the app has any of the `hidden` anti-features.
+    pub fn hidden_by(&self, hidden: &BTreeSet<AntiFeature>) -> bool {
+        self.anti_features.iter().any(|a| hidden.contains(a))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Package {
     pub version: String,
     #[serde(rename = "versioncode")]
@@ -99,26 +336,63 @@ pub struct Package {
     pub apk_name: String,
     #[serde(rename = "srcname")]
     pub src_name: Option<String>,
-    pub hash: String,
+    pub hash: FileHash,
     pub size: u32,
     #[serde(rename = "sdkver")]
     pub sdk_version: Option<u32>,
     #[serde(rename = "targetSdkVersion")]
     pub target_sdk_version: Option<u32>,
     pub added: String,
+    /// MD5 of the hex encoded signing certificate, see [crate::apk::fdroid_sig].
     pub sig: Option<String>,
+    /// SHA-256 of the signing certificate, index-v2 lists this instead of `sig`.
+    #[serde(default)]
+    pub signer: Option<String>,
     pub permissions: Option<Vec<String>>,
+    /// ABIs the apk has native code for, any ABI will do if there are none.
+    #[serde(
+        rename = "nativecode",
+        alias = "native_code",
+        default,
+        deserialize_with = "list_or_split_by_comma"
+    )]
     pub native_code: Option<Vec<String>>,
+    /// Features the apk uses, like `android.hardware.camera`.
+    #[serde(default, deserialize_with = "list_or_split_by_comma")]
     pub features: Option<Vec<String>>,
 }
 
+/// Digest of the apk, like `<hash type="sha256">…</hash>` in `index.xml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileHash {
+    /// Name of the algorithm, only `sha256` and `sha512` can be checked.
+    #[serde(rename = "@type", default = "sha256")]
+    pub kind: String,
+    /// Hex encoded digest.
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+impl FileHash {
+    pub fn sha256(value: String) -> Self {
+        Self {
+            kind: sha256(),
+            value,
+        }
+    }
+}
+
+fn sha256() -> String {
+    "sha256".to_string()
+}
+
 /// The [Category](https://f-droid.org/en/docs/Build_Metadata_Reference/#Categories) of the package.
 /// Preferably a predefined category like [Category::Games] or [Category::Money], but can also
 /// be a custom Category (see [Category::Custom]).
 ///
 /// During Deserialization, if a categories does not match any category defined in the enum,
 /// it automatically gets assigned to [Category::Custom]
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Category {
     Connectivity,
     Development,
@@ -143,6 +417,45 @@ pub enum Category {
     Custom(String),
 }
 
+impl Category {
+    /// The name used in the index.
+    pub fn name(&self) -> &str {
+        match self {
+            Category::Connectivity => "Connectivity",
+            Category::Development => "Development",
+            Category::Games => "Games",
+            Category::Graphics => "Graphics",
+            Category::Internet => "Internet",
+            Category::Money => "Money",
+            Category::Multimedia => "Multimedia",
+            Category::Navigation => "Navigation",
+            Category::PhoneSms => "Phone & SMS",
+            Category::Reading => "Reading",
+            Category::ScienceEducation => "Science & Education",
+            Category::Security => "Security",
+            Category::SportsHealth => "Sports & Health",
+            Category::System => "System",
+            Category::Theming => "Theming",
+            Category::Time => "Time",
+            Category::Writing => "Writing",
+            Category::Custom(name) => name,
+        }
+    }
+}
+
+impl AsRef<str> for Category {
+    fn as_ref(&self) -> &str {
+        self.name()
+    }
+}
+
+impl From<&str> for Category {
+    fn from(name: &str) -> Self {
+        let deserializer: StrDeserializer<de::value::Error> = name.into_deserializer();
+        Category::deserialize(deserializer).unwrap_or_else(|_| Category::Custom(name.to_string()))
+    }
+}
+
 /// The type of repository - for automatic building from source. If this is not specified, automatic building is disabled for this application.
 ///
 /// See [documentation](https://f-droid.org/en/docs/Build_Metadata_Reference/#RepoType)
@@ -165,7 +478,9 @@ pub enum RepoType {
 /// Features of the application that hinders the user.
 ///
 /// See [anti-feature](https://en.wiktionary.org/wiki/anti-feature)
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// Like [Category], anti-features that are not in the enum are kept as [AntiFeature::Custom].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AntiFeature {
     /// The application contains advertising
     Ads,
@@ -190,20 +505,112 @@ pub enum AntiFeature {
     ApplicationDebuggable,
     /// Upstream source for this app is no longer available. Either the app went commercial, the repo was dropped, or it has moved to a location currently unknown to us. This usually means there won’t be further updates unless the source reappears.
     NoSourceSince,
+    Custom(String),
 }
 
-fn split_by_comma<'de, D>(deserializer: D) -> Result<Vec<Category>, D::Error>
+impl AntiFeature {
+    /// Every anti-feature but [AntiFeature::Custom].
+    pub const ALL: [AntiFeature; 11] = [
+        AntiFeature::Ads,
+        AntiFeature::Tracking,
+        AntiFeature::NonFreeNet,
+        AntiFeature::NonFreeAdd,
+        AntiFeature::NonFreeDep,
+        AntiFeature::Nsfw,
+        AntiFeature::UpstreamNonFree,
+        AntiFeature::NonFreeAssets,
+        AntiFeature::KnownVuln,
+        AntiFeature::ApplicationDebuggable,
+        AntiFeature::NoSourceSince,
+    ];
+
+    /// The name used in the index.
+    pub fn name(&self) -> &str {
+        match self {
+            AntiFeature::Ads => "Ads",
+            AntiFeature::Tracking => "Tracking",
+            AntiFeature::NonFreeNet => "NonFreeNet",
+            AntiFeature::NonFreeAdd => "NonFreeAdd",
+            AntiFeature::NonFreeDep => "NonFreeDep",
+            AntiFeature::Nsfw => "NSFW",
+            AntiFeature::UpstreamNonFree => "UpstreamNonFree",
+            AntiFeature::NonFreeAssets => "NonFreeAssets",
+            AntiFeature::KnownVuln => "KnownVuln",
+            AntiFeature::ApplicationDebuggable => "ApplicationDebuggable",
+            AntiFeature::NoSourceSince => "NoSourceSince",
+            AntiFeature::Custom(name) => name,
+        }
+    }
+
+    /// A short explanation for users.
+    pub fn description(&self) -> &str {
+        match self {
+            AntiFeature::Ads => "Contains advertising",
+            AntiFeature::Tracking => "Tracks or leaks your activity",
+            AntiFeature::NonFreeNet => "Promotes or depends on a non-free network service",
+            AntiFeature::NonFreeAdd => "Promotes non-free add-ons",
+            AntiFeature::NonFreeDep => "Depends on a non-free app",
+            AntiFeature::Nsfw => "Contains content that is not safe for work",
+            AntiFeature::UpstreamNonFree => "The upstream source code is not entirely free",
+            AntiFeature::NonFreeAssets => "Contains non-free assets",
+            AntiFeature::KnownVuln => "Has known security vulnerabilities",
+            AntiFeature::ApplicationDebuggable => "Is built for debugging",
+            AntiFeature::NoSourceSince => "The source code is no longer available",
+            AntiFeature::Custom(name) => name,
+        }
+    }
+}
+
+impl AsRef<str> for AntiFeature {
+    fn as_ref(&self) -> &str {
+        self.name()
+    }
+}
+
+impl From<&str> for AntiFeature {
+    fn from(name: &str) -> Self {
+        let deserializer: StrDeserializer<de::value::Error> = name.into_deserializer();
+        AntiFeature::deserialize(deserializer)
+            .unwrap_or_else(|_| AntiFeature::Custom(name.to_string()))
+    }
+}
+
+fn split_by_comma<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
 where
     D: Deserializer<'de>,
+    T: for<'a> From<&'a str>,
 {
     let s: String = String::deserialize(deserializer)?;
-    s.split(',')
-        .map(|s| {
-            let trimmed = s.trim();
-            let deserializer: StrDeserializer<D::Error> = trimmed.into_deserializer();
-            Category::deserialize(deserializer).map_err(de::Error::custom)
-        })
-        .collect::<Result<Vec<_>, _>>()
+    Ok(s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(T::from)
+        .collect())
+}
+
+fn join_by_comma<S, T>(items: &[T], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: AsRef<str>,
+{
+    serializer.serialize_str(&items.iter().map(AsRef::as_ref).join(","))
+}
+
+/// `index.xml` separates lists with commas, the json cache has them as arrays.
+fn list_or_split_by_comma<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let list: Option<Vec<String>> = Option::deserialize(deserializer)?;
+    Ok(list.map(|items| {
+        items
+            .iter()
+            .flat_map(|item| item.split(','))
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    }))
 }
 
 pub fn deserialize_mutex<'de, D>(deserializer: D) -> Result<Vec<Arc<Mutex<Repo>>>, D::Error>
@@ -216,3 +623,89 @@ where
         .map(|repo| Arc::new(Mutex::new(repo)))
         .collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "43238d512c1e5eb2d6569f4a3afbf5523418b82e0a3ed1552770abb9a9c9ccab";
+
+    #[test]
+    fn fdroid_links_stand_for_http() {
+        let link = format!("fdroidrepos://f-droid.org/repo?fingerprint={FINGERPRINT}");
+        assert_eq!(
+            parse_repo_link(&link).unwrap(),
+            (
+                "https://f-droid.org/repo".to_string(),
+                Some(FINGERPRINT.to_string())
+            )
+        );
+        assert_eq!(
+            parse_repo_link("fdroidrepo://example.org:8080/fdroid/repo/").unwrap(),
+            ("http://example.org:8080/fdroid/repo".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn web_links_keep_their_scheme() {
+        let link = format!("  https://example.org/fdroid/repo/?FINGERPRINT={FINGERPRINT}#top ");
+        assert_eq!(
+            parse_repo_link(&link).unwrap(),
+            (
+                "https://example.org/fdroid/repo".to_string(),
+                Some(FINGERPRINT.to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn bad_links_are_rejected() {
+        for link in [
+            "f-droid.org/repo",
+            "ftp://f-droid.org/repo",
+            "edroid://updates",
+            "fdroidrepos:///repo",
+            "https://f-droid.org/repo?fingerprint=1234",
+        ] {
+            assert!(parse_repo_link(link).is_err(), "{link}");
+        }
+    }
+
+    #[test]
+    fn fingerprints_are_normalized() {
+        let upper = FINGERPRINT.to_uppercase();
+        let colons = upper
+            .as_bytes()
+            .chunks(2)
+            .map(|c| std::str::from_utf8(c).unwrap());
+        for fingerprint in [
+            upper.clone(),
+            colons.clone().collect::<Vec<_>>().join(":"),
+            colons.clone().collect::<Vec<_>>().join(" "),
+            colons.collect::<Vec<_>>().join("-"),
+        ] {
+            assert_eq!(normalize_fingerprint(&fingerprint).unwrap(), FINGERPRINT);
+        }
+    }
+
+    #[test]
+    fn malformed_fingerprints_are_rejected() {
+        for fingerprint in [
+            "",
+            &FINGERPRINT[1..],
+            &format!("{FINGERPRINT}00"),
+            &format!("{}g", &FINGERPRINT[1..]),
+            &format!("sha256:{}", &FINGERPRINT[7..]),
+        ] {
+            assert!(normalize_fingerprint(fingerprint).is_err(), "{fingerprint}");
+        }
+    }
+
+    #[test]
+    fn a_malformed_fingerprint_trusts_nothing() {
+        let mut meta = Repo::new("https://example.org/repo", None).meta;
+        meta.fingerprint = Some("not a fingerprint".to_string());
+        assert!(!meta.trusts(b"certificate"));
+        assert_eq!(meta.signer_fingerprint(), None);
+    }
+}
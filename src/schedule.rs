@@ -0,0 +1,293 @@
+//! Syncing the repos in the background on a schedule.
+//!
+//! The user picks how often and under which conditions, [Conditions] tells what the network and
+//! battery are like right now.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::repo::Repo;
+
+/// How often the scheduler looks for repos to sync.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Battery level, in percent, below which a device not charging doesn't sync.
+const LOW_BATTERY: u32 = 15;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SyncSchedule {
+    pub enabled: bool,
+    /// Hours between two syncs of a repo.
+    pub interval_hours: u32,
+    /// Sync on a metered network, like mobile data.
+    pub on_metered: bool,
+    /// Sync only while the device is charging.
+    pub only_charging: bool,
+}
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_hours: 24,
+            on_metered: false,
+            only_charging: false,
+        }
+    }
+}
+
+impl SyncSchedule {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(u64::from(self.interval_hours.max(1)) * 60 * 60)
+    }
+
+    /// Why the repos can't be synced now, if they can't.
+    pub fn blocked_by(&self, conditions: &dyn Conditions) -> Result<Option<&'static str>> {
+        if !self.enabled {
+            return Ok(Some("scheduled syncs are off"));
+        }
+        if !self.on_metered && conditions.is_metered()? {
+            return Ok(Some("the network is metered"));
+        }
+
+        let charging = conditions.is_charging()?;
+        if self.only_charging && !charging {
+            return Ok(Some("the device isn't charging"));
+        }
+        if !charging && conditions.battery_level()?.is_some_and(|l| l < LOW_BATTERY) {
+            return Ok(Some("the battery is low"));
+        }
+        Ok(None)
+    }
+
+    /// Whether `repo` wasn't synced for longer than the interval, or never.
+    pub fn is_due(&self, repo: &Repo) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        repo.config
+            .last_synced
+            .is_none_or(|last| now.saturating_sub(last) >= self.interval().as_secs())
+    }
+}
+
+/// The state of the device the schedule depends on.
+pub trait Conditions: Send + Sync {
+    /// Whether the active network costs money per byte, or is limited.
+    fn is_metered(&self) -> Result<bool>;
+
+    fn is_charging(&self) -> Result<bool>;
+
+    /// Percent of the battery left, `None` without a battery.
+    fn battery_level(&self) -> Result<Option<u32>>;
+}
+
+/// Network and battery from the ConnectivityManager and BatteryManager.
+#[cfg(target_os = "android")]
+pub struct AndroidConditions;
+
+#[cfg(target_os = "android")]
+impl AndroidConditions {
+    fn with_service<T>(
+        name: &str,
+        f: impl FnOnce(&mut jni::JNIEnv, &jni::objects::JObject) -> Result<T>,
+    ) -> Result<T> {
+        use jni::objects::{JObject, JValue};
+
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { jni::JavaVM::from_raw(ctx.vm().cast()) }?;
+        let mut env = vm.attach_current_thread()?;
+
+        let activity = unsafe { JObject::from_raw(ctx.context().cast()) };
+        let service_name = JObject::from(env.new_string(name)?);
+        let service = env
+            .call_method(
+                activity,
+                "getSystemService",
+                "(Ljava/lang/String;)Ljava/lang/Object;",
+                &[JValue::Object(&service_name)],
+            )?
+            .l()?;
+        anyhow::ensure!(!service.is_null(), "no {name} service");
+        f(&mut env, &service)
+    }
+}
+
+#[cfg(target_os = "android")]
+impl Conditions for AndroidConditions {
+    fn is_metered(&self) -> Result<bool> {
+        Self::with_service("connectivity", |env, connectivity| {
+            Ok(env
+                .call_method(connectivity, "isActiveNetworkMetered", "()Z", &[])?
+                .z()?)
+        })
+    }
+
+    fn is_charging(&self) -> Result<bool> {
+        Self::with_service("batterymanager", |env, battery| {
+            Ok(env.call_method(battery, "isCharging", "()Z", &[])?.z()?)
+        })
+    }
+
+    fn battery_level(&self) -> Result<Option<u32>> {
+        use jni::objects::JValue;
+
+        /// `BatteryManager.BATTERY_PROPERTY_CAPACITY`.
+        const CAPACITY: i32 = 4;
+
+        Self::with_service("batterymanager", |env, battery| {
+            let level = env
+                .call_method(battery, "getIntProperty", "(I)I", &[JValue::Int(CAPACITY)])?
+                .i()?;
+            // Integer.MIN_VALUE if there's no battery
+            Ok(u32::try_from(level).ok())
+        })
+    }
+}
+
+/// A desktop, on an unmetered network and plugged in.
+pub struct DesktopConditions;
+
+impl Conditions for DesktopConditions {
+    fn is_metered(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn is_charging(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn battery_level(&self) -> Result<Option<u32>> {
+        Ok(None)
+    }
+}
+
+/// The conditions of the platform.
+pub fn conditions() -> Box<dyn Conditions> {
+    #[cfg(target_os = "android")]
+    return Box::new(AndroidConditions);
+
+    #[cfg(not(target_os = "android"))]
+    Box::new(DesktopConditions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeConditions {
+        metered: bool,
+        charging: bool,
+        battery: Option<u32>,
+    }
+
+    impl Conditions for FakeConditions {
+        fn is_metered(&self) -> Result<bool> {
+            Ok(self.metered)
+        }
+
+        fn is_charging(&self) -> Result<bool> {
+            Ok(self.charging)
+        }
+
+        fn battery_level(&self) -> Result<Option<u32>> {
+            Ok(self.battery)
+        }
+    }
+
+    const ON_BATTERY: FakeConditions = FakeConditions {
+        metered: false,
+        charging: false,
+        battery: Some(80),
+    };
+
+    fn synced_hours_ago(hours: u64) -> Repo {
+        let mut repo = Repo::new("https://example.org/repo", None);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        repo.config.last_synced = Some(now.as_secs() - hours * 60 * 60);
+        repo
+    }
+
+    #[test]
+    fn repos_are_due_after_the_interval() {
+        let schedule = SyncSchedule::default();
+        assert!(schedule.is_due(&Repo::new("https://example.org/repo", None)));
+        assert!(!schedule.is_due(&synced_hours_ago(0)));
+        assert!(!schedule.is_due(&synced_hours_ago(23)));
+        assert!(schedule.is_due(&synced_hours_ago(24)));
+
+        let hourly = SyncSchedule {
+            interval_hours: 0,
+            ..Default::default()
+        };
+        assert!(!hourly.is_due(&synced_hours_ago(0)));
+        assert!(hourly.is_due(&synced_hours_ago(1)));
+    }
+
+    #[test]
+    fn syncs_wait_for_the_right_conditions() {
+        let schedule = SyncSchedule::default();
+        assert_eq!(schedule.blocked_by(&ON_BATTERY).unwrap(), None);
+        assert_eq!(schedule.blocked_by(&DesktopConditions).unwrap(), None);
+
+        let off = SyncSchedule {
+            enabled: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            off.blocked_by(&DesktopConditions).unwrap(),
+            Some("scheduled syncs are off")
+        );
+
+        let metered = FakeConditions {
+            metered: true,
+            ..ON_BATTERY
+        };
+        assert_eq!(
+            schedule.blocked_by(&metered).unwrap(),
+            Some("the network is metered")
+        );
+        let on_metered = SyncSchedule {
+            on_metered: true,
+            ..Default::default()
+        };
+        assert_eq!(on_metered.blocked_by(&metered).unwrap(), None);
+
+        let only_charging = SyncSchedule {
+            only_charging: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            only_charging.blocked_by(&ON_BATTERY).unwrap(),
+            Some("the device isn't charging")
+        );
+    }
+
+    #[test]
+    fn low_batteries_only_sync_while_charging() {
+        let schedule = SyncSchedule::default();
+        let low = FakeConditions {
+            battery: Some(LOW_BATTERY - 1),
+            ..ON_BATTERY
+        };
+        assert_eq!(
+            schedule.blocked_by(&low).unwrap(),
+            Some("the battery is low")
+        );
+
+        let charging = FakeConditions {
+            charging: true,
+            ..low
+        };
+        assert_eq!(schedule.blocked_by(&charging).unwrap(), None);
+
+        let no_battery = FakeConditions {
+            battery: None,
+            ..ON_BATTERY
+        };
+        assert_eq!(schedule.blocked_by(&no_battery).unwrap(), None);
+    }
+}
@@ -0,0 +1,372 @@
+//! In-memory full-text search over the apps of all enabled repos.
+//!
+//! Words of the name, package id, summary and description go into an inverted index, each with
+//! the weight of the best field it appears in. A query matches apps that have every query word,
+//! exactly, as a prefix (for search as you type) or with a typo or two for longer words. Typos
+//! are only looked for after the first letter, which keeps the words to compare with few.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Bound::{Excluded, Unbounded},
+};
+
+use crate::{
+    description::{self, Block},
+    repo::{AntiFeature, Repo},
+};
+
+const NAME: f32 = 10.0;
+const ID: f32 = 5.0;
+const SUMMARY: f32 = 3.0;
+const DESCRIPTION: f32 = 1.0;
+
+const PREFIX_MATCH: f32 = 0.8;
+const FUZZY_MATCH: f32 = 0.5;
+
+/// The parts of an app that are searched, copied out of the repos so the index can be built
+/// without holding on to them.
+pub struct Searchable {
+    /// [Repo::cache_key] of the repo the app is from.
+    pub repo: String,
+    pub id: String,
+    pub name: String,
+    pub summary: String,
+    pub desc: String,
+}
+
+impl Searchable {
+    /// The apps of all enabled repos, except those with `hidden` anti-features.
+    pub fn collect(repos: &[Repo], hidden: &BTreeSet<AntiFeature>) -> Vec<Self> {
+        repos
+            .iter()
+            .filter(|r| r.config.enabled)
+            .flat_map(|repo| {
+                let key = repo.cache_key();
+                repo.apps
+                    .iter()
+                    .flatten()
+                    .filter(|app| !app.hidden_by(hidden))
+                    .map(move |app| Self {
+                        repo: key.clone(),
+                        id: app.id.clone(),
+                        name: app.name.clone(),
+                        summary: app.summary.clone(),
+                        desc: app.desc.clone(),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// An app in the index.
+pub struct Document {
+    pub repo: String,
+    pub id: String,
+    name: String,
+}
+
+pub struct Hit<'a> {
+    pub document: &'a Document,
+    pub score: f32,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    documents: Vec<Document>,
+    /// Word to the documents it appears in and the weight of the best field it's in there.
+    terms: BTreeMap<String, Vec<(usize, f32)>>,
+    /// The words by their first letter and their length in letters, to find typos in.
+    by_start: BTreeSet<(char, usize, String)>,
+}
+
+impl SearchIndex {
+    pub fn build(apps: Vec<Searchable>) -> Self {
+        let mut index = Self::default();
+        for app in apps {
+            let doc = index.documents.len();
+            let mut weights: HashMap<String, f32> = HashMap::new();
+            let mut add = |text: &str, weight: f32| {
+                for word in words(text) {
+                    let best = weights.entry(word).or_default();
+                    *best = best.max(weight);
+                }
+            };
+            add(&app.name, NAME);
+            add(&app.id, ID);
+            add(&app.summary, SUMMARY);
+            add(&plain_text(&app.desc), DESCRIPTION);
+
+            for (word, weight) in weights {
+                index.terms.entry(word).or_default().push((doc, weight));
+            }
+            index.documents.push(Document {
+                repo: app.repo,
+                id: app.id,
+                name: app.name,
+            });
+        }
+        index.by_start = index
+            .terms
+            .keys()
+            .filter_map(|term| Some((term.chars().next()?, term.chars().count(), term.clone())))
+            .collect();
+        index
+    }
+
+    /// The best `limit` apps having all the words of `query`, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<Hit<'_>> {
+        let query: Vec<String> = words(query).collect();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scores: Option<HashMap<usize, f32>> = None;
+        for word in &query {
+            let mut word_scores: HashMap<usize, f32> = HashMap::new();
+            for (term, quality) in self.matching_terms(word) {
+                for &(doc, weight) in &self.terms[term] {
+                    let score = word_scores.entry(doc).or_default();
+                    *score = score.max(weight * quality);
+                }
+            }
+
+            // Every word has to match
+            scores = Some(match scores {
+                None => word_scores,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(doc, score)| Some((doc, score + word_scores.get(&doc)?)))
+                    .collect(),
+            });
+        }
+
+        let mut hits: Vec<Hit> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(doc, score)| Hit {
+                document: &self.documents[doc],
+                score,
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.document.name.len().cmp(&b.document.name.len()))
+                .then_with(|| a.document.name.cmp(&b.document.name))
+        });
+        hits.truncate(limit);
+        hits
+    }
+
+    /// Terms of the index that `word` stands for, with how well they match it.
+    fn matching_terms<'a>(&'a self, word: &str) -> Vec<(&'a str, f32)> {
+        let mut matching: Vec<_> = self
+            .terms
+            .get_key_value(word)
+            .map(|(term, _)| (term.as_str(), 1.0))
+            .into_iter()
+            .collect();
+        matching.extend(
+            self.terms
+                .range::<str, _>((Excluded(word), Unbounded))
+                .map(|(term, _)| term.as_str())
+                .take_while(|term| term.starts_with(word))
+                .map(|term| (term, PREFIX_MATCH)),
+        );
+
+        let len = word.chars().count();
+        let max_typos = match len {
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        };
+        if let (Some(first), true) = (word.chars().next(), max_typos > 0) {
+            let shortest = (first, len - max_typos, String::new());
+            let longest = (first, len + max_typos + 1, String::new());
+            matching.extend(
+                self.by_start
+                    .range(shortest..longest)
+                    .map(|(_, _, term)| term.as_str())
+                    .filter(|term| {
+                        !term.starts_with(word) && within_distance(word, term, max_typos)
+                    })
+                    .map(|term| (term, FUZZY_MATCH)),
+            );
+        }
+        matching
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+}
+
+/// Lowercase words of `text`, also splitting package ids like `org.example.app`.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// The text of a description without its markup.
+fn plain_text(desc: &str) -> String {
+    description::parse(desc)
+        .iter()
+        .flat_map(|block| match block {
+            Block::Paragraph(spans) | Block::ListItem { spans, .. } => spans,
+        })
+        .map(|span| span.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether `a` can be turned into `b` with at most `max` insertions, deletions, substitutions or
+/// swaps of two neighbouring letters.
+fn within_distance(a: &str, b: &str, max: usize) -> bool {
+    if a.len().abs_diff(b.len()) > max * 4 {
+        return false;
+    }
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return false;
+    }
+
+    let mut before_previous: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 0..a.len() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for j in 0..b.len() {
+            current[j + 1] = (previous[j] + usize::from(a[i] != b[j]))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+            if i > 0 && j > 0 && a[i] == b[j - 1] && a[i - 1] == b[j] {
+                current[j + 1] = current[j + 1].min(before_previous[j - 1] + 1);
+            }
+        }
+        // Distances only grow from here
+        if current.iter().all(|&d| d > max) {
+            return false;
+        }
+        before_previous = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()] <= max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(id: &str, name: &str, summary: &str, desc: &str) -> Searchable {
+        Searchable {
+            repo: "repo".to_string(),
+            id: id.to_string(),
+            name: name.to_string(),
+            summary: summary.to_string(),
+            desc: desc.to_string(),
+        }
+    }
+
+    fn index() -> SearchIndex {
+        SearchIndex::build(vec![
+            app(
+                "org.fdroid.fdroid",
+                "F-Droid",
+                "The app store that respects freedom and privacy",
+                "<p>Find, install and <b>update</b> free software.</p>",
+            ),
+            app(
+                "org.mozilla.fennec_fdroid",
+                "Fennec F-Droid",
+                "Privacy oriented web browser",
+                "<p>Based on Firefox.</p>",
+            ),
+            app(
+                "de.danoeh.antennapod",
+                "AntennaPod",
+                "Easy-to-use, flexible podcast manager",
+                "<p>Keep your podcasts up to date, works with any <i>browser</i> link.</p>",
+            ),
+            app(
+                "org.example.notes",
+                "Notes",
+                "Take notes",
+                "<p>Plain notes.</p>",
+            ),
+        ])
+    }
+
+    fn ids(index: &SearchIndex, query: &str) -> Vec<String> {
+        index
+            .search(query, 10)
+            .into_iter()
+            .map(|hit| hit.document.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn names_weigh_more_than_descriptions() {
+        assert_eq!(
+            ids(&index(), "browser"),
+            ["org.mozilla.fennec_fdroid", "de.danoeh.antennapod"]
+        );
+        // Shorter names first when the score is the same
+        assert_eq!(
+            ids(&index(), "fdroid"),
+            ["org.fdroid.fdroid", "org.mozilla.fennec_fdroid"]
+        );
+    }
+
+    #[test]
+    fn every_word_has_to_match() {
+        assert_eq!(
+            ids(&index(), "privacy browser"),
+            ["org.mozilla.fennec_fdroid"]
+        );
+        assert!(ids(&index(), "privacy podcast").is_empty());
+        assert!(ids(&index(), " .,- ").is_empty());
+    }
+
+    #[test]
+    fn words_match_as_prefixes() {
+        assert_eq!(ids(&index(), "ant"), ["de.danoeh.antennapod"]);
+        assert_eq!(ids(&index(), "Pod"), ["de.danoeh.antennapod"]);
+        // An exact match beats a longer word starting the same
+        let index = SearchIndex::build(vec![
+            app("a", "Note taking", "", ""),
+            app("b", "Notes", "", ""),
+        ]);
+        assert_eq!(ids(&index, "note"), ["a", "b"]);
+    }
+
+    #[test]
+    fn longer_words_match_with_typos() {
+        assert_eq!(ids(&index(), "antenapod"), ["de.danoeh.antennapod"]);
+        assert_eq!(ids(&index(), "podcsat"), ["de.danoeh.antennapod"]);
+        assert_eq!(ids(&index(), "antenaopd"), ["de.danoeh.antennapod"]);
+        // Too short for a typo, and typos in the first letter aren't looked for
+        assert!(ids(&index(), "motes").is_empty());
+        assert!(ids(&index(), "nte").is_empty());
+        assert!(ids(&index(), "bodcast").is_empty());
+    }
+
+    #[test]
+    fn results_are_limited() {
+        let apps = (0..20)
+            .map(|i| app(&i.to_string(), "Same", "", ""))
+            .collect();
+        assert_eq!(SearchIndex::build(apps).search("same", 5).len(), 5);
+    }
+
+    #[test]
+    fn distances() {
+        assert!(within_distance("kitten", "kitten", 0));
+        assert!(within_distance("kitten", "sitten", 1));
+        assert!(within_distance("kitten", "kiten", 1));
+        assert!(within_distance("kitten", "kitetn", 1));
+        assert!(within_distance("kitten", "sitting", 3));
+        assert!(!within_distance("kitten", "sitting", 2));
+        assert!(!within_distance("abc", "abcdef", 2));
+        assert!(within_distance("größe", "grösse", 2));
+    }
+}
@@ -0,0 +1,623 @@
+use std::{
+    cmp::Ordering,
+    io::Cursor,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, Response, StatusCode,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    index_v1::IndexV1,
+    index_v2::{self, Entry, FileV2, IndexV2},
+    jar::{self, SignedEntry},
+    repo::{Meta, Repo, Validators},
+};
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("server answered {0}")]
+    HttpStatus(StatusCode),
+    #[error("not a valid jar: {0}")]
+    BadZip(#[from] ZipError),
+    #[error("{0}")]
+    MissingEntry(String),
+    #[error("can't parse the index: {0}")]
+    Parse(String),
+    /// The index isn't signed, isn't signed by the pinned certificate or doesn't match what's signed.
+    #[error("bad signature: {0}")]
+    Signature(String),
+    /// The index is older than the one we have, a mirror lagging behind or someone replaying an old index.
+    #[error("index from {received} is older than the current one from {current}")]
+    Rollback { current: u64, received: u64 },
+    #[error("no url to download the index from")]
+    NoMirror,
+}
+
+impl SyncError {
+    /// Whether the mirror doesn't have the file at all, so an older index format can be tried.
+    fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            Self::HttpStatus(StatusCode::NOT_FOUND) | Self::MissingEntry(_)
+        )
+    }
+}
+
+impl From<serde_json::Error> for SyncError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<quick_xml::DeError> for SyncError {
+    fn from(e: quick_xml::DeError) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub enum SyncStatus {
+    #[default]
+    Idle,
+    Syncing,
+    /// Seconds since the epoch of the last successful sync.
+    Ok(u64),
+    Failed(SyncError),
+}
+
+impl SyncStatus {
+    pub fn succeeded_now() -> Self {
+        Self::Ok(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        )
+    }
+}
+
+type Result<T> = std::result::Result<T, SyncError>;
+
+/// A freshly downloaded index, signed by the certificate the repo is pinned to.
+pub struct Index {
+    pub repo: Repo,
+    /// DER encoded certificate the index was signed with.
+    pub signer: Vec<u8>,
+    /// Sent along the next time, so an unchanged index isn't downloaded again.
+    pub validators: Validators,
+}
+
+/// What we already have of a repo, so unchanged or slightly changed indexes stay cheap.
+pub struct Local {
+    pub meta: Meta,
+    /// Whether the apps of the index in `meta` are still around, if not it's downloaded again even
+    /// if it didn't change.
+    pub has_apps: bool,
+    pub raw_v2: Option<Arc<Value>>,
+    pub validators: Option<Validators>,
+}
+
+impl Local {
+    /// Whether an index with `timestamp` (in seconds) has nothing new for us.
+    ///
+    /// Older indexes are rejected, so a stale mirror or an attacker can't roll the repo back.
+    fn is_current(&self, timestamp: u64) -> Result<bool> {
+        match timestamp.cmp(&self.meta.timestamp) {
+            Ordering::Less => Err(SyncError::Rollback {
+                current: self.meta.timestamp,
+                received: timestamp,
+            }),
+            Ordering::Equal => Ok(self.has_apps),
+            Ordering::Greater => Ok(false),
+        }
+    }
+}
+
+pub struct Fetched {
+    /// The mirror that answered.
+    pub mirror: String,
+    /// `None` if the repo hasn't changed since [Local].
+    pub index: Option<Index>,
+}
+
+/// Downloads the index from the first of `mirrors` that serves one signed by the pinned certificate.
+pub async fn fetch_index(client: &Client, mirrors: &[String], local: &Local) -> Result<Fetched> {
+    let mut last_error = SyncError::NoMirror;
+
+    for mirror in mirrors {
+        match fetch_from(client, mirror, local).await {
+            Ok(index) => {
+                return Ok(Fetched {
+                    mirror: mirror.clone(),
+                    index,
+                })
+            }
+            Err(e) => {
+                log::warn!("{mirror}: {e}");
+                last_error = e;
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Tries index-v2, index-v1 and `index.xml` of one mirror, in that order.
+///
+/// Only a missing index falls back to the next format, a bad signature or a rollback fails the
+/// mirror so it can't be worked around with an older format.
+async fn fetch_from(client: &Client, url: &str, local: &Local) -> Result<Option<Index>> {
+    let mut index = match fetch_v2(client, url, local).await {
+        Ok(None) => return Ok(None),
+        Ok(Some(index)) => index,
+        Err(e) if e.is_not_found() => {
+            log::info!("{url}: no index-v2 ({e}), falling back to index-v1");
+            match fetch_v1(client, url, local).await {
+                Ok(None) => return Ok(None),
+                Ok(Some(index)) => index,
+                Err(e) if e.is_not_found() => {
+                    log::info!("{url}: no index-v1 ({e}), falling back to index.xml");
+                    match fetch_xml(client, url, local).await? {
+                        None => return Ok(None),
+                        Some(index) => index,
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(e) => return Err(e),
+    };
+    if local.is_current(index.repo.meta.timestamp)? {
+        return Ok(None);
+    }
+
+    index.repo.meta.pub_key = hex::encode(&index.signer);
+    index.repo.meta.fingerprint = local.meta.fingerprint.clone();
+
+    Ok(Some(index))
+}
+
+async fn fetch_v2(client: &Client, url: &str, local: &Local) -> Result<Option<Index>> {
+    let Some((entry, validators)) =
+        fetch_signed(client, &format!("{url}/entry.jar"), "entry.json", local).await?
+    else {
+        return Ok(None);
+    };
+    let signer = entry.signer;
+    let entry: Entry = serde_json::from_slice(&entry.data)?;
+
+    if local.is_current(entry.timestamp / 1000)? {
+        return Ok(None);
+    }
+
+    let diff = local.raw_v2.as_ref().and_then(|raw| {
+        let file = entry.diffs.get(&index_v2::timestamp(raw)?.to_string())?;
+        Some((raw, file))
+    });
+    // A diff is only a shortcut, the whole index is always there to fall back to
+    let patched = match diff {
+        Some((raw, file)) => fetch_diff(client, url, raw, file, entry.timestamp)
+            .await
+            .inspect_err(|e| log::warn!("{url}: can't apply the index-v2 diff: {e}"))
+            .ok(),
+        None => None,
+    };
+    let raw = match patched {
+        Some(raw) => raw,
+        None => fetch_json(client, url, &entry.index).await?,
+    };
+    if index_v2::timestamp(&raw) != Some(entry.timestamp) {
+        return Err(SyncError::Signature(
+            "index-v2 doesn't have the timestamp of entry.json".into(),
+        ));
+    }
+
+    let mut repo: Repo = IndexV2::deserialize(&raw)?.into();
+    repo.raw_v2 = Some(Arc::new(raw));
+
+    Ok(Some(Index {
+        repo,
+        signer,
+        validators,
+    }))
+}
+
+async fn fetch_v1(client: &Client, url: &str, local: &Local) -> Result<Option<Index>> {
+    let Some((entry, validators)) = fetch_signed(
+        client,
+        &format!("{url}/index-v1.jar"),
+        "index-v1.json",
+        local,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    let index: IndexV1 = serde_json::from_slice(&entry.data)?;
+
+    Ok(Some(Index {
+        repo: index.into(),
+        signer: entry.signer,
+        validators,
+    }))
+}
+
+async fn fetch_xml(client: &Client, url: &str, local: &Local) -> Result<Option<Index>> {
+    let Some((entry, validators)) =
+        fetch_signed(client, &format!("{url}/index.jar"), "index.xml", local).await?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(Index {
+        repo: quick_xml::de::from_reader(entry.data.as_slice())?,
+        signer: entry.signer,
+        validators,
+    }))
+}
+
+/// Downloads a jar and reads `name` from it, if it's signed by the certificate the repo is pinned to.
+///
+/// Returns `None` if the server says the jar didn't change since [Local::validators].
+async fn fetch_signed(
+    client: &Client,
+    jar_url: &str,
+    name: &str,
+    local: &Local,
+) -> Result<Option<(SignedEntry, Validators)>> {
+    let mut request = client.get(jar_url);
+    if let Some(validators) = local
+        .validators
+        .as_ref()
+        .filter(|v| local.has_apps && v.url == jar_url)
+    {
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let response = check_status(response)?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = Validators {
+        url: jar_url.to_string(),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    let bytes = response.bytes().await?;
+
+    let entry = jar::read_signed(&mut ZipArchive::new(Cursor::new(bytes))?, name).map_err(|e| {
+        match e.downcast_ref::<ZipError>() {
+            Some(ZipError::FileNotFound) => SyncError::MissingEntry(e.to_string()),
+            _ => SyncError::Signature(format!("{e:#}")),
+        }
+    })?;
+    if !local.meta.trusts(&entry.signer) {
+        return Err(SyncError::Signature(format!(
+            "{jar_url} is not signed by the pinned certificate"
+        )));
+    }
+    Ok(Some((entry, validators)))
+}
+
+/// Applies the diff `file` to the index-v2 we have, which has to lead to the index with `timestamp`.
+async fn fetch_diff(
+    client: &Client,
+    url: &str,
+    raw: &Value,
+    file: &FileV2,
+    timestamp: u64,
+) -> Result<Value> {
+    let mut raw = raw.clone();
+    index_v2::merge(&mut raw, fetch_json(client, url, file).await?);
+    if index_v2::timestamp(&raw) != Some(timestamp) {
+        return Err(SyncError::Parse(format!(
+            "{} doesn't lead to the current index",
+            file.name
+        )));
+    }
+    Ok(raw)
+}
+
+/// Downloads a file listed in `entry.json`, which is only trusted through the hash listed there.
+async fn fetch_json(client: &Client, url: &str, file: &FileV2) -> Result<Value> {
+    let bytes = check_status(client.get(format!("{url}{}", file.name)).send().await?)?
+        .bytes()
+        .await?;
+
+    let expected = file.sha256.as_deref().unwrap_or_default();
+    if !expected.eq_ignore_ascii_case(&hex::encode(Sha256::digest(&bytes))) {
+        return Err(SyncError::Signature(format!(
+            "{} doesn't match its sha256 in entry.json",
+            file.name
+        )));
+    }
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+fn check_status(response: Response) -> Result<Response> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(SyncError::HttpStatus(response.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const INDEX_V1: &str = include_str!("../tests/fixtures/index-v1.json");
+    const INDEX_V1_JAR: &[u8] = include_bytes!("../tests/fixtures/index-v1.jar");
+    /// SHA-256 of the certificate the jar is signed with, see `make-jars.py`.
+    const ENTRY_JAR: &[u8] = include_bytes!("../tests/fixtures/entry.jar");
+    const INDEX_V2: &[u8] = include_bytes!("../tests/fixtures/index-v2.json");
+    const DIFF: &[u8] = include_bytes!("../tests/fixtures/diff/1699999940000.json");
+    const STALE_DIFF: &[u8] = include_bytes!("../tests/fixtures/diff/1699999880000.json");
+    const JAR_SIGNER: &str = "eeba212c729c2e69a8b3bf740a3b3df5262f5b14ef156d139ac33bd5413c35ad";
+
+    /// Serves `files` by their path and 404 for anything else, returns the url of the server.
+    async fn serve(files: Vec<(&'static str, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let files = Arc::new(files);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let files = files.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut path = String::new();
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap() > 2 {
+                        if let Some(rest) = line.strip_prefix("GET ") {
+                            path = rest.split(' ').next().unwrap_or_default().to_string();
+                        }
+                        line.clear();
+                    }
+
+                    let mut stream = stream.into_inner();
+                    let response = match files.iter().find(|(p, _)| *p == path) {
+                        Some((_, body)) => {
+                            let head =
+                                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", body.len());
+                            [head.as_bytes(), b"Connection: close\r\n\r\n", body].concat()
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+                                  Connection: close\r\n\r\n"
+                            .to_vec(),
+                    };
+                    stream.write_all(&response).await.unwrap();
+                });
+            }
+        });
+
+        url
+    }
+
+    /// The fixture index with its timestamp moved by `offset` seconds.
+    fn index_at(offset: i64) -> Repo {
+        let mut raw: Value = serde_json::from_str(INDEX_V1).unwrap();
+        let timestamp = raw["repo"]["timestamp"].as_i64().unwrap() + offset * 1000;
+        raw["repo"]["timestamp"] = timestamp.into();
+        IndexV1::deserialize(raw).unwrap().into()
+    }
+
+    fn local(has_apps: bool) -> Local {
+        Local {
+            meta: index_at(0).meta,
+            has_apps,
+            raw_v2: None,
+            validators: None,
+        }
+    }
+
+    #[test]
+    fn older_index_is_a_rollback() {
+        let older = index_at(-60).meta.timestamp;
+        for has_apps in [true, false] {
+            match local(has_apps).is_current(older) {
+                Err(SyncError::Rollback { current, received }) => {
+                    assert_eq!(current, 1_700_000_000);
+                    assert_eq!(received, 1_700_000_000 - 60);
+                }
+                other => panic!("expected a rollback, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn same_index_is_current_only_with_its_apps() {
+        let same = index_at(0).meta.timestamp;
+        assert!(local(true).is_current(same).unwrap());
+        assert!(!local(false).is_current(same).unwrap());
+    }
+
+    #[test]
+    fn newer_index_is_not_current() {
+        let newer = index_at(60).meta.timestamp;
+        for has_apps in [true, false] {
+            assert!(!local(has_apps).is_current(newer).unwrap());
+        }
+    }
+
+    #[test]
+    fn index_is_stale_after_its_max_age() {
+        // The fixture asks for 14 days
+        let meta = index_at(0).meta;
+        let max_age = 14 * 24 * 60 * 60;
+        assert!(!meta.is_stale(meta.timestamp));
+        assert!(!meta.is_stale(meta.timestamp + max_age));
+        assert!(meta.is_stale(meta.timestamp + max_age + 1));
+        // A clock behind the index isn't a reason to distrust it
+        assert!(!meta.is_stale(meta.timestamp - 60));
+    }
+
+    #[test]
+    fn index_without_max_age_never_gets_stale() {
+        let far_future = u64::MAX / 2;
+        for max_age in ["", "0", "not a number"] {
+            let mut meta = index_at(0).meta;
+            meta.max_age = max_age.to_string();
+            assert!(!meta.is_stale(far_future), "max_age {max_age:?}");
+        }
+
+        // Nor does a repo that was never synced
+        let mut meta = index_at(0).meta;
+        meta.timestamp = 0;
+        assert!(!meta.is_stale(far_future));
+    }
+
+    #[test]
+    fn only_missing_indexes_fall_back() {
+        assert!(SyncError::HttpStatus(StatusCode::NOT_FOUND).is_not_found());
+        assert!(SyncError::MissingEntry("index-v1.json".into()).is_not_found());
+
+        assert!(!SyncError::HttpStatus(StatusCode::INTERNAL_SERVER_ERROR).is_not_found());
+        assert!(!SyncError::Signature("not signed".into()).is_not_found());
+        assert!(!SyncError::Rollback {
+            current: 2,
+            received: 1
+        }
+        .is_not_found());
+    }
+
+    /// Nothing synced yet, pinned to `fingerprint`.
+    fn pinned(fingerprint: &str) -> Local {
+        Local {
+            meta: Repo::new("https://example.org/repo", Some(fingerprint.into())).meta,
+            has_apps: false,
+            raw_v2: None,
+            validators: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn jars_are_only_trusted_from_the_pinned_signer() {
+        let url = serve(vec![("/index-v1.jar", INDEX_V1_JAR.to_vec())]).await;
+        let jar_url = format!("{url}/index-v1.jar");
+        let client = Client::new();
+
+        let (entry, _) = fetch_signed(&client, &jar_url, "index-v1.json", &pinned(JAR_SIGNER))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.data, INDEX_V1.as_bytes());
+
+        let other = "c87105c20e29913ab9ef4b3b24a756b87d61d00a4af51fd9ce200ab2da7e0fb0";
+        match fetch_signed(&client, &jar_url, "index-v1.json", &pinned(other)).await {
+            Err(SyncError::Signature(_)) => {}
+            Err(e) => panic!("expected a signature error, got {e}"),
+            Ok(_) => panic!("a jar of another signer was trusted"),
+        }
+    }
+
+    #[tokio::test]
+    async fn index_v1_is_preferred_over_index_xml() {
+        // A mirror without index-v2, whose index.jar would fail the sync if it was read at all
+        let url = serve(vec![
+            ("/index-v1.jar", INDEX_V1_JAR.to_vec()),
+            ("/index.jar", b"not a jar".to_vec()),
+        ])
+        .await;
+
+        let fetched = fetch_index(
+            &Client::new(),
+            std::slice::from_ref(&url),
+            &pinned(JAR_SIGNER),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetched.mirror, url);
+        let index = fetched.index.unwrap();
+        assert_eq!(index.repo.meta.timestamp, 1_700_000_000);
+        assert_eq!(index.repo.apps.unwrap()[0].id, "org.fdroid.fdroid");
+        assert_eq!(index.repo.meta.pub_key, hex::encode(&index.signer));
+        assert_eq!(hex::encode(Sha256::digest(&index.signer)), JAR_SIGNER);
+    }
+
+    /// What we'd have synced from the fixture index-v2 at `timestamp` (in milliseconds), before
+    /// its diffs changed the summary.
+    fn synced_v2(timestamp: u64) -> Local {
+        let mut raw: Value = serde_json::from_slice(INDEX_V2).unwrap();
+        raw["repo"]["timestamp"] = timestamp.into();
+        let summary = &mut raw["packages"]["org.fdroid.fdroid"]["metadata"]["summary"];
+        summary["en-US"] = "The app store".into();
+        summary["en-GB"] = "The app store".into();
+
+        Local {
+            raw_v2: Some(Arc::new(raw)),
+            has_apps: true,
+            ..pinned(JAR_SIGNER)
+        }
+    }
+
+    async fn fetch_v2_from(files: Vec<(&'static str, Vec<u8>)>, local: &Local) -> Value {
+        let url = serve([vec![("/entry.jar", ENTRY_JAR.to_vec())], files].concat()).await;
+        let index = fetch_v2(&Client::new(), &url, local)
+            .await
+            .unwrap()
+            .unwrap();
+        Arc::unwrap_or_clone(index.repo.raw_v2.unwrap())
+    }
+
+    #[tokio::test]
+    async fn v2_diffs_are_applied() {
+        // Without index-v2.json on the server, only the diff can lead to the current index
+        let files = vec![("/diff/1699999940000.json", DIFF.to_vec())];
+        let raw = fetch_v2_from(files, &synced_v2(1699999940000)).await;
+        assert_eq!(raw, serde_json::from_slice::<Value>(INDEX_V2).unwrap());
+    }
+
+    #[tokio::test]
+    async fn broken_v2_diffs_fall_back_to_the_whole_index() {
+        let full = || ("/index-v2.json", INDEX_V2.to_vec());
+        for (files, local) in [
+            // The diff is missing
+            (vec![full()], synced_v2(1699999940000)),
+            // The diff isn't the one in entry.json
+            (
+                vec![full(), ("/diff/1699999940000.json", STALE_DIFF.to_vec())],
+                synced_v2(1699999940000),
+            ),
+            // The diff doesn't end at the timestamp of entry.json
+            (
+                vec![full(), ("/diff/1699999880000.json", STALE_DIFF.to_vec())],
+                synced_v2(1699999880000),
+            ),
+        ] {
+            let raw = fetch_v2_from(files, &local).await;
+            assert_eq!(raw, serde_json::from_slice::<Value>(INDEX_V2).unwrap());
+        }
+    }
+}
@@ -0,0 +1,134 @@
+#!/usr/bin/env python3
+"""Writes the signed apks in this directory, run it from here with `python3 make-apks.py`.
+
+They aren't real apps, only zips with an APK Signature Scheme v2 or v3 block, see
+https://source.android.com/docs/security/features/apksigning/v2. The keys are thrown away, the
+SHA-256 of the certificates is printed for the tests.
+"""
+
+import datetime
+import hashlib
+import io
+import struct
+import zipfile
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+from cryptography.x509.oid import NameOID
+
+V2_BLOCK_ID = 0x7109871A
+V3_BLOCK_ID = 0xF05368C0
+RSA_PKCS1_SHA256 = 0x0103
+CHUNK_SIZE = 1024 * 1024
+
+
+def key_and_cert(name):
+    key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+    subject = x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, name)])
+    start = datetime.datetime(2024, 1, 1, tzinfo=datetime.timezone.utc)
+    cert = (
+        x509.CertificateBuilder()
+        .subject_name(subject)
+        .issuer_name(subject)
+        .public_key(key.public_key())
+        .serial_number(1)
+        .not_valid_before(start)
+        .not_valid_after(start + datetime.timedelta(days=365 * 30))
+        .sign(key, hashes.SHA256())
+    )
+    return key, cert.public_bytes(serialization.Encoding.DER)
+
+
+def prefixed(data):
+    return struct.pack("<I", len(data)) + data
+
+
+def unsigned_apk():
+    out = io.BytesIO()
+    with zipfile.ZipFile(out, "w", zipfile.ZIP_STORED) as apk:
+        for name, content in [
+            ("AndroidManifest.xml", b"<manifest package='org.example.app'/>"),
+            ("classes.dex", b"dex\n035\0" + bytes(range(256)) * 4),
+            ("res/raw/readme.txt", b"Signed by tests/fixtures/make-apks.py\n"),
+        ]:
+            info = zipfile.ZipInfo(name, date_time=(2024, 1, 1, 0, 0, 0))
+            apk.writestr(info, content)
+    return out.getvalue()
+
+
+def split(apk):
+    eocd_start = apk.rindex(b"PK\x05\x06")
+    cd_size, cd_offset = struct.unpack("<II", apk[eocd_start + 12 : eocd_start + 20])
+    return apk[:cd_offset], apk[cd_offset:eocd_start], apk[eocd_start:]
+
+
+def content_digest(entries, cd, eocd):
+    chunks = []
+    for section in [entries, cd, eocd]:
+        for i in range(0, len(section), CHUNK_SIZE):
+            chunk = section[i : i + CHUNK_SIZE]
+            chunks.append(
+                hashlib.sha256(b"\xa5" + struct.pack("<I", len(chunk)) + chunk).digest()
+            )
+    return hashlib.sha256(b"\x5a" + struct.pack("<I", len(chunks)) + b"".join(chunks)).digest()
+
+
+def signer(digest, key, cert, v3):
+    sdks = struct.pack("<II", 24, 0x7FFFFFFF) if v3 else b""
+    signed_data = (
+        prefixed(prefixed(struct.pack("<I", RSA_PKCS1_SHA256) + prefixed(digest)))
+        + prefixed(prefixed(cert))
+        + sdks
+        + prefixed(b"")
+    )
+    signature = key.sign(signed_data, padding.PKCS1v15(), hashes.SHA256())
+    public_key = key.public_key().public_bytes(
+        serialization.Encoding.DER, serialization.PublicFormat.SubjectPublicKeyInfo
+    )
+    return prefixed(
+        prefixed(signed_data)
+        + sdks
+        + prefixed(prefixed(struct.pack("<I", RSA_PKCS1_SHA256) + prefixed(signature)))
+        + prefixed(public_key)
+    )
+
+
+def sign(apk, signers, block_id):
+    entries, cd, eocd = split(apk)
+    digest = content_digest(entries, cd, eocd)
+    v3 = block_id == V3_BLOCK_ID
+    value = prefixed(b"".join(signer(digest, key, cert, v3) for key, cert in signers))
+    pair = struct.pack("<I", block_id) + value
+    pairs = struct.pack("<Q", len(pair)) + pair
+    size = len(pairs) + 8 + 16
+    block = struct.pack("<Q", size) + pairs + struct.pack("<Q", size) + b"APK Sig Block 42"
+    eocd = eocd[:16] + struct.pack("<I", len(entries) + len(block)) + eocd[20:]
+    return entries + block + cd + eocd
+
+
+def main():
+    first = key_and_cert("Edroid tests")
+    second = key_and_cert("Someone else")
+    apk = unsigned_apk()
+
+    v2 = sign(apk, [first], V2_BLOCK_ID)
+    # The same bytes are in the entry and in the digest input, flipping one breaks the digest
+    tampered = v2.replace(b"Signed by", b"Signed bY", 1)
+    for name, signed in [
+        ("app-v2.apk", v2),
+        ("app-v3.apk", sign(apk, [first], V3_BLOCK_ID)),
+        ("app-v3-two-signers.apk", sign(apk, [first, second], V3_BLOCK_ID)),
+        ("app-other-signer.apk", sign(apk, [second], V2_BLOCK_ID)),
+        ("app-tampered.apk", tampered),
+    ]:
+        with open(name, "wb") as f:
+            f.write(signed)
+        print(name, hashlib.sha256(signed).hexdigest())
+
+    for name, (_, cert) in [("signer", first), ("other signer", second)]:
+        print(name, hashlib.sha256(cert).hexdigest())
+
+
+if __name__ == "__main__":
+    main()
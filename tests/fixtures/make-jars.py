@@ -0,0 +1,122 @@
+#!/usr/bin/env python3
+"""Writes the JAR (v1) signed files in this directory, run it from here with `python3 make-jars.py`.
+
+`index-v1.jar` is signed like an F-Droid repo index and holds `index-v1.json`, `entry.jar` holds
+an index-v2 `entry.json` pointing at `index-v2.json` and the diffs in `diff/`, `app-v1.apk` is an
+apk with only a v1 signature, see
+https://docs.oracle.com/en/java/javase/17/docs/specs/jar/jar.html#signed-jar-file. The key is
+thrown away, the SHA-256 of the certificate is printed for the tests.
+"""
+
+import base64
+import datetime
+import hashlib
+import io
+import json
+import os
+import zipfile
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import rsa
+from cryptography.hazmat.primitives.serialization import pkcs7
+from cryptography.x509.oid import NameOID
+
+
+def key_and_cert(name):
+    key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+    subject = x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, name)])
+    start = datetime.datetime(2024, 1, 1, tzinfo=datetime.timezone.utc)
+    cert = (
+        x509.CertificateBuilder()
+        .subject_name(subject)
+        .issuer_name(subject)
+        .public_key(key.public_key())
+        .serial_number(1)
+        .not_valid_before(start)
+        .not_valid_after(start + datetime.timedelta(days=365 * 30))
+        .sign(key, hashes.SHA256())
+    )
+    return key, cert
+
+
+def digest(data):
+    return base64.b64encode(hashlib.sha256(data).digest()).decode()
+
+
+def file_v2(name):
+    with open(name, "rb") as f:
+        content = f.read()
+    return {"name": "/" + name, "sha256": hashlib.sha256(content).hexdigest(), "size": len(content)}
+
+
+def entry():
+    # diff/1699999880000.json doesn't move the timestamp, like a diff that leads somewhere else
+    diffs = {name[: -len(".json")]: file_v2(f"diff/{name}") for name in os.listdir("diff")}
+    timestamp = json.load(open("index-v2.json"))["repo"]["timestamp"]
+    entry = {"timestamp": timestamp, "version": 20002, "index": file_v2("index-v2.json"), "diffs": diffs}
+    return json.dumps(entry, indent=2, sort_keys=True).encode()
+
+
+def sign(files, key, cert):
+    sections = [
+        f"Name: {name}\r\nSHA-256-Digest: {digest(content)}\r\n\r\n".encode()
+        for name, content in files
+    ]
+    manifest = b"Manifest-Version: 1.0\r\nCreated-By: make-jars.py\r\n\r\n" + b"".join(sections)
+    sf = (
+        b"Signature-Version: 1.0\r\n"
+        + f"SHA-256-Digest-Manifest: {digest(manifest)}\r\n".encode()
+        + b"Created-By: make-jars.py\r\n\r\n"
+        + b"".join(
+            f"Name: {name}\r\nSHA-256-Digest: {digest(section)}\r\n\r\n".encode()
+            for (name, _), section in zip(files, sections)
+        )
+    )
+    block = (
+        pkcs7.PKCS7SignatureBuilder()
+        .set_data(sf)
+        .add_signer(cert, key, hashes.SHA256())
+        .sign(serialization.Encoding.DER, [pkcs7.PKCS7Options.DetachedSignature])
+    )
+
+    out = io.BytesIO()
+    with zipfile.ZipFile(out, "w", zipfile.ZIP_DEFLATED) as jar:
+        for name, content in [
+            ("META-INF/MANIFEST.MF", manifest),
+            ("META-INF/CERT.SF", sf),
+            ("META-INF/CERT.RSA", block),
+            *files,
+        ]:
+            info = zipfile.ZipInfo(name, date_time=(2024, 1, 1, 0, 0, 0))
+            jar.writestr(info, content)
+    return out.getvalue()
+
+
+def main():
+    key, cert = key_and_cert("Edroid tests")
+    with open("index-v1.json", "rb") as f:
+        index = f.read()
+
+    for name, files in [
+        ("index-v1.jar", [("index-v1.json", index)]),
+        ("entry.jar", [("entry.json", entry())]),
+        (
+            "app-v1.apk",
+            [
+                ("AndroidManifest.xml", b"<manifest package='org.example.app'/>"),
+                ("classes.dex", b"dex\n035\0" + bytes(range(256)) * 4),
+                ("res/raw/readme.txt", b"Signed by tests/fixtures/make-jars.py\n"),
+            ],
+        ),
+    ]:
+        signed = sign(files, key, cert)
+        with open(name, "wb") as f:
+            f.write(signed)
+        print(name, hashlib.sha256(signed).hexdigest())
+
+    print("signer", hashlib.sha256(cert.public_bytes(serialization.Encoding.DER)).hexdigest())
+
+
+if __name__ == "__main__":
+    main()